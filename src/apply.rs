@@ -0,0 +1,526 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::cli::BraveVersion;
+use crate::config::{Config, Extension, get_policy_config};
+use crate::error::DebloaterError;
+use crate::platforms::{
+    BACKUP_DIR_NAME, BACKUP_TIMESTAMP_FORMAT, LINUX_FLATPAK_APP_ID, LINUX_FLATPAK_POLICY_EXTENSION, LINUX_SANDBOX_POLICY_FILE,
+    LINUX_SNAP_NAME, get_linux_flags_file, get_linux_native_binary, get_linux_native_install_dir, get_linux_policy_path,
+    get_version_suffix, has_linux_sandboxed_packages,
+};
+use crate::preferences::{
+    BrowserPreferences, LocalState, MODIFIED_PREFERENCES, PreferencesInputConfig, ProfilePreferenceSet, REPLACED_PREFERENCES, calculate_mac,
+    add_launch_flag, get_default_experimental_features, get_default_launch_flags, get_list_switch, get_profile_preference_sets, get_protected_preferences,
+    get_backup_retention, get_user_preferences,
+};
+
+/// A key whose value was changed by `apply`.
+#[derive(Debug)]
+pub struct ChangedKey {
+    pub file: PathBuf,
+    pub key: String,
+    pub old_value: Option<Value>,
+    pub new_value: Value,
+}
+
+/// How Brave was installed. Each kind reads its policies from and keeps its user data in a different place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxPackaging {
+    Native,
+    Snap,
+    FlatpakSystem,
+    FlatpakUser,
+}
+
+/// Brave installation whose policies and user data are configured.
+#[derive(Debug)]
+pub struct LinuxInstallation {
+    pub packaging: LinuxPackaging,
+    pub policy_path: PathBuf,
+    pub data_dir: PathBuf,
+    pub config_dir: PathBuf,
+}
+
+/// Timestamped snapshot of the files changed by one run, listed in `manifest.txt`.
+#[derive(Debug)]
+pub struct BackupSnapshot {
+    pub data_dir: PathBuf,
+    pub dir: PathBuf,
+}
+
+/// Configures the local Linux machine without generating a script.
+///
+/// System policies are only written when the policy directory is writable, e.g. when run as root.
+pub fn apply_linux(
+    config: &Config,
+    extensions: &[Extension],
+    version: &BraveVersion,
+    preferences_config: Option<&PreferencesInputConfig>,
+) -> Result<Vec<ChangedKey>, DebloaterError> {
+    if !cfg!(target_os = "linux") {
+        return Err(DebloaterError::UnsupportedPlatform("apply is only supported on Linux".to_string()));
+    }
+
+    if is_brave_running() {
+        println!("WARNING: Brave browser is running! Close it, or it will overwrite the new preferences on exit.");
+    }
+
+    let mut changes = Vec::new();
+    let installations = detect_installations(version)?;
+
+    for installation in &installations {
+        println!("Detected {} Brave installation", installation.packaging.name());
+        match apply_system_policies(&installation.policy_path, config, extensions) {
+            Ok(policy_changes) => changes.extend(policy_changes),
+            Err(DebloaterError::Io(e)) if e.kind() == ErrorKind::PermissionDenied => {
+                println!("Skipping system policies (no write access to {})", installation.policy_path.display());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // The system and user Flatpak share one data directory
+    let mut configured: Vec<&Path> = Vec::new();
+    for installation in &installations {
+        if configured.contains(&installation.data_dir.as_path()) {
+            continue;
+        }
+        configured.push(&installation.data_dir);
+        println!("Configuring user data in {}", installation.data_dir.display());
+        changes.extend(apply_user_data(installation, version, preferences_config)?);
+    }
+
+    Ok(changes)
+}
+
+/// Configures the profiles, Local State and launch flags of one installation.
+pub fn apply_user_data(
+    installation: &LinuxInstallation,
+    version: &BraveVersion,
+    preferences_config: Option<&PreferencesInputConfig>,
+) -> Result<Vec<ChangedKey>, DebloaterError> {
+    let mut changes = Vec::new();
+    fs::create_dir_all(&installation.data_dir)?;
+
+    let backup = BackupSnapshot::new(&installation.data_dir);
+    let local_state_path = installation.data_dir.join("Local State");
+    let local_state = read_json(&local_state_path)?;
+
+    let preference_sets = get_profile_preference_sets(preferences_config);
+    for profile in get_profiles(&installation.data_dir, &local_state) {
+        let Some(preference_set) = select_preference_set(&preference_sets, &profile, &local_state) else {
+            println!("Skipping profile: {}", profile);
+            continue;
+        };
+        println!("Applying preferences to profile: {}", profile);
+        let prefs_path = installation.data_dir.join(&profile).join("Preferences");
+        changes.extend(apply_profile_preferences(&prefs_path, preference_set, &backup)?);
+    }
+
+    let local_state_changes = LocalState {
+        browser: Some(BrowserPreferences {
+            enabled_labs_experiments: Some(get_default_experimental_features(preferences_config)),
+        }),
+    };
+    changes.extend(merge_into_file(&local_state_path, serde_json::to_value(local_state_changes)?, &backup)?);
+    backup.rotate(get_backup_retention(preferences_config))?;
+
+    let flags_path = installation.config_dir.join(get_linux_flags_file(version));
+    changes.extend(apply_launch_flags(&flags_path, &get_default_launch_flags(preferences_config))?);
+
+    Ok(changes)
+}
+
+/// Detects every native, Snap and Flatpak install the same way as the generated script. Falls back
+/// to the native paths when none is found.
+pub fn detect_installations(version: &BraveVersion) -> Result<Vec<LinuxInstallation>, DebloaterError> {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| DebloaterError::UnsupportedPlatform("HOME is not set".to_string()))?;
+    let version_suffix = get_version_suffix(version);
+    let mut installations = Vec::new();
+
+    let native_binary = get_linux_native_binary(version);
+    let on_path = std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(native_binary).exists()));
+    if on_path || Path::new(get_linux_native_install_dir(version)).exists() {
+        installations.push(native_installation(&home, version));
+    }
+
+    if has_linux_sandboxed_packages(version) {
+        if Path::new("/snap").join(LINUX_SNAP_NAME).exists() {
+            let config_dir = home.join("snap").join(LINUX_SNAP_NAME).join("current/.config");
+            installations.push(LinuxInstallation {
+                packaging: LinuxPackaging::Snap,
+                policy_path: Path::new("/var/snap").join(LINUX_SNAP_NAME).join("current").join(LINUX_SANDBOX_POLICY_FILE),
+                data_dir: config_dir.join("BraveSoftware").join(version_suffix),
+                config_dir,
+            });
+        }
+
+        let flatpak_roots = [
+            (LinuxPackaging::FlatpakSystem, PathBuf::from("/var/lib/flatpak")),
+            (LinuxPackaging::FlatpakUser, home.join(".local/share/flatpak")),
+        ];
+        for (packaging, root) in flatpak_roots {
+            if !root.join("app").join(LINUX_FLATPAK_APP_ID).exists() {
+                continue;
+            }
+            let config_dir = home.join(".var/app").join(LINUX_FLATPAK_APP_ID).join("config");
+            let extension_dir = root.join("extension").join(LINUX_FLATPAK_POLICY_EXTENSION).join(std::env::consts::ARCH).join("1");
+            installations.push(LinuxInstallation {
+                packaging,
+                policy_path: extension_dir.join(LINUX_SANDBOX_POLICY_FILE),
+                data_dir: config_dir.join("BraveSoftware").join(version_suffix),
+                config_dir,
+            });
+        }
+    }
+
+    if installations.is_empty() {
+        installations.push(native_installation(&home, version));
+    }
+    Ok(installations)
+}
+
+fn native_installation(home: &Path, version: &BraveVersion) -> LinuxInstallation {
+    LinuxInstallation {
+        packaging: LinuxPackaging::Native,
+        policy_path: PathBuf::from(get_linux_policy_path(version)),
+        data_dir: home.join(".config/BraveSoftware").join(get_version_suffix(version)),
+        config_dir: std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".config")),
+    }
+}
+
+impl LinuxPackaging {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinuxPackaging::Native => "native",
+            LinuxPackaging::Snap => "Snap",
+            LinuxPackaging::FlatpakSystem => "system Flatpak",
+            LinuxPackaging::FlatpakUser => "user Flatpak",
+        }
+    }
+}
+
+/// Lists profile directories from `profile.info_cache`, falling back to scanning the data directory.
+pub fn get_profiles(data_dir: &Path, local_state: &Value) -> Vec<String> {
+    let mut profiles: Vec<String> = local_state
+        .pointer("/profile/info_cache")
+        .and_then(Value::as_object)
+        .map(|cache| cache.keys().cloned().collect())
+        .unwrap_or_default();
+
+    if profiles.is_empty() {
+        if let Ok(entries) = fs::read_dir(data_dir) {
+            profiles = entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name == "Default" || name.starts_with("Profile "))
+                .collect();
+            profiles.sort();
+        }
+    }
+
+    if profiles.is_empty() {
+        profiles.push("Default".to_string());
+    }
+    profiles
+}
+
+/// Picks the preference set for a profile by directory or display name.
+pub fn select_preference_set<'a>(
+    preference_sets: &'a [ProfilePreferenceSet],
+    profile: &str,
+    local_state: &Value,
+) -> Option<&'a ProfilePreferenceSet> {
+    let display_name = local_state
+        .get("profile")
+        .and_then(|p| p.get("info_cache"))
+        .and_then(|cache| cache.get(profile))
+        .and_then(|info| info.get("name"))
+        .and_then(Value::as_str);
+
+    preference_sets.iter().find(|set| match set.profile.as_deref() {
+        None => true,
+        Some(name) => name == profile || Some(name) == display_name,
+    })
+}
+
+fn apply_system_policies(policy_path: &Path, config: &Config, extensions: &[Extension]) -> Result<Vec<ChangedKey>, DebloaterError> {
+    let policies = serde_json::to_value(get_policy_config(config, extensions))?;
+    let existing = read_json(policy_path)?;
+
+    if let Some(dir) = policy_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomic(policy_path, &serde_json::to_string_pretty(&policies)?)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(policy_path, fs::Permissions::from_mode(0o644))?;
+    }
+
+    let mut changes = Vec::new();
+    if let Value::Object(policies) = policies {
+        for (key, value) in policies {
+            let old_value = existing.get(&key).cloned();
+            if old_value.as_ref() != Some(&value) {
+                changes.push(ChangedKey { file: policy_path.to_path_buf(), key, old_value, new_value: value });
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn apply_profile_preferences(
+    prefs_path: &Path,
+    preference_set: &ProfilePreferenceSet,
+    backup: &BackupSnapshot,
+) -> Result<Vec<ChangedKey>, DebloaterError> {
+    if let Some(dir) = prefs_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let changes = merge_into_file(prefs_path, serde_json::to_value(get_user_preferences(preference_set))?, backup)?;
+    update_preference_macs(prefs_path, backup)?;
+    Ok(changes)
+}
+
+/// Recomputes the MACs of the protected preferences we touch. Linux has no device ID.
+fn update_preference_macs(prefs_path: &Path, backup: &BackupSnapshot) -> Result<(), DebloaterError> {
+    let prefs = read_json(prefs_path)?;
+    let secure_prefs_path = prefs_path.with_file_name("Secure Preferences");
+    backup.add(&secure_prefs_path)?;
+
+    for macs_path in [prefs_path, secure_prefs_path.as_path()] {
+        let mut macs_json = read_json(macs_path)?;
+        let Some(macs) = macs_json.pointer_mut("/protection/macs").and_then(Value::as_object_mut) else {
+            continue;
+        };
+
+        for path in get_protected_preferences(MODIFIED_PREFERENCES) {
+            let value = path.split('.').try_fold(&prefs, |value, key| value.get(key));
+            let mac = Value::String(calculate_mac("", path, value));
+            set_path(macs, path, mac);
+        }
+
+        if macs_json.pointer("/protection/super_mac").is_some() {
+            let super_mac = calculate_mac("", "", macs_json.pointer("/protection/macs"));
+            macs_json["protection"]["super_mac"] = Value::String(super_mac);
+        }
+        write_atomic(macs_path, &serde_json::to_string(&macs_json)?)?;
+    }
+    Ok(())
+}
+
+fn apply_launch_flags(flags_path: &Path, launch_flags: &[String]) -> Result<Vec<ChangedKey>, DebloaterError> {
+    if launch_flags.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(flags_path).unwrap_or_default();
+    let lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut flags = lines.clone();
+    for flag in launch_flags {
+        add_launch_flag(&mut flags, flag);
+    }
+
+    let changes: Vec<ChangedKey> = flags
+        .iter()
+        .filter(|flag| !lines.contains(flag))
+        .map(|flag| ChangedKey {
+            file: flags_path.to_path_buf(),
+            key: flag.clone(),
+            old_value: get_list_switch(flag)
+                .and_then(|name| lines.iter().find(|line| get_list_switch(line) == Some(name)))
+                .map(|line| Value::String(line.clone())),
+            new_value: Value::Bool(true),
+        })
+        .collect();
+
+    if !changes.is_empty() {
+        if let Some(dir) = flags_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(flags_path, &format!("{}\n", flags.join("\n")))?;
+    }
+    Ok(changes)
+}
+
+/// Deep-merges `changes` into a JSON file, saving the original to the backup snapshot.
+fn merge_into_file(path: &Path, changes: Value, backup: &BackupSnapshot) -> Result<Vec<ChangedKey>, DebloaterError> {
+    let mut json = read_json(path)?;
+    backup.add(path)?;
+
+    let mut changed_keys = Vec::new();
+    merge_value(&mut json, changes, "", &mut |key, old_value, new_value| {
+        changed_keys.push(ChangedKey { file: path.to_path_buf(), key, old_value, new_value });
+    });
+
+    write_atomic(path, &serde_json::to_string(&json)?)?;
+    Ok(changed_keys)
+}
+
+/// Deep-merges `changes` into `target` and reports every changed leaf, [`REPLACED_PREFERENCES`] are set whole.
+pub fn merge_value(target: &mut Value, changes: Value, prefix: &str, on_change: &mut dyn FnMut(String, Option<Value>, Value)) {
+    let Value::Object(changes) = changes else {
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        return;
+    };
+
+    for (key, new_value) in changes {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match new_value {
+            Value::Object(_) if !REPLACED_PREFERENCES.contains(&path.as_str()) => {
+                let child = target.entry(key).or_insert_with(|| Value::Object(Map::new()));
+                merge_value(child, new_value, &path, on_change);
+            }
+            new_value => {
+                let old_value = target.get(&key).cloned();
+                if old_value.as_ref() != Some(&new_value) {
+                    target.insert(key, new_value.clone());
+                    on_change(path, old_value, new_value);
+                }
+            }
+        }
+    }
+}
+
+fn set_path(map: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        None => {
+            map.insert(path.to_string(), value);
+        }
+        Some((key, rest)) => {
+            let child = map.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            if let Value::Object(child) = child {
+                set_path(child, rest, value);
+            }
+        }
+    }
+}
+
+impl BackupSnapshot {
+    pub fn new(data_dir: &Path) -> Self {
+        let timestamp = chrono::Local::now().format(BACKUP_TIMESTAMP_FORMAT).to_string();
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            dir: data_dir.join(BACKUP_DIR_NAME).join(timestamp),
+        }
+    }
+
+    /// Copies a file into the snapshot once, keeping its path relative to the data directory.
+    pub fn add(&self, path: &Path) -> Result<(), DebloaterError> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let relative_path = path.strip_prefix(&self.data_dir).unwrap_or(path);
+        let target = self.dir.join(relative_path);
+        if target.exists() {
+            return Ok(());
+        }
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(path, &target)?;
+
+        let manifest_path = self.dir.join("manifest.txt");
+        let mut manifest = fs::read_to_string(&manifest_path).unwrap_or_default();
+        manifest.push_str(&relative_path.to_string_lossy());
+        manifest.push('\n');
+        fs::write(manifest_path, manifest)?;
+        Ok(())
+    }
+
+    /// Removes old snapshots so that the oldest one, from before the first run, and the newest `retention` others are kept.
+    pub fn rotate(&self, retention: usize) -> Result<(), DebloaterError> {
+        let Some(backup_root) = self.dir.parent() else {
+            return Ok(());
+        };
+        if self.dir.exists() {
+            println!("Backup saved to: {}", self.dir.display());
+        }
+        let Ok(entries) = fs::read_dir(backup_root) else {
+            return Ok(());
+        };
+        let mut snapshots: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        // The oldest snapshot holds the files from before the first run and is never removed
+        snapshots.sort();
+        for snapshot in snapshots.into_iter().skip(1).rev().skip(retention) {
+            fs::remove_dir_all(snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over the original,
+/// keeping the original's permissions and owner. On failure the original is left untouched.
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), DebloaterError> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = write_and_replace(path, &tmp_path, contents);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn write_and_replace(path: &Path, tmp_path: &Path, contents: &str) -> Result<(), DebloaterError> {
+    let mut file = fs::File::create(tmp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(tmp_path, metadata.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Only root can hand a file to another user, keep our own ownership otherwise
+            let _ = std::os::unix::fs::chown(tmp_path, Some(metadata.uid()), Some(metadata.gid()));
+        }
+    }
+
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Reads a JSON file, treating a missing file as an empty object.
+pub fn read_json(path: &Path) -> Result<Value, DebloaterError> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Value::Object(Map::new())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn is_brave_running() -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join("comm")).ok())
+        .any(|comm| comm.trim() == "brave")
+}
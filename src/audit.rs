@@ -0,0 +1,232 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::apply::{LinuxInstallation, detect_installations, get_profiles, read_json, select_preference_set};
+use crate::cli::BraveVersion;
+use crate::config::{Config, Extension, get_policy_config};
+use crate::error::DebloaterError;
+use crate::platforms::escape::xml_escape;
+use crate::platforms::get_linux_flags_file;
+use crate::preferences::{
+    PreferencesInputConfig, add_launch_flag, get_default_experimental_features, get_default_launch_flags, get_preference_values,
+    get_profile_preference_sets, get_user_preferences,
+};
+
+/// The result of comparing one key against the configured value.
+#[derive(Debug, Serialize)]
+pub struct AuditCheck {
+    pub file: PathBuf,
+    pub key: String,
+    pub expected: Value,
+    pub actual: Option<Value>,
+    pub passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub checks: Vec<AuditCheck>,
+}
+
+/// Compares the local Linux machine against the configuration `apply` and the generated scripts write.
+pub fn audit_linux(
+    config: &Config,
+    extensions: &[Extension],
+    version: &BraveVersion,
+    preferences_config: Option<&PreferencesInputConfig>,
+) -> Result<AuditReport, DebloaterError> {
+    if !cfg!(target_os = "linux") {
+        return Err(DebloaterError::UnsupportedPlatform("audit is only supported on Linux".to_string()));
+    }
+
+    audit_installations(&detect_installations(version)?, config, extensions, version, preferences_config)
+}
+
+/// Compares the given installations against the configuration.
+pub fn audit_installations(
+    installations: &[LinuxInstallation],
+    config: &Config,
+    extensions: &[Extension],
+    version: &BraveVersion,
+    preferences_config: Option<&PreferencesInputConfig>,
+) -> Result<AuditReport, DebloaterError> {
+    let mut checks = Vec::new();
+
+    let policies = serde_json::to_value(get_policy_config(config, extensions))?;
+    for installation in installations {
+        check_values(&mut checks, &installation.policy_path, &read_json(&installation.policy_path)?, &policies);
+    }
+
+    // The system and user Flatpak share one data directory
+    let mut audited: Vec<&Path> = Vec::new();
+    for installation in installations {
+        if audited.contains(&installation.data_dir.as_path()) {
+            continue;
+        }
+        audited.push(&installation.data_dir);
+        audit_user_data(&mut checks, installation, extensions, version, preferences_config)?;
+    }
+
+    let failed = checks.iter().filter(|c| !c.passed).count();
+    Ok(AuditReport { passed: checks.len() - failed, failed, checks })
+}
+
+fn audit_user_data(
+    checks: &mut Vec<AuditCheck>,
+    installation: &LinuxInstallation,
+    extensions: &[Extension],
+    version: &BraveVersion,
+    preferences_config: Option<&PreferencesInputConfig>,
+) -> Result<(), DebloaterError> {
+    let local_state_path = installation.data_dir.join("Local State");
+    let local_state = read_json(&local_state_path)?;
+
+    let preference_sets = get_profile_preference_sets(preferences_config);
+    for profile in get_profiles(&installation.data_dir, &local_state) {
+        let Some(preference_set) = select_preference_set(&preference_sets, &profile, &local_state) else {
+            continue;
+        };
+        let prefs_path = installation.data_dir.join(&profile).join("Preferences");
+        let prefs = read_json(&prefs_path)?;
+        check_values(checks, &prefs_path, &prefs, &serde_json::to_value(get_user_preferences(preference_set))?);
+
+        // Forced extensions show up in extensions.settings once Brave has installed them
+        let secure_prefs = read_json(&prefs_path.with_file_name("Secure Preferences"))?;
+        for extension in extensions {
+            let pointer = format!("/extensions/settings/{}", extension.id);
+            let installed = prefs.pointer(&pointer).is_some() || secure_prefs.pointer(&pointer).is_some();
+            checks.push(AuditCheck {
+                file: prefs_path.clone(),
+                key: format!("extensions.settings.{}", extension.id),
+                expected: Value::Bool(true),
+                actual: Some(Value::Bool(installed)),
+                passed: installed,
+            });
+        }
+    }
+
+    let local_state_changes = serde_json::json!({
+        "browser": { "enabled_labs_experiments": get_default_experimental_features(preferences_config) }
+    });
+    check_values(checks, &local_state_path, &local_state, &local_state_changes);
+
+    let flags_path = installation.config_dir.join(get_linux_flags_file(version));
+    let flags_content = fs::read_to_string(&flags_path).unwrap_or_default();
+    let lines: Vec<String> = flags_content.lines().map(str::to_string).collect();
+    for flag in get_default_launch_flags(preferences_config) {
+        // Present when adding the flag would not change the file, list switches may hold more values
+        let mut expected = lines.clone();
+        add_launch_flag(&mut expected, &flag);
+        let present = expected == lines;
+        checks.push(AuditCheck {
+            file: flags_path.clone(),
+            key: flag,
+            expected: Value::Bool(true),
+            actual: Some(Value::Bool(present)),
+            passed: present,
+        });
+    }
+    Ok(())
+}
+
+fn check_values(checks: &mut Vec<AuditCheck>, file: &Path, json: &Value, expected: &Value) {
+    for (key, expected) in get_preference_values(expected) {
+        let actual = key.split('.').try_fold(json, |value, k| value.get(k)).cloned();
+        checks.push(AuditCheck {
+            file: file.to_path_buf(),
+            passed: actual.as_ref().is_some_and(|actual| value_matches(actual, &expected)),
+            key,
+            expected,
+            actual,
+        });
+    }
+}
+
+/// Whether `actual` holds `expected`. Dictionaries may have extra keys, which Brave adds to e.g. the search engine.
+fn value_matches(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => {
+            expected.iter().all(|(key, expected)| actual.get(key).is_some_and(|actual| value_matches(actual, expected)))
+        }
+        _ => actual == expected,
+    }
+}
+
+impl AuditReport {
+    pub fn to_text(&self) -> String {
+        let mut content = String::new();
+        for check in &self.checks {
+            if check.passed {
+                content.push_str(&format!("PASS  {}: {}\n", check.file.display(), check.key));
+            } else {
+                content.push_str(&format!(
+                    "FAIL  {}: {} = {} (expected {})\n",
+                    check.file.display(),
+                    check.key,
+                    format_actual(&check.actual),
+                    check.expected
+                ));
+            }
+        }
+        content.push_str(&format!("\n{} checks, {} passed, {} failed\n", self.checks.len(), self.passed, self.failed));
+        content
+    }
+
+    pub fn to_json(&self) -> Result<String, DebloaterError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Renders one test suite per audited file and one test case per key.
+    pub fn to_junit(&self) -> String {
+        let mut files: Vec<&Path> = Vec::new();
+        for check in &self.checks {
+            if !files.contains(&check.file.as_path()) {
+                files.push(&check.file);
+            }
+        }
+
+        let mut content = String::new();
+        content.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        content.push_str(&format!(
+            "<testsuites name=\"brave-debloater audit\" tests=\"{}\" failures=\"{}\">\n",
+            self.checks.len(),
+            self.failed
+        ));
+        for file in files {
+            let checks: Vec<&AuditCheck> = self.checks.iter().filter(|c| c.file == file).collect();
+            let failures = checks.iter().filter(|c| !c.passed).count();
+            content.push_str(&format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+                xml_escape(&file.display().to_string()),
+                checks.len(),
+                failures
+            ));
+            for check in checks {
+                let testcase = format!(
+                    "    <testcase classname=\"{}\" name=\"{}\"",
+                    xml_escape(&file.display().to_string()),
+                    xml_escape(&check.key)
+                );
+                if check.passed {
+                    content.push_str(&format!("{}/>\n", testcase));
+                } else {
+                    let message = format!("expected {}, found {}", check.expected, format_actual(&check.actual));
+                    content.push_str(&format!("{}>\n", testcase));
+                    content.push_str(&format!("      <failure message=\"{}\"/>\n", xml_escape(&message)));
+                    content.push_str("    </testcase>\n");
+                }
+            }
+            content.push_str("  </testsuite>\n");
+        }
+        content.push_str("</testsuites>\n");
+        content
+    }
+}
+
+fn format_actual(actual: &Option<Value>) -> String {
+    actual.as_ref().map_or("(unset)".to_string(), |v| v.to_string())
+}
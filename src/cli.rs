@@ -1,4 +1,4 @@
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Clone, ValueEnum)]
 pub enum Platform {
@@ -13,25 +13,107 @@ pub enum BraveVersion {
     Nightly,
 }
 
+/// How the macOS scripts write the policies to `/Library/Managed Preferences`.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum MacOSPolicyMode {
+    /// Write the whole plist file at once
+    #[default]
+    Plist,
+    /// Write each key with `defaults write`, restart cfprefsd and read the keys back
+    Defaults,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Junit,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Apply the configuration to this Linux machine directly, without generating a script
+    Apply,
+    /// Check this Linux machine against the configuration and report every key that differs
+    Audit {
+        #[arg(long, value_enum, default_value = "text")]
+        format: ReportFormat,
+
+        #[arg(long, help = "Write the report to a file instead of stdout")]
+        report: Option<String>,
+    },
+    /// Print the platform-neutral action plan that every generated script applies, as JSON
+    Plan {
+        #[arg(long, help = "Write the plan to a file instead of stdout")]
+        file: Option<String>,
+    },
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "brave-debloater")]
 #[command(about = "A tool to generate Brave browser debloat configurations for different platforms")]
+#[command(subcommand_negates_reqs = true)]
 pub struct Args {
-    #[arg(short, long, value_enum)]
-    pub platform: Platform,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    
+    #[arg(short, long, value_enum, required = true)]
+    pub platform: Option<Platform>,
     
-    #[arg(short, long, value_enum, default_value = "normal")]
+    #[arg(short, long, value_enum, default_value = "normal", global = true)]
     pub version: BraveVersion,
     
-    #[arg(short, long, default_value = "configs/privacy-focused.json")]
+    #[arg(short, long, default_value = "configs/privacy-focused.json", global = true)]
     pub config: String,
     
-    #[arg(short, long, default_value = "extensions.json")]
+    #[arg(short, long, default_value = "extensions.json", global = true)]
     pub extensions: String,
     
     #[arg(short, long, default_value = "output")]
     pub output: String,
     
-    #[arg(long, default_value = "preferences.json", help = "Preferences configuration file")]
+    #[arg(long, help = "Also write the policies as .reg files for regedit (Windows only)")]
+    pub reg: bool,
+    
+    #[arg(long, help = "Also write standalone PowerShell .ps1 scripts (Windows only)")]
+    pub ps1: bool,
+    
+    #[arg(long, help = "Also write the policies as Group Policy Registry.pol files (Windows only)")]
+    pub pol: bool,
+    
+    #[arg(long, help = "Also write a GPO backup folder with the Registry.pol that GPMC can import (Windows only)")]
+    pub gpo_backup: bool,
+    
+    #[arg(long, help = "Also write the policies as a .mobileconfig configuration profile (macOS only)")]
+    pub mobileconfig: bool,
+    
+    #[arg(long, value_enum, default_value = "plist", help = "How to write the policies (macOS only)")]
+    pub macos_policies: MacOSPolicyMode,
+    
+    #[arg(long, help = "Convert the policy plist to the binary format (macOS only)")]
+    pub binary_plist: bool,
+    
+    #[arg(long, help = "Also write the policies, extensions and launch flags as a NixOS / Home Manager module (Linux only)")]
+    pub nix: bool,
+    
+    #[arg(long, help = "Also build a .deb package installing the policy file (Linux only)")]
+    pub deb: bool,
+    
+    #[arg(long, help = "Also build an .rpm package installing the policy file (Linux only)")]
+    pub rpm: bool,
+    
+    #[arg(long, default_value = "1.0", help = "Version of the .deb and .rpm packages")]
+    pub package_version: String,
+    
+    #[arg(long, default_value = "Brave Debloater <root@localhost>", help = "Maintainer of the .deb and .rpm packages")]
+    pub package_maintainer: String,
+    
+    #[arg(long, help = "Also write an Ansible role applying the configuration (Linux and macOS)")]
+    pub ansible: bool,
+    
+    #[arg(long, help = "Also write an initial_preferences file that new profiles start from")]
+    pub initial_preferences: bool,
+    
+    #[arg(long, default_value = "preferences.json", help = "Preferences configuration file", global = true)]
     pub preferences_config: String,
 }
\ No newline at end of file
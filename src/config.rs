@@ -6,7 +6,7 @@ use std::path::Path;
 use crate::error::DebloaterError;
 use crate::preferences::PreferencesInputConfig;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum ConfigValue {
     Bool(bool),
@@ -29,6 +29,16 @@ pub struct ExtensionsConfig {
     pub extensions: Vec<Extension>,
 }
 
+/// Returns the managed policy set with the extensions added to `ExtensionInstallForcelist`.
+pub fn get_policy_config(config: &Config, extensions: &[Extension]) -> Config {
+    let mut final_config = config.clone();
+    if !extensions.is_empty() {
+        let extension_ids: Vec<String> = extensions.iter().map(|e| e.id.clone()).collect();
+        final_config.insert("ExtensionInstallForcelist".to_string(), ConfigValue::StringArray(extension_ids));
+    }
+    final_config
+}
+
 pub fn load_config(config_path: &str) -> Result<Config, DebloaterError> {
     if !Path::new(config_path).exists() {
         return Err(DebloaterError::ConfigNotFound(config_path.to_string()));
@@ -8,4 +8,8 @@ pub enum DebloaterError {
     Json(#[from] serde_json::Error),
     #[error("Config file not found: {0}")]
     ConfigNotFound(String),
+    #[error("Unsupported platform: {0}")]
+    UnsupportedPlatform(String),
+    #[error("Invalid package field: {0}")]
+    InvalidPackageField(String),
 }
\ No newline at end of file
@@ -1,11 +1,54 @@
 use std::fs;
+use std::path::Path;
 
-use crate::cli::{Platform, BraveVersion};
+use crate::cli::{Platform, BraveVersion, MacOSPolicyMode};
 use crate::config::{Config, Extension};
 use crate::error::DebloaterError;
-use crate::platforms::{PlatformGenerator, windows::WindowsGenerator, macos::MacOSGenerator, linux::LinuxGenerator};
+use crate::plan::build_plan;
+use crate::platforms::{
+    PlatformGenerator, get_initial_preferences_path, windows::WindowsGenerator, macos::MacOSGenerator, linux::LinuxGenerator,
+    ansible::AnsibleGenerator, package::PackageOptions,
+};
 use crate::preferences::PreferencesInputConfig;
 
+/// Optional Windows files written next to the batch scripts.
+#[derive(Debug, Clone, Default)]
+pub struct WindowsOutputs {
+    pub reg: bool,
+    pub registry_pol: bool,
+    pub gpo_backup: bool,
+    pub powershell: bool,
+}
+
+/// Optional macOS files written next to the shell scripts, and how the scripts write the policies.
+#[derive(Debug, Clone, Default)]
+pub struct MacOSOutputs {
+    pub mobileconfig: bool,
+    pub policy_mode: MacOSPolicyMode,
+    pub binary_plist: bool,
+    /// Name of the configuration, shown in the configuration profile and part of its identifier.
+    pub profile_name: String,
+}
+
+/// Optional Linux files written next to the shell scripts.
+#[derive(Debug, Clone, Default)]
+pub struct LinuxOutputs {
+    pub nix: bool,
+    pub packages: PackageOptions,
+}
+
+/// Optional files written next to the scripts, per platform.
+#[derive(Debug, Clone, Default)]
+pub struct ExtraOutputs {
+    pub windows: WindowsOutputs,
+    pub macos: MacOSOutputs,
+    pub linux: LinuxOutputs,
+    /// Ansible role for Linux and macOS hosts.
+    pub ansible: bool,
+    /// `initial_preferences` file that new profiles start from.
+    pub initial_preferences: bool,
+}
+
 pub struct DebloaterGenerator {
     config: Config,
     extensions: Vec<Extension>,
@@ -13,6 +56,7 @@ pub struct DebloaterGenerator {
     version: BraveVersion,
     output_dir: String,
     preferences_config: Option<PreferencesInputConfig>,
+    extra_outputs: ExtraOutputs,
 }
 
 impl DebloaterGenerator {
@@ -23,6 +67,7 @@ impl DebloaterGenerator {
         version: BraveVersion,
         output_dir: String,
         preferences_config: Option<PreferencesInputConfig>,
+        extra_outputs: ExtraOutputs,
     ) -> Self {
         Self {
             config,
@@ -31,6 +76,14 @@ impl DebloaterGenerator {
             version,
             output_dir,
             preferences_config,
+            extra_outputs,
+        }
+    }
+
+    fn macos_generator(&self) -> MacOSGenerator {
+        MacOSGenerator {
+            policy_mode: self.extra_outputs.macos.policy_mode,
+            binary_plist: self.extra_outputs.macos.binary_plist,
         }
     }
 
@@ -39,16 +92,57 @@ impl DebloaterGenerator {
         
         let generator: Box<dyn PlatformGenerator> = match self.platform {
             Platform::Windows => Box::new(WindowsGenerator),
-            Platform::MacOS => Box::new(MacOSGenerator),
+            Platform::MacOS => Box::new(self.macos_generator()),
             Platform::Linux => Box::new(LinuxGenerator),
         };
 
-        generator.generate_unified_script(
-            &self.config,
-            &self.extensions,
-            &self.version,
-            &self.output_dir,
-            self.preferences_config.as_ref(),
-        )
+        let plan = build_plan(&self.config, &self.extensions, self.preferences_config.as_ref())?;
+        generator.generate_unified_script(&plan, &self.version, &self.output_dir)?;
+        generator.generate_revert_script(&plan, &self.version, &self.output_dir)?;
+
+        if matches!(self.platform, Platform::Windows) {
+            if self.extra_outputs.windows.reg {
+                WindowsGenerator.generate_reg_files(&plan, &self.version, &self.output_dir)?;
+            }
+            if self.extra_outputs.windows.powershell {
+                WindowsGenerator.generate_powershell_scripts(&plan, &self.version, &self.output_dir)?;
+            }
+            if self.extra_outputs.windows.registry_pol || self.extra_outputs.windows.gpo_backup {
+                WindowsGenerator.generate_registry_pol(&plan, &self.version, &self.output_dir, self.extra_outputs.windows.gpo_backup)?;
+            }
+        }
+
+        if matches!(self.platform, Platform::MacOS) && self.extra_outputs.macos.mobileconfig {
+            self.macos_generator().generate_mobileconfig(&plan, &self.version, &self.output_dir, &self.extra_outputs.macos.profile_name)?;
+        }
+
+        if matches!(self.platform, Platform::Linux) && self.extra_outputs.linux.nix {
+            LinuxGenerator.generate_nix_module(&plan, &self.version, &self.output_dir)?;
+        }
+
+        let packages = &self.extra_outputs.linux.packages;
+        if matches!(self.platform, Platform::Linux) && (packages.deb || packages.rpm) {
+            LinuxGenerator.generate_packages(&plan, &self.version, &self.output_dir, packages, self.extra_outputs.initial_preferences)?;
+        }
+
+        if !matches!(self.platform, Platform::Windows) && self.extra_outputs.ansible {
+            AnsibleGenerator.generate_role(&plan, &self.version, &self.output_dir)?;
+        }
+
+        if self.extra_outputs.initial_preferences {
+            let filename = match self.version {
+                BraveVersion::Normal => "initial_preferences",
+                BraveVersion::Nightly => "nightly_initial_preferences",
+            };
+            let initial_preferences = plan.initial_preferences_json();
+            if initial_preferences.as_object().is_some_and(|preferences| preferences.is_empty()) {
+                eprintln!("Warning: no preference set applies to new profiles, add one for every profile or for \"Default\"");
+            }
+            let content = serde_json::to_string_pretty(&initial_preferences)?;
+            fs::write(Path::new(&self.output_dir).join(filename), content + "\n")?;
+            println!("Install {} as {}", filename, get_initial_preferences_path(&self.platform, &self.version));
+        }
+
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,11 +1,17 @@
+pub mod apply;
+pub mod audit;
 pub mod cli;
 pub mod config;
 pub mod error;
 pub mod generator;
+pub mod plan;
 pub mod platforms;
 pub mod preferences;
 
+pub use apply::*;
+pub use audit::*;
 pub use cli::*;
 pub use config::*;
 pub use error::*;
-pub use generator::*;
\ No newline at end of file
+pub use generator::*;
+pub use plan::*;
\ No newline at end of file
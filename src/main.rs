@@ -1,37 +1,91 @@
+use std::path::Path;
+
 use clap::Parser;
 
 use brave_debloater::{
-    Args, DebloaterError, DebloaterGenerator,
-    load_config, load_extensions, load_preferences_config,
-    Platform
+    Args, Command, DebloaterError, DebloaterGenerator,
+    apply_linux, audit_linux, build_plan, load_config, load_extensions, load_preferences_config,
+    ExtraOutputs, LinuxOutputs, MacOSOutputs, Platform, ReportFormat, WindowsOutputs
 };
+use brave_debloater::platforms::package::PackageOptions;
 
 fn main() -> Result<(), DebloaterError> {
     let args = Args::parse();
     
-    println!("Loading configuration from: {}", args.config);
+    // Progress goes to stderr so audit reports on stdout stay machine-readable
+    eprintln!("Loading configuration from: {}", args.config);
     let config = load_config(&args.config)?;
     
-    println!("Loading extensions from: {}", args.extensions);
+    eprintln!("Loading extensions from: {}", args.extensions);
     let extensions = load_extensions(&args.extensions)?;
     
     let extension_names: Vec<String> = extensions.iter().map(|e| e.name.clone()).collect();
-    println!("Loaded {} extensions: {}", 
+    eprintln!("Loaded {} extensions: {}", 
              extensions.len(),
              extension_names.join(", "));
 
     // Always load preferences config for unified scripts
-    println!("Loading preferences from: {}", args.preferences_config);
+    eprintln!("Loading preferences from: {}", args.preferences_config);
     let preferences_config = load_preferences_config(&args.preferences_config)?;
 
     if preferences_config.is_some() {
-        println!("Loaded preferences configuration");
+        eprintln!("Loaded preferences configuration");
     } else {
-        println!("Using default preferences configuration");
+        eprintln!("Using default preferences configuration");
+    }
+    
+    if let Some(Command::Apply) = args.command {
+        println!("Applying configuration for Brave {:?}...", args.version);
+        let changes = apply_linux(&config, &extensions, &args.version, preferences_config.as_ref())?;
+        
+        println!("Changed {} keys:", changes.len());
+        for change in &changes {
+            let old_value = change.old_value.as_ref().map_or("(unset)".to_string(), |v| v.to_string());
+            println!("  {}: {} = {} -> {}", change.file.display(), change.key, old_value, change.new_value);
+        }
+        println!("Please restart Brave browser for changes to take effect.");
+        
+        return Ok(());
+    }
+    
+    if let Some(Command::Audit { format, report }) = &args.command {
+        eprintln!("Auditing configuration for Brave {:?}...", args.version);
+        let audit = audit_linux(&config, &extensions, &args.version, preferences_config.as_ref())?;
+        
+        let content = match format {
+            ReportFormat::Text => audit.to_text(),
+            ReportFormat::Json => audit.to_json()?,
+            ReportFormat::Junit => audit.to_junit(),
+        };
+        match report {
+            Some(path) => {
+                std::fs::write(path, content)?;
+                eprintln!("Audit report written to: {}", path);
+            }
+            None => print!("{}", content),
+        }
+        
+        if audit.failed > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    if let Some(Command::Plan { file }) = &args.command {
+        let content = build_plan(&config, &extensions, preferences_config.as_ref())?.to_json()?;
+        match file {
+            Some(path) => {
+                std::fs::write(path, content)?;
+                eprintln!("Plan written to: {}", path);
+            }
+            None => println!("{}", content),
+        }
+        return Ok(());
     }
     
+    let platform = args.platform.expect("--platform is required without a subcommand");
     println!("Generating unified {} script for Brave {:?}...", 
-             match args.platform {
+             match platform {
                  Platform::Windows => "Windows",
                  Platform::MacOS => "macOS",
                  Platform::Linux => "Linux",
@@ -39,10 +93,35 @@ fn main() -> Result<(), DebloaterError> {
              args.version);
     
     let output_dir = args.output.clone();
-    let generator = DebloaterGenerator::new(config, extensions, args.platform, args.version, args.output, preferences_config);
+    let extra_outputs = ExtraOutputs {
+        windows: WindowsOutputs {
+            reg: args.reg,
+            registry_pol: args.pol,
+            gpo_backup: args.gpo_backup,
+            powershell: args.ps1,
+        },
+        macos: MacOSOutputs {
+            mobileconfig: args.mobileconfig,
+            policy_mode: args.macos_policies,
+            binary_plist: args.binary_plist,
+            profile_name: Path::new(&args.config).file_stem().map_or("custom".to_string(), |stem| stem.to_string_lossy().into_owned()),
+        },
+        linux: LinuxOutputs {
+            nix: args.nix,
+            packages: PackageOptions {
+                deb: args.deb,
+                rpm: args.rpm,
+                version: args.package_version,
+                maintainer: args.package_maintainer,
+            },
+        },
+        ansible: args.ansible,
+        initial_preferences: args.initial_preferences,
+    };
+    let generator = DebloaterGenerator::new(config, extensions, platform, args.version, args.output, preferences_config, extra_outputs);
     generator.generate()?;
     
     println!("Configuration files generated successfully in: {}", output_dir);
     
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,212 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::{Config, ConfigValue, Extension, get_policy_config};
+use crate::error::DebloaterError;
+use crate::preferences::{
+    DEFAULT_BACKUP_RETENTION, PreferencesInputConfig, get_backup_retention, get_default_experimental_features, get_default_launch_flags,
+    get_preference_values, get_profile_preference_sets, get_user_preferences, nest_preference_values,
+};
+
+/// A user data file changed by a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserFile {
+    LocalState,
+    Preferences,
+    SecurePreferences,
+}
+
+impl UserFile {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            UserFile::LocalState => "Local State",
+            UserFile::Preferences => "Preferences",
+            UserFile::SecurePreferences => "Secure Preferences",
+        }
+    }
+}
+
+/// One step of a debloat plan. Every platform backend renders the same steps.
+///
+/// `profile` selects the profiles a step applies to by directory or display name; `None` means every profile.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Warns about a running Brave, which would overwrite the changed files on exit.
+    CheckProcess,
+    /// Sets a managed policy.
+    WritePolicy { key: String, value: ConfigValue },
+    /// Copies a user data file into the backup snapshot before it is changed.
+    BackupFile {
+        file: UserFile,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Sets a dotted preference path in Local State or in the Preferences of the selected profiles.
+    SetPreference {
+        file: UserFile,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        path: String,
+        value: Value,
+    },
+    /// Recomputes the MACs of the protected preferences of the selected profiles.
+    UpdatePreferenceMacs {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Removes the oldest backup snapshots beyond `retention`.
+    RotateBackups { retention: usize },
+    /// Adds a command-line switch to the Brave launcher.
+    AddLaunchFlag { flag: String },
+}
+
+/// Preferences changes for one group of profiles.
+#[derive(Debug)]
+pub struct ProfileChanges<'a> {
+    pub profile: Option<&'a str>,
+    pub preferences: Vec<(&'a str, &'a Value)>,
+}
+
+/// The platform-neutral list of actions compiled from the configuration.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+}
+
+/// Compiles the policy, extension and preferences configuration into a plan.
+pub fn build_plan(config: &Config, extensions: &[Extension], preferences_config: Option<&PreferencesInputConfig>) -> Result<Plan, DebloaterError> {
+    let mut actions = vec![Action::CheckProcess];
+
+    // Sorted so that every backend and the exported plan list the policies in the same order
+    let mut policies: Vec<(String, ConfigValue)> = get_policy_config(config, extensions).into_iter().collect();
+    policies.sort_by(|a, b| a.0.cmp(&b.0));
+    actions.extend(policies.into_iter().map(|(key, value)| Action::WritePolicy { key, value }));
+
+    actions.push(Action::BackupFile { file: UserFile::LocalState, profile: None });
+    for preference_set in get_profile_preference_sets(preferences_config) {
+        let profile = preference_set.profile.clone();
+        actions.push(Action::BackupFile { file: UserFile::Preferences, profile: profile.clone() });
+        for (path, value) in get_preference_values(&serde_json::to_value(get_user_preferences(&preference_set))?) {
+            actions.push(Action::SetPreference { file: UserFile::Preferences, profile: profile.clone(), path, value });
+        }
+        actions.push(Action::BackupFile { file: UserFile::SecurePreferences, profile: profile.clone() });
+        actions.push(Action::UpdatePreferenceMacs { profile });
+    }
+
+    actions.push(Action::SetPreference {
+        file: UserFile::LocalState,
+        profile: None,
+        path: "browser.enabled_labs_experiments".to_string(),
+        value: Value::from(get_default_experimental_features(preferences_config)),
+    });
+    actions.push(Action::RotateBackups { retention: get_backup_retention(preferences_config) });
+
+    actions.extend(get_default_launch_flags(preferences_config).into_iter().map(|flag| Action::AddLaunchFlag { flag }));
+
+    Ok(Plan { actions })
+}
+
+impl Plan {
+    pub fn to_json(&self) -> Result<String, DebloaterError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn checks_process(&self) -> bool {
+        self.actions.contains(&Action::CheckProcess)
+    }
+
+    pub fn policies(&self) -> Vec<(&str, &ConfigValue)> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::WritePolicy { key, value } => Some((key.as_str(), value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the policies as a JSON object, as written to a policy file.
+    pub fn policies_json(&self) -> Result<Value, DebloaterError> {
+        let mut policies = serde_json::Map::new();
+        for (key, value) in self.policies() {
+            policies.insert(key.to_string(), serde_json::to_value(value)?);
+        }
+        Ok(Value::Object(policies))
+    }
+
+    /// Groups the Preferences changes by profile selection, in plan order.
+    pub fn profile_changes(&self) -> Vec<ProfileChanges<'_>> {
+        let mut groups: Vec<ProfileChanges> = Vec::new();
+        for action in &self.actions {
+            let (profile, preference) = match action {
+                Action::BackupFile { file: UserFile::Preferences, profile } => (profile, None),
+                Action::SetPreference { file: UserFile::Preferences, profile, path, value } => (profile, Some((path.as_str(), value))),
+                _ => continue,
+            };
+            let profile = profile.as_deref();
+            let index = match groups.iter().position(|group| group.profile == profile) {
+                Some(index) => index,
+                None => {
+                    groups.push(ProfileChanges { profile, preferences: Vec::new() });
+                    groups.len() - 1
+                }
+            };
+            groups[index].preferences.extend(preference);
+        }
+        groups
+    }
+
+    /// Returns the Preferences values for an `initial_preferences` file. New profiles get the preference set for
+    /// every profile, or the one for `Default` when the sets are per profile.
+    pub fn initial_preferences_json(&self) -> Value {
+        let profile_changes = self.profile_changes();
+        let preferences = profile_changes
+            .iter()
+            .find(|changes| changes.profile.is_none())
+            .or_else(|| profile_changes.iter().find(|changes| changes.profile == Some("Default")))
+            .map(|changes| changes.preferences.as_slice())
+            .unwrap_or_default();
+        nest_preference_values(preferences)
+    }
+
+    pub fn local_state_changes(&self) -> Vec<(&str, &Value)> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::SetPreference { file: UserFile::LocalState, path, value, .. } => Some((path.as_str(), value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether `file` is copied into the backup snapshot before it is changed.
+    pub fn backs_up(&self, file: UserFile) -> bool {
+        self.actions.iter().any(|action| matches!(action, Action::BackupFile { file: f, .. } if *f == file))
+    }
+
+    pub fn updates_preference_macs(&self) -> bool {
+        self.actions.iter().any(|action| matches!(action, Action::UpdatePreferenceMacs { .. }))
+    }
+
+    pub fn backup_retention(&self) -> usize {
+        self.actions
+            .iter()
+            .find_map(|action| match action {
+                Action::RotateBackups { retention } => Some(*retention),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_BACKUP_RETENTION)
+    }
+
+    pub fn launch_flags(&self) -> Vec<&str> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::AddLaunchFlag { flag } => Some(flag.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
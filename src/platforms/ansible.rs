@@ -0,0 +1,553 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::cli::BraveVersion;
+use crate::error::DebloaterError;
+use crate::plan::Plan;
+use crate::platforms::escape::yaml_quote;
+use crate::platforms::{
+    LINUX_FLATPAK_APP_ID, LINUX_FLATPAK_POLICY_EXTENSION, LINUX_SANDBOX_POLICY_FILE, LINUX_SNAP_NAME, get_linux_flags_file,
+    get_linux_policy_path, get_macos_app_name, get_macos_bundle_id, get_version_suffix,
+};
+use crate::preferences::{LIST_SWITCHES, MODIFIED_PREFERENCES, PREF_HASH_SEED, REPLACED_PREFERENCES, get_protected_preferences, nest_preference_values};
+
+/// Writes an Ansible role applying the plan to Linux and macOS hosts.
+pub struct AnsibleGenerator;
+
+impl AnsibleGenerator {
+    pub fn generate_role(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let role_name = match version {
+            BraveVersion::Normal => "brave_debloat",
+            BraveVersion::Nightly => "brave_nightly_debloat",
+        };
+        let role_dir = Path::new(output_dir).join(role_name);
+
+        let mut defaults = String::new();
+        add_defaults(&mut defaults, plan, version)?;
+        write_role_file(&role_dir, "defaults/main.yml", &defaults)?;
+
+        let mut vars = String::new();
+        add_vars(&mut vars);
+        write_role_file(&role_dir, "vars/main.yml", &vars)?;
+
+        let mut main_tasks = String::new();
+        add_main_tasks(&mut main_tasks);
+        write_role_file(&role_dir, "tasks/main.yml", &main_tasks)?;
+
+        let mut user_tasks = String::new();
+        add_user_tasks(&mut user_tasks);
+        write_role_file(&role_dir, "tasks/user.yml", &user_tasks)?;
+
+        let mut profile_tasks = String::new();
+        add_profile_tasks(&mut profile_tasks);
+        write_role_file(&role_dir, "tasks/profile.yml", &profile_tasks)?;
+
+        let mut handlers = String::new();
+        add_handlers(&mut handlers);
+        write_role_file(&role_dir, "handlers/main.yml", &handlers)?;
+
+        let mut plist_template = String::new();
+        add_plist_template(&mut plist_template);
+        write_role_file(&role_dir, "templates/brave_policies.plist.j2", &plist_template)?;
+
+        let mut mac_script = String::new();
+        add_mac_script(&mut mac_script);
+        write_role_file(&role_dir, "files/update_preference_macs.py", &mac_script)?;
+
+        let mut meta = String::new();
+        add_meta(&mut meta, role_name);
+        write_role_file(&role_dir, "meta/main.yml", &meta)?;
+
+        Ok(())
+    }
+}
+
+fn write_role_file(role_dir: &Path, relative_path: &str, content: &str) -> Result<(), DebloaterError> {
+    let path = role_dir.join(relative_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Renders a JSON value as a YAML block value, nested collections are indented from `indent` levels.
+/// Strings that could start a Jinja expression are tagged `!unsafe` so Ansible does not template them.
+pub fn yaml_value(value: &Value, indent: usize) -> String {
+    let padding = "  ".repeat(indent);
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) if s.contains('{') => format!("!unsafe {}", yaml_quote(s)),
+        Value::String(s) => yaml_quote(s),
+        Value::Array(items) if items.is_empty() => "[]".to_string(),
+        Value::Array(items) => {
+            let mut content = String::new();
+            for item in items {
+                // A nested block starts on the line of its dash
+                let item = yaml_value(item, indent + 1);
+                let item = item.strip_prefix(&format!("\n{}  ", padding)).unwrap_or(&item);
+                content.push_str(&format!("\n{}- {}", padding, item));
+            }
+            content
+        }
+        Value::Object(map) if map.is_empty() => "{}".to_string(),
+        Value::Object(map) => {
+            let mut content = String::new();
+            for (key, value) in map {
+                let value = yaml_value(value, indent + 1);
+                let separator = if value.starts_with('\n') { "" } else { " " };
+                content.push_str(&format!("\n{}{}:{}{}", padding, yaml_key(key), separator, value));
+            }
+            content
+        }
+    }
+}
+
+fn add_yaml_variable(content: &mut String, name: &str, value: &Value) {
+    let value = yaml_value(value, 1);
+    let separator = if value.starts_with('\n') { "" } else { " " };
+    content.push_str(&format!("{}:{}{}\n", name, separator, value));
+}
+
+/// Returns the key as is when YAML reads it back as the same string, and quoted otherwise.
+pub fn yaml_key(key: &str) -> String {
+    let mut chars = key.chars();
+    let is_plain = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    let is_keyword = matches!(
+        key.to_ascii_lowercase().as_str(),
+        "y" | "n" | "yes" | "no" | "on" | "off" | "true" | "false" | "null"
+    );
+    if is_plain && !is_keyword {
+        key.to_string()
+    } else {
+        yaml_quote(key)
+    }
+}
+
+fn add_defaults(content: &mut String, plan: &Plan, version: &BraveVersion) -> Result<(), DebloaterError> {
+    let channel = match version {
+        BraveVersion::Normal => "normal",
+        BraveVersion::Nightly => "nightly",
+    };
+    let profile_preferences: Vec<Value> = plan
+        .profile_changes()
+        .iter()
+        .map(|changes| serde_json::json!({ "profile": changes.profile, "preferences": nest_preference_values(&changes.preferences) }))
+        .collect();
+
+    content.push_str("---\n");
+    content.push_str("# Brave Browser debloat configuration, generated by brave-debloater\n\n");
+    content.push_str("# Brave channel to configure: normal or nightly\n");
+    content.push_str(&format!("brave_debloat_channel: {}\n\n", channel));
+    content.push_str("# Users whose Brave profiles are configured\n");
+    content.push_str("brave_debloat_users:\n");
+    content.push_str("  - \"{{ ansible_env.SUDO_USER | default(ansible_user_id) }}\"\n\n");
+    content.push_str("# How Brave is installed on Linux hosts: native, snap or flatpak (system installation)\n");
+    content.push_str("brave_debloat_linux_package: native\n\n");
+    content.push_str("# Write the managed policies, this needs root\n");
+    content.push_str("brave_debloat_manage_policies: true\n\n");
+    content.push_str("# Recompute the MACs of protected preferences so Brave keeps the changes (Linux only)\n");
+    content.push_str(&format!("brave_debloat_update_preference_macs: {}\n\n", plan.updates_preference_macs()));
+    add_yaml_variable(content, "brave_debloat_policies", &plan.policies_json()?);
+    content.push('\n');
+    content.push_str("# Preferences merged into each profile. The first entry whose profile is null or names the\n");
+    content.push_str("# profile directory or display name applies.\n");
+    add_yaml_variable(content, "brave_debloat_profile_preferences", &Value::from(profile_preferences));
+    content.push('\n');
+    add_yaml_variable(content, "brave_debloat_local_state", &nest_preference_values(&plan.local_state_changes()));
+    content.push('\n');
+    content.push_str("# Added to the launcher flags file (Linux only)\n");
+    add_yaml_variable(content, "brave_debloat_launch_flags", &Value::from(plan.launch_flags()));
+    Ok(())
+}
+
+fn add_vars(content: &mut String) {
+    content.push_str("---\n");
+    content.push_str("brave_debloat_channels:\n");
+    for (channel, version) in [("normal", BraveVersion::Normal), ("nightly", BraveVersion::Nightly)] {
+        content.push_str(&format!("  {}:\n", channel));
+        content.push_str(&format!("    linux_policy_file: {}\n", yaml_quote(get_linux_policy_path(&version))));
+        content.push_str(&format!("    linux_flags_file: {}\n", yaml_quote(get_linux_flags_file(&version))));
+        content.push_str("    linux_process: brave\n");
+        content.push_str(&format!("    macos_bundle_id: {}\n", yaml_quote(get_macos_bundle_id(&version))));
+        content.push_str(&format!("    macos_process: {}\n", yaml_quote(get_macos_app_name(&version))));
+        content.push_str(&format!("    data_dir: {}\n", yaml_quote(get_version_suffix(&version))));
+    }
+    content.push('\n');
+    content.push_str("brave_debloat_channel_paths: \"{{ brave_debloat_channels[brave_debloat_channel] }}\"\n\n");
+
+    // Paths of the policy file, and of the config directory relative to the home directory
+    content.push_str("brave_debloat_linux_packages:\n");
+    content.push_str("  native:\n");
+    content.push_str("    policy_file: \"{{ brave_debloat_channel_paths.linux_policy_file }}\"\n");
+    content.push_str("    config_dir: .config\n");
+    content.push_str("  snap:\n");
+    content.push_str(&format!("    policy_file: /var/snap/{}/current/{}\n", LINUX_SNAP_NAME, LINUX_SANDBOX_POLICY_FILE));
+    content.push_str(&format!("    config_dir: snap/{}/current/.config\n", LINUX_SNAP_NAME));
+    content.push_str("  flatpak:\n");
+    content.push_str(&format!(
+        "    policy_file: \"/var/lib/flatpak/extension/{}/{{{{ ansible_architecture }}}}/1/{}\"\n",
+        LINUX_FLATPAK_POLICY_EXTENSION, LINUX_SANDBOX_POLICY_FILE
+    ));
+    content.push_str(&format!("    config_dir: .var/app/{}/config\n", LINUX_FLATPAK_APP_ID));
+    content.push_str("brave_debloat_linux_paths: \"{{ brave_debloat_linux_packages[brave_debloat_linux_package] }}\"\n\n");
+
+    // A recursive combine merges dictionaries, so these are cleared before the preferences are merged
+    let replaced: Vec<(&str, &Value)> = REPLACED_PREFERENCES.iter().map(|path| (*path, &Value::Null)).collect();
+    content.push_str("# Dictionaries the preferences replace whole instead of merging into\n");
+    add_yaml_variable(content, "brave_debloat_replaced_preferences", &nest_preference_values(&replaced));
+    content.push('\n');
+    content.push_str("# Launch flags whose comma-separated values are merged into one line\n");
+    add_yaml_variable(content, "brave_debloat_list_switches", &Value::from(LIST_SWITCHES));
+    content.push('\n');
+    content.push_str("# Preferences whose MAC in protection.macs must match their value\n");
+    add_yaml_variable(content, "brave_debloat_protected_preferences", &Value::from(get_protected_preferences(MODIFIED_PREFERENCES)));
+}
+
+fn add_meta(content: &mut String, role_name: &str) {
+    content.push_str("---\n");
+    content.push_str("galaxy_info:\n");
+    content.push_str(&format!("  role_name: {}\n", role_name));
+    content.push_str("  author: brave-debloater\n");
+    content.push_str("  description: Applies Brave Browser policies, preferences and launch flags\n");
+    content.push_str("  license: MIT\n");
+    content.push_str("  min_ansible_version: \"2.14\"\n");
+    content.push_str("  platforms:\n");
+    content.push_str("    - name: GenericLinux\n");
+    content.push_str("      versions: [all]\n");
+    content.push_str("    - name: MacOSX\n");
+    content.push_str("      versions: [all]\n");
+    content.push_str("dependencies: []\n");
+}
+
+/// Writes the policies, then includes `user.yml` for each target user.
+fn add_main_tasks(content: &mut String) {
+    content.push_str("---\n");
+    content.push_str("- name: Check the Brave channel and package\n");
+    content.push_str("  ansible.builtin.assert:\n");
+    content.push_str("    that:\n");
+    content.push_str("      - ansible_system in [\"Linux\", \"Darwin\"]\n");
+    content.push_str("      - brave_debloat_channel in brave_debloat_channels\n");
+    content.push_str("      - brave_debloat_linux_package in brave_debloat_linux_packages\n");
+    content.push_str("      - brave_debloat_channel == \"normal\" or brave_debloat_linux_package == \"native\"\n");
+    content.push_str("    fail_msg: Unsupported system, channel or package. Snap and Flatpak only ship the normal channel.\n");
+    content.push_str("    quiet: true\n\n");
+    content.push_str("- name: Write the managed policy JSON (Linux)\n");
+    content.push_str("  when: ansible_system == \"Linux\" and brave_debloat_manage_policies\n");
+    content.push_str("  block:\n");
+    content.push_str("    - name: Create the policy directory\n");
+    content.push_str("      ansible.builtin.file:\n");
+    content.push_str("        path: \"{{ brave_debloat_linux_paths.policy_file | dirname }}\"\n");
+    content.push_str("        state: directory\n");
+    content.push_str("        mode: \"0755\"\n\n");
+    content.push_str("    - name: Write the policy JSON\n");
+    content.push_str("      ansible.builtin.copy:\n");
+    content.push_str("        content: \"{{ brave_debloat_policies | to_nice_json }}\\n\"\n");
+    content.push_str("        dest: \"{{ brave_debloat_linux_paths.policy_file }}\"\n");
+    content.push_str("        mode: \"0644\"\n");
+    content.push_str("      notify: Brave configuration changed\n\n");
+    content.push_str("- name: Write the managed policy plist (macOS)\n");
+    content.push_str("  when: ansible_system == \"Darwin\" and brave_debloat_manage_policies\n");
+    content.push_str("  block:\n");
+    content.push_str("    - name: Create the managed preferences directory\n");
+    content.push_str("      ansible.builtin.file:\n");
+    content.push_str("        path: /Library/Managed Preferences\n");
+    content.push_str("        state: directory\n");
+    content.push_str("        mode: \"0755\"\n\n");
+    content.push_str("    - name: Write the policy plist\n");
+    content.push_str("      ansible.builtin.template:\n");
+    content.push_str("        src: brave_policies.plist.j2\n");
+    content.push_str("        dest: \"/Library/Managed Preferences/{{ brave_debloat_channel_paths.macos_bundle_id }}.plist\"\n");
+    content.push_str("        mode: \"0644\"\n");
+    content.push_str("      notify: Brave configuration changed\n\n");
+    content.push_str("- name: Warn about protected preferences (macOS)\n");
+    content.push_str("  ansible.builtin.debug:\n");
+    content.push_str("    msg: \"Brave protects {{ brave_debloat_protected_preferences | join(', ') }} with a MAC keyed to the device ID and will revert changes to them\"\n");
+    content.push_str("  when: ansible_system == \"Darwin\" and brave_debloat_protected_preferences | length > 0\n\n");
+    content.push_str("- name: Configure the Brave profiles of each user\n");
+    content.push_str("  ansible.builtin.include_tasks: user.yml\n");
+    content.push_str("  loop: \"{{ brave_debloat_users }}\"\n");
+    content.push_str("  loop_control:\n");
+    content.push_str("    loop_var: brave_debloat_user\n");
+}
+
+/// Finds the user data directory and profiles of `brave_debloat_user`, then edits Local State and the launch flags.
+fn add_user_tasks(content: &mut String) {
+    content.push_str("---\n");
+    content.push_str("- name: Look up the home directory of {{ brave_debloat_user }} (Linux)\n");
+    content.push_str("  ansible.builtin.getent:\n");
+    content.push_str("    database: passwd\n");
+    content.push_str("    key: \"{{ brave_debloat_user }}\"\n");
+    content.push_str("  when: ansible_system == \"Linux\"\n\n");
+    content.push_str("- name: Look up the home directory of {{ brave_debloat_user }} (macOS)\n");
+    content.push_str("  ansible.builtin.command:\n");
+    content.push_str("    argv: [dscl, \".\", -read, \"/Users/{{ brave_debloat_user }}\", NFSHomeDirectory]\n");
+    content.push_str("  register: brave_debloat_dscl\n");
+    content.push_str("  changed_when: false\n");
+    content.push_str("  when: ansible_system == \"Darwin\"\n\n");
+    content.push_str("- name: Set the Brave config directory of {{ brave_debloat_user }}\n");
+    content.push_str("  ansible.builtin.set_fact:\n");
+    content.push_str("    brave_debloat_config_dir: >-\n");
+    content.push_str("      {{ getent_passwd[brave_debloat_user][4] ~ \"/\" ~ brave_debloat_linux_paths.config_dir\n");
+    content.push_str("         if ansible_system == \"Linux\"\n");
+    content.push_str("         else (brave_debloat_dscl.stdout.split(\": \", 1) | last) ~ \"/Library/Application Support\" }}\n\n");
+    content.push_str("- name: Set the Brave user data directory of {{ brave_debloat_user }}\n");
+    content.push_str("  ansible.builtin.set_fact:\n");
+    content.push_str("    brave_debloat_data_dir: \"{{ brave_debloat_config_dir }}/BraveSoftware/{{ brave_debloat_channel_paths.data_dir }}\"\n\n");
+    content.push_str("- name: Create the Brave user data directory\n");
+    content.push_str("  ansible.builtin.file:\n");
+    content.push_str("    path: \"{{ brave_debloat_data_dir }}\"\n");
+    content.push_str("    state: directory\n");
+    content.push_str("    owner: \"{{ brave_debloat_user }}\"\n");
+    content.push_str("    mode: \"0700\"\n\n");
+    content.push_str("- name: Read Local State\n");
+    content.push_str("  ansible.builtin.slurp:\n");
+    content.push_str("    src: \"{{ brave_debloat_data_dir }}/Local State\"\n");
+    content.push_str("  register: brave_debloat_local_state_file\n");
+    content.push_str("  failed_when: false\n\n");
+    content.push_str("- name: Parse Local State\n");
+    content.push_str("  ansible.builtin.set_fact:\n");
+    content.push_str("    brave_debloat_local_state_json: \"{{ brave_debloat_local_state_file.content | default('e30=') | b64decode | from_json }}\"\n\n");
+    content.push_str("- name: Find the profile directories\n");
+    content.push_str("  ansible.builtin.find:\n");
+    content.push_str("    paths: \"{{ brave_debloat_data_dir }}\"\n");
+    content.push_str("    file_type: directory\n");
+    content.push_str("    patterns: [\"Default\", \"Profile *\"]\n");
+    content.push_str("  register: brave_debloat_profile_dirs\n\n");
+    content.push_str("# Profiles known to Brave are listed in Local State, fall back to the profile directories\n");
+    content.push_str("- name: List the profiles\n");
+    content.push_str("  ansible.builtin.set_fact:\n");
+    content.push_str("    brave_debloat_profiles: >-\n");
+    content.push_str("      {{ (brave_debloat_local_state_json.profile.info_cache | default({}) | list)\n");
+    content.push_str("         or (brave_debloat_profile_dirs.files | map(attribute=\"path\") | map(\"basename\") | sort)\n");
+    content.push_str("         or [\"Default\"] }}\n\n");
+    content.push_str("- name: Configure each profile\n");
+    content.push_str("  ansible.builtin.include_tasks: profile.yml\n");
+    content.push_str("  loop: \"{{ brave_debloat_profiles }}\"\n");
+    content.push_str("  loop_control:\n");
+    content.push_str("    loop_var: brave_debloat_profile\n\n");
+    content.push_str("- name: Merge the changes into Local State\n");
+    content.push_str("  ansible.builtin.set_fact:\n");
+    content.push_str("    brave_debloat_local_state_updated: \"{{ brave_debloat_local_state_json | combine(brave_debloat_local_state, recursive=True) }}\"\n\n");
+    content.push_str("- name: Write Local State\n");
+    content.push_str("  ansible.builtin.copy:\n");
+    content.push_str("    content: \"{{ brave_debloat_local_state_updated | to_json(separators=[',', ':']) }}\"\n");
+    content.push_str("    dest: \"{{ brave_debloat_data_dir }}/Local State\"\n");
+    content.push_str("    owner: \"{{ brave_debloat_user }}\"\n");
+    content.push_str("    mode: \"0600\"\n");
+    content.push_str("    backup: true\n");
+    content.push_str("  when: brave_debloat_local_state_updated != brave_debloat_local_state_json\n");
+    content.push_str("  notify: Brave configuration changed\n\n");
+    content.push_str("- name: Read the launch flags file (Linux)\n");
+    content.push_str("  ansible.builtin.slurp:\n");
+    content.push_str("    src: \"{{ brave_debloat_config_dir }}/{{ brave_debloat_channel_paths.linux_flags_file }}\"\n");
+    content.push_str("  register: brave_debloat_flags_file\n");
+    content.push_str("  failed_when: false\n");
+    content.push_str("  when: ansible_system == \"Linux\"\n\n");
+    content.push_str("# Chromium only reads the last occurrence of a switch, so list switches get the values already in the file merged in\n");
+    content.push_str("- name: Add the launch flags (Linux)\n");
+    content.push_str("  ansible.builtin.lineinfile:\n");
+    content.push_str("    path: \"{{ brave_debloat_config_dir }}/{{ brave_debloat_channel_paths.linux_flags_file }}\"\n");
+    content.push_str("    regexp: \"{{ ('^' ~ (brave_debloat_list_switch | regex_escape) ~ '=') if brave_debloat_list_switch else omit }}\"\n");
+    content.push_str("    line: >-\n");
+    content.push_str("      {{ brave_debloat_list_switch ~ '=' ~ ((brave_debloat_flags_lines | select('match', brave_debloat_list_switch ~ '=')\n");
+    content.push_str("         | map('regex_replace', '^[^=]*=', '') | join(',')).split(',') + item.split('=', 1)[1].split(',')) | select | unique | join(',')\n");
+    content.push_str("         if brave_debloat_list_switch else item }}\n");
+    content.push_str("    create: true\n");
+    content.push_str("    owner: \"{{ brave_debloat_user }}\"\n");
+    content.push_str("    mode: \"0644\"\n");
+    content.push_str("  vars:\n");
+    content.push_str("    brave_debloat_list_switch: \"{{ item.split('=')[0] if '=' in item and item.split('=')[0] in brave_debloat_list_switches else '' }}\"\n");
+    content.push_str("    brave_debloat_flags_lines: \"{{ (brave_debloat_flags_file.content | default('') | b64decode).splitlines() }}\"\n");
+    content.push_str("  loop: \"{{ brave_debloat_launch_flags }}\"\n");
+    content.push_str("  when: ansible_system == \"Linux\"\n");
+    content.push_str("  notify: Brave configuration changed\n");
+}
+
+/// Merges the matching preference set into the Preferences of `brave_debloat_profile`.
+fn add_profile_tasks(content: &mut String) {
+    content.push_str("---\n");
+    content.push_str("# The first preference set for every profile (null) or naming this directory or display name applies\n");
+    content.push_str("- name: Pick the preferences for profile {{ brave_debloat_profile }}\n");
+    content.push_str("  ansible.builtin.set_fact:\n");
+    content.push_str("    brave_debloat_profile_matches: >-\n");
+    content.push_str("      {{ brave_debloat_profile_preferences | selectattr(\"profile\", \"in\", [none, brave_debloat_profile,\n");
+    content.push_str("         brave_debloat_local_state_json.profile.info_cache[brave_debloat_profile].name | default(none)]) | list }}\n\n");
+    content.push_str("- name: Configure profile {{ brave_debloat_profile }}\n");
+    content.push_str("  when: brave_debloat_profile_matches | length > 0\n");
+    content.push_str("  vars:\n");
+    content.push_str("    brave_debloat_prefs_path: \"{{ brave_debloat_data_dir }}/{{ brave_debloat_profile }}/Preferences\"\n");
+    content.push_str("  block:\n");
+    content.push_str("    - name: Create the profile directory\n");
+    content.push_str("      ansible.builtin.file:\n");
+    content.push_str("        path: \"{{ brave_debloat_prefs_path | dirname }}\"\n");
+    content.push_str("        state: directory\n");
+    content.push_str("        owner: \"{{ brave_debloat_user }}\"\n");
+    content.push_str("        mode: \"0700\"\n\n");
+    content.push_str("    - name: Read Preferences\n");
+    content.push_str("      ansible.builtin.slurp:\n");
+    content.push_str("        src: \"{{ brave_debloat_prefs_path }}\"\n");
+    content.push_str("      register: brave_debloat_prefs_file\n");
+    content.push_str("      failed_when: false\n\n");
+    content.push_str("    - name: Parse Preferences\n");
+    content.push_str("      ansible.builtin.set_fact:\n");
+    content.push_str("        brave_debloat_prefs_json: \"{{ brave_debloat_prefs_file.content | default('e30=') | b64decode | from_json }}\"\n\n");
+    content.push_str("    - name: Merge the preferences\n");
+    content.push_str("      ansible.builtin.set_fact:\n");
+    content.push_str("        brave_debloat_prefs_updated: >-\n");
+    content.push_str("          {{ brave_debloat_prefs_json | combine(brave_debloat_replaced_preferences, recursive=True)\n");
+    content.push_str("             | combine(brave_debloat_profile_matches[0].preferences, recursive=True) }}\n\n");
+    content.push_str("    - name: Write Preferences\n");
+    content.push_str("      ansible.builtin.copy:\n");
+    content.push_str("        content: \"{{ brave_debloat_prefs_updated | to_json(separators=[',', ':']) }}\"\n");
+    content.push_str("        dest: \"{{ brave_debloat_prefs_path }}\"\n");
+    content.push_str("        owner: \"{{ brave_debloat_user }}\"\n");
+    content.push_str("        mode: \"0600\"\n");
+    content.push_str("        backup: true\n");
+    content.push_str("      when: brave_debloat_prefs_updated != brave_debloat_prefs_json\n");
+    content.push_str("      register: brave_debloat_prefs_written\n");
+    content.push_str("      notify: Brave configuration changed\n\n");
+    content.push_str("    - name: Update the MACs of the protected preferences (Linux)\n");
+    content.push_str("      ansible.builtin.script:\n");
+    content.push_str("        cmd: update_preference_macs.py {{ brave_debloat_prefs_path | quote }} {{ brave_debloat_protected_preferences | map(\"quote\") | join(\" \") }}\n");
+    content.push_str("        executable: python3\n");
+    content.push_str("      register: brave_debloat_macs\n");
+    content.push_str("      changed_when: \"'changed' in brave_debloat_macs.stdout\"\n");
+    content.push_str("      when:\n");
+    content.push_str("        - brave_debloat_prefs_written is changed\n");
+    content.push_str("        - ansible_system == \"Linux\"\n");
+    content.push_str("        - brave_debloat_update_preference_macs\n");
+    content.push_str("        - brave_debloat_protected_preferences | length > 0\n");
+}
+
+/// Asks to restart Brave when it is running while the configuration changed.
+fn add_handlers(content: &mut String) {
+    content.push_str("---\n");
+    content.push_str("- name: Check whether Brave is running\n");
+    content.push_str("  ansible.builtin.command:\n");
+    content.push_str("    argv:\n");
+    content.push_str("      - pgrep\n");
+    content.push_str("      - -x\n");
+    content.push_str("      - \"{{ brave_debloat_channel_paths.linux_process if ansible_system == 'Linux' else brave_debloat_channel_paths.macos_process }}\"\n");
+    content.push_str("  register: brave_debloat_running\n");
+    content.push_str("  changed_when: false\n");
+    content.push_str("  failed_when: false\n");
+    content.push_str("  listen: Brave configuration changed\n\n");
+    content.push_str("- name: Ask to restart Brave\n");
+    content.push_str("  ansible.builtin.debug:\n");
+    content.push_str("    msg: >-\n");
+    content.push_str("      Brave is running on {{ inventory_hostname }}. Restart it to load the new configuration.\n");
+    content.push_str("      If it was open while its preferences were edited, it may overwrite them when it exits.\n");
+    content.push_str("  when: brave_debloat_running.rc == 0\n");
+    content.push_str("  listen: Brave configuration changed\n");
+}
+
+/// Renders `brave_debloat_policies` as a managed preferences plist, the same way as the macOS scripts.
+fn add_plist_template(content: &mut String) {
+    content.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    content.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+    content.push_str("<plist version=\"1.0\">\n");
+    content.push_str("<dict>\n");
+    content.push_str("{% for key, value in brave_debloat_policies | dictsort %}\n");
+    content.push_str("    <key>{{ key | e }}</key>\n");
+    content.push_str("{% if value is sameas true %}\n");
+    content.push_str("    <true/>\n");
+    content.push_str("{% elif value is sameas false %}\n");
+    content.push_str("    <false/>\n");
+    content.push_str("{% elif value is number %}\n");
+    content.push_str("    <integer>{{ value }}</integer>\n");
+    content.push_str("{% elif value is string %}\n");
+    content.push_str("    <string>{{ value | e }}</string>\n");
+    content.push_str("{% else %}\n");
+    content.push_str("    <array>\n");
+    content.push_str("{% for item in value %}\n");
+    content.push_str("        <string>{{ item | e }}</string>\n");
+    content.push_str("{% endfor %}\n");
+    content.push_str("    </array>\n");
+    content.push_str("{% endif %}\n");
+    content.push_str("{% endfor %}\n");
+    content.push_str("</dict>\n");
+    content.push_str("</plist>\n");
+}
+
+/// Recomputes `protection.macs` like the Linux script, in Python since the role cannot rely on jq and openssl.
+fn add_mac_script(content: &mut String) {
+    content.push_str("#!/usr/bin/env python3\n");
+    content.push_str("\"\"\"Recomputes the protection.macs entries of protected preferences after their values changed.\n\n");
+    content.push_str("Usage: update_preference_macs.py <Preferences file> <dotted path>...\n\n");
+    content.push_str("Each MAC is HMAC-SHA256(seed, device ID + path + JSON value). The device ID is empty on Linux.\n");
+    content.push_str("Prints \"changed\" when Preferences or Secure Preferences was rewritten.\n");
+    content.push_str("\"\"\"\n");
+    content.push_str("import copy\n");
+    content.push_str("import hashlib\n");
+    content.push_str("import hmac\n");
+    content.push_str("import json\n");
+    content.push_str("import os\n");
+    content.push_str("import sys\n");
+    content.push_str("import tempfile\n\n");
+    content.push_str(&format!("SEED = bytes.fromhex(\"{}\")\n\n", PREF_HASH_SEED));
+    content.push_str("def remove_empty(value):\n");
+    content.push_str("    if isinstance(value, dict):\n");
+    content.push_str("        cleaned = {key: remove_empty(child) for key, child in value.items()}\n");
+    content.push_str("        return {key: child for key, child in cleaned.items() if child != {} and child != []}\n");
+    content.push_str("    if isinstance(value, list):\n");
+    content.push_str("        return [remove_empty(item) for item in value]\n");
+    content.push_str("    return value\n\n");
+    content.push_str("def pref_hash_json(value):\n");
+    content.push_str("    text = json.dumps(remove_empty(value), sort_keys=True, separators=(\",\", \":\"), ensure_ascii=False)\n");
+    content.push_str("    return text.replace(\"<\", \"\\\\u003C\")\n\n");
+    content.push_str("def calculate_mac(path, value):\n");
+    content.push_str("    message = path + (\"\" if value is None else pref_hash_json(value))\n");
+    content.push_str("    return hmac.new(SEED, message.encode(\"utf-8\"), hashlib.sha256).hexdigest().upper()\n\n");
+    content.push_str("def get_path(value, path):\n");
+    content.push_str("    for key in path.split(\".\"):\n");
+    content.push_str("        if not isinstance(value, dict) or key not in value:\n");
+    content.push_str("            return None\n");
+    content.push_str("        value = value[key]\n");
+    content.push_str("    return value\n\n");
+    content.push_str("def set_path(value, path, new_value):\n");
+    content.push_str("    keys = path.split(\".\")\n");
+    content.push_str("    for key in keys[:-1]:\n");
+    content.push_str("        if not isinstance(value.get(key), dict):\n");
+    content.push_str("            value[key] = {}\n");
+    content.push_str("        value = value[key]\n");
+    content.push_str("    value[keys[-1]] = new_value\n\n");
+    content.push_str("def write_json(path, value):\n");
+    content.push_str("    status = os.stat(path)\n");
+    content.push_str("    fd, tmp_path = tempfile.mkstemp(dir=os.path.dirname(path))\n");
+    content.push_str("    with os.fdopen(fd, \"w\", encoding=\"utf-8\") as f:\n");
+    content.push_str("        json.dump(value, f, separators=(\",\", \":\"), ensure_ascii=False)\n");
+    content.push_str("    os.chmod(tmp_path, status.st_mode & 0o7777)\n");
+    content.push_str("    if os.geteuid() == 0:\n");
+    content.push_str("        os.chown(tmp_path, status.st_uid, status.st_gid)\n");
+    content.push_str("    os.replace(tmp_path, path)\n\n");
+    content.push_str("def main():\n");
+    content.push_str("    prefs_path, paths = sys.argv[1], sys.argv[2:]\n");
+    content.push_str("    with open(prefs_path, encoding=\"utf-8\") as f:\n");
+    content.push_str("        prefs = json.load(f)\n\n");
+    content.push_str("    changed = False\n");
+    content.push_str("    for macs_path in (prefs_path, os.path.join(os.path.dirname(prefs_path), \"Secure Preferences\")):\n");
+    content.push_str("        try:\n");
+    content.push_str("            with open(macs_path, encoding=\"utf-8\") as f:\n");
+    content.push_str("                macs_json = json.load(f)\n");
+    content.push_str("        except (OSError, ValueError):\n");
+    content.push_str("            continue\n");
+    content.push_str("        if not isinstance(get_path(macs_json, \"protection.macs\"), dict):\n");
+    content.push_str("            continue\n\n");
+    content.push_str("        updated = copy.deepcopy(macs_json)\n");
+    content.push_str("        for path in paths:\n");
+    content.push_str("            set_path(updated[\"protection\"][\"macs\"], path, calculate_mac(path, get_path(prefs, path)))\n");
+    content.push_str("        if \"super_mac\" in updated[\"protection\"]:\n");
+    content.push_str("            updated[\"protection\"][\"super_mac\"] = calculate_mac(\"\", updated[\"protection\"][\"macs\"])\n");
+    content.push_str("        if updated != macs_json:\n");
+    content.push_str("            write_json(macs_path, updated)\n");
+    content.push_str("            changed = True\n");
+    content.push_str("    print(\"changed\" if changed else \"ok\")\n\n");
+    content.push_str("if __name__ == \"__main__\":\n");
+    content.push_str("    main()\n");
+}
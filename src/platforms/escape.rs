@@ -0,0 +1,128 @@
+/// Quotes a value as a single word for bash and zsh.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Escapes a value for XML text and attribute values, e.g. plist strings.
+pub fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Quotes a value as a `.reg` file string. Line breaks cannot be quoted and must be written as `hex(1):` data instead.
+pub fn reg_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quotes a value as a Nix double-quoted string, escaping `${` so nothing is interpolated.
+pub fn nix_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+/// Quotes a value as a YAML double-quoted string. JSON escapes are valid in YAML, except that YAML also
+/// needs the C1 controls, the Unicode line separators and the byte order mark escaped.
+pub fn yaml_quote(value: &str) -> String {
+    let mut quoted = String::new();
+    for c in serde_json::to_string(value).unwrap_or_default().chars() {
+        if matches!(c, '\u{7f}'..='\u{9f}' | '\u{2028}' | '\u{2029}' | '\u{feff}') {
+            quoted.push_str(&format!("\\u{:04x}", c as u32));
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted
+}
+
+/// Quotes a value as a PowerShell single-quoted string.
+pub fn powershell_quote(value: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in value.chars() {
+        // PowerShell also ends single-quoted strings at typographic quotes
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Escapes a value for the Windows argument parser, to be placed between double quotes: `\"` for quotes,
+/// doubling the backslashes in front of them. Line breaks cannot be passed and become spaces.
+pub fn escape_argument(value: &str) -> String {
+    let mut argument = String::new();
+    let mut backslashes = 0;
+    for c in value.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                argument.push_str(&"\\".repeat(backslashes * 2 + 1));
+                argument.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                argument.push_str(&"\\".repeat(backslashes));
+                argument.push(if c == '\r' || c == '\n' { ' ' } else { c });
+                backslashes = 0;
+            }
+        }
+    }
+    argument.push_str(&"\\".repeat(backslashes * 2));
+    argument
+}
+
+/// Quotes a value as the last argument of a command in a batch file that runs with delayed expansion.
+///
+/// cmd has no `\"` escape, so after a value with an odd number of quotes it stays inside quotes until the
+/// end of the line. Arguments followed by others are stored with [`batch_set`] and read as `"!NAME!"`.
+pub fn batch_quote(value: &str) -> String {
+    escape_batch_line(&format!("\"{}\"", escape_argument(value)), false)
+}
+
+/// Builds a `set "NAME=value"` line that stores the value literally, for reading back with `!NAME!`.
+pub fn batch_set(name: &str, value: &str) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+    format!("set \"{}={}\"", name, escape_batch_line(&value, true))
+}
+
+/// Escapes text for a batch line with delayed expansion enabled, tracking where cmd sees quotes.
+///
+/// cmd removes one level of `^` outside quotes when parsing the line, and when the line contains `!`
+/// removes another level everywhere during delayed expansion.
+fn escape_batch_line(text: &str, quoted: bool) -> String {
+    let delayed = text.contains('!');
+    let mut in_quotes = quoted;
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                escaped.push(c);
+            }
+            '%' => escaped.push_str("%%"),
+            '!' => escaped.push_str(if in_quotes { "^!" } else { "^^!" }),
+            '^' => escaped.push_str(match (delayed, in_quotes) {
+                (false, true) => "^",
+                (false, false) | (true, true) => "^^",
+                (true, false) => "^^^^",
+            }),
+            '&' | '|' | '<' | '>' | '(' | ')' if !in_quotes => {
+                escaped.push('^');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,24 +1,30 @@
 use std::fs;
 use std::path::Path;
 
-use crate::cli::BraveVersion;
-use crate::config::{Config, Extension, ConfigValue};
+use serde_json::Value;
+
+use crate::cli::{BraveVersion, Platform};
 use crate::error::DebloaterError;
-use crate::platforms::{PlatformGenerator, get_linux_policy_path, get_version_suffix};
-use crate::preferences::{PreferencesInputConfig, get_default_search_provider, get_default_dashboard_config, get_default_experimental_features};
+use crate::plan::{Plan, ProfileChanges, UserFile};
+use crate::platforms::escape::{nix_quote, shell_quote};
+use crate::platforms::nix::{get_forced_extensions, nix_value};
+use crate::platforms::package::{PackageFile, PackageInfo, PackageOptions, build_deb, build_rpm, get_deb_filename, get_rpm_filename, validate_package_info};
+use crate::platforms::{
+    BACKUP_DIR_NAME, BACKUP_TIMESTAMP_FORMAT, LATEST_SNAPSHOT, LINUX_FLATPAK_APP_ID, LINUX_FLATPAK_POLICY_EXTENSION, LINUX_SANDBOX_POLICY_FILE,
+    LINUX_SNAP_NAME, PlatformGenerator, get_linux_flags_file, get_linux_native_binary, get_linux_native_install_dir,
+    get_initial_preferences_path, get_linux_policy_path, get_version_suffix, has_linux_sandboxed_packages,
+};
+use crate::preferences::{LIST_SWITCHES, MODIFIED_PREFERENCES, PREF_HASH_SEED, get_preference_values, get_protected_preferences};
 
 pub struct LinuxGenerator;
 
 impl PlatformGenerator for LinuxGenerator {
-    fn generate_unified_script(&self, config: &Config, extensions: &[Extension], version: &BraveVersion, output_dir: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
+    fn generate_unified_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
         let filename = match version {
             BraveVersion::Normal => "brave_debloat_linux.sh",
             BraveVersion::Nightly => "brave_nightly_debloat_linux.sh",
         };
         
-        let version_suffix = get_version_suffix(version);
-        let policy_path = get_linux_policy_path(version);
-        
         let mut content = String::new();
         content.push_str("#!/bin/bash\n");
         content.push_str("# Unified Brave Browser Debloater Script for Linux\n");
@@ -26,14 +32,19 @@ impl PlatformGenerator for LinuxGenerator {
         content.push_str("# Run with sudo for system-wide changes\n\n");
         
         add_color_definitions(&mut content);
+        add_script_options(&mut content, false);
         add_header(&mut content);
         add_sudo_check(&mut content);
-        add_brave_process_check(&mut content);
-        add_system_policies(&mut content, config, extensions, policy_path)?;
-        add_user_preferences(&mut content, version_suffix, preferences_config)?;
+        if plan.checks_process() {
+            add_brave_process_check(&mut content);
+        }
+        add_jq_installation_check(&mut content);
+        add_change_preview(&mut content);
+        add_installation_detection(&mut content, version);
+        add_system_policies(&mut content, plan)?;
+        add_user_preferences(&mut content, plan, version)?;
         
-        content.push_str("echo -e \"${GREEN}Configuration complete!${NC}\"\n");
-        content.push_str("echo -e \"${GREEN}Please restart Brave browser for changes to take effect.${NC}\"\n");
+        add_completion_message(&mut content, "Configuration complete!");
         
         let output_path = Path::new(output_dir).join(filename);
         fs::write(&output_path, content)?;
@@ -49,6 +60,174 @@ impl PlatformGenerator for LinuxGenerator {
         
         Ok(())
     }
+
+    fn generate_revert_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let filename = match version {
+            BraveVersion::Normal => "brave_revert_linux.sh",
+            BraveVersion::Nightly => "brave_nightly_revert_linux.sh",
+        };
+        
+        let mut content = String::new();
+        content.push_str("#!/bin/bash\n");
+        content.push_str("# Brave Browser Debloater Revert Script for Linux\n");
+        content.push_str("# This script removes the policies and restores the preferences changed by the debloat script\n");
+        content.push_str("# Run with sudo to remove system policies\n\n");
+        
+        add_color_definitions(&mut content);
+        content.push_str("echo -e \"${GREEN}Brave Browser Debloater Revert for Linux${NC}\"\n");
+        content.push_str("echo -e \"${GREEN}==========================================${NC}\"\n");
+        content.push_str("echo\n\n");
+        add_script_options(&mut content, true);
+        add_sudo_check(&mut content);
+        if plan.checks_process() {
+            add_brave_process_check(&mut content);
+        }
+        add_installation_detection(&mut content, version);
+        add_restore_check(&mut content);
+        
+        content.push_str("for INSTALL in \"${!INSTALL_NAMES[@]}\"; do\n");
+        content.push_str("    POLICY_PATH=\"${INSTALL_POLICIES[$INSTALL]}\"\n");
+        content.push_str("    if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+        content.push_str("        [ -f \"$POLICY_PATH\" ] && echo \"Would remove policy file: $POLICY_PATH\"\n");
+        content.push_str("    elif [ \"$SKIP_POLICIES\" -eq 0 ] || [ \"${POLICY_PATH#\"$HOME\"/}\" != \"$POLICY_PATH\" ]; then\n");
+        content.push_str("        echo -e \"${GREEN}Removing ${INSTALL_NAMES[$INSTALL]} policies...${NC}\"\n");
+        content.push_str("        rm -f \"$POLICY_PATH\"\n");
+        content.push_str("        echo -e \"${GREEN}Removed $POLICY_PATH${NC}\"\n");
+        content.push_str("    else\n");
+        content.push_str("        echo -e \"${YELLOW}Skipping ${INSTALL_NAMES[$INSTALL]} policies (not running as sudo)${NC}\"\n");
+        content.push_str("    fi\n");
+        content.push_str("done\n");
+        content.push_str("echo\n\n");
+        
+        content.push_str("restore_installation() {\n");
+        add_snapshot_selection(&mut content);
+        add_snapshot_restore(&mut content);
+        add_launch_flags_removal(&mut content, get_linux_flags_file(version), &plan.launch_flags());
+        content.push_str("}\n\n");
+        add_installation_loop(&mut content, "Restoring user preferences in", "restore_installation");
+        
+        add_completion_message(&mut content, "Revert complete!");
+        
+        let output_path = Path::new(output_dir).join(filename);
+        fs::write(&output_path, content)?;
+        
+        // Make the script executable
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(&output_path)?.permissions();
+            perms.set_mode(0o755);
+            fs::set_permissions(&output_path, perms)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl LinuxGenerator {
+    /// Writes the policies, forced extensions and launch flags as a Nix file with a NixOS and a Home Manager module.
+    pub fn generate_nix_module(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let filename = match version {
+            BraveVersion::Normal => "brave_debloat.nix",
+            BraveVersion::Nightly => "brave_nightly_debloat.nix",
+        };
+        let policy_path = get_linux_policy_path(version).trim_start_matches("/etc/");
+
+        let mut content = String::new();
+        content.push_str("# Brave Browser debloat configuration, generated by brave-debloater\n");
+        content.push_str("#\n");
+        content.push_str("# NixOS (configuration.nix), writes the managed policy file:\n");
+        content.push_str(&format!("#   imports = [ (import ./{}).nixosModule ];\n", filename));
+        content.push_str("#\n");
+        content.push_str("# Home Manager (home.nix), installs Brave with the extensions and launch flags:\n");
+        content.push_str(&format!("#   imports = [ (import ./{}).homeManagerModule ];\n", filename));
+        content.push_str("#\n");
+        content.push_str("# The same values work for Chromium with Home Manager:\n");
+        content.push_str(&format!(
+            "#   programs.chromium = {{ enable = true; inherit (import ./{}) extensions commandLineArgs; }};\n",
+            filename
+        ));
+        content.push_str("let\n");
+        content.push_str(&format!("  policies = {};\n\n", nix_value(&plan.policies_json()?, 1)));
+
+        let extensions = get_forced_extensions(plan);
+        if extensions.is_empty() {
+            content.push_str("  extensions = [ ];\n\n");
+        } else {
+            content.push_str("  extensions = [\n");
+            for (id, update_url) in extensions {
+                content.push_str(&format!("    {{ id = {}; updateUrl = {}; }}\n", nix_quote(id), nix_quote(update_url)));
+            }
+            content.push_str("  ];\n\n");
+        }
+        content.push_str(&format!("  commandLineArgs = {};\n", nix_value(&Value::from(plan.launch_flags()), 1)));
+        content.push_str("in\n");
+        content.push_str("{\n");
+        content.push_str("  inherit policies extensions commandLineArgs;\n\n");
+        content.push_str("  nixosModule = {\n");
+        content.push_str(&format!("    environment.etc.{}.text = builtins.toJSON policies;\n", nix_quote(policy_path)));
+        content.push_str("  };\n\n");
+        content.push_str("  homeManagerModule = {\n");
+        content.push_str("    programs.brave = {\n");
+        content.push_str("      enable = true;\n");
+        content.push_str("      inherit extensions commandLineArgs;\n");
+        content.push_str("    };\n");
+        content.push_str("  };\n");
+        content.push_str("}\n");
+
+        fs::write(Path::new(output_dir).join(filename), content)?;
+        Ok(())
+    }
+
+    /// Writes `.deb` and/or `.rpm` packages installing the native policy file, and optionally `initial_preferences`.
+    pub fn generate_packages(&self, plan: &Plan, version: &BraveVersion, output_dir: &str, options: &PackageOptions, initial_preferences: bool) -> Result<(), DebloaterError> {
+        let other_version = match version {
+            BraveVersion::Normal => BraveVersion::Nightly,
+            BraveVersion::Nightly => BraveVersion::Normal,
+        };
+        // Files are dated SOURCE_DATE_EPOCH when set, so rebuilding the same version gives the same packages
+        let mtime = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.parse().ok())
+            .unwrap_or_else(|| chrono::Utc::now().timestamp().max(0) as u64);
+        let info = PackageInfo {
+            name: get_linux_package_name(version),
+            version: options.version.clone(),
+            maintainer: options.maintainer.clone(),
+            conflicts: get_linux_package_name(&other_version),
+            enhances: get_linux_native_binary(version).to_string(),
+            summary: format!("Debloat policies for {}", get_linux_native_binary(version)),
+            description: "Managed policies generated by brave-debloater.".to_string(),
+            mtime,
+        };
+        validate_package_info(&info)?;
+
+        let mut files = vec![PackageFile {
+            path: get_linux_policy_path(version).to_string(),
+            mode: 0o644,
+            content: format!("{}\n", serde_json::to_string_pretty(&plan.policies_json()?)?).into_bytes(),
+        }];
+        if initial_preferences {
+            files.push(PackageFile {
+                path: get_initial_preferences_path(&Platform::Linux, version),
+                mode: 0o644,
+                content: format!("{}\n", serde_json::to_string_pretty(&plan.initial_preferences_json())?).into_bytes(),
+            });
+        }
+
+        if options.deb {
+            fs::write(Path::new(output_dir).join(get_deb_filename(&info)), build_deb(&info, &files))?;
+        }
+        if options.rpm {
+            fs::write(Path::new(output_dir).join(get_rpm_filename(&info)), build_rpm(&info, &files))?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of the package holding the policies, e.g. `brave-browser-debloat`.
+pub fn get_linux_package_name(version: &BraveVersion) -> String {
+    format!("{}-debloat", get_linux_native_binary(version))
 }
 
 fn add_color_definitions(content: &mut String) {
@@ -67,8 +246,10 @@ fn add_header(content: &mut String) {
 
 fn add_sudo_check(content: &mut String) {
     content.push_str("if [ \"$EUID\" -ne 0 ]; then\n");
-    content.push_str("    echo -e \"${YELLOW}Note: Running without sudo. System policies will be skipped.${NC}\"\n");
-    content.push_str("    echo -e \"${YELLOW}Run with sudo for complete configuration.${NC}\"\n");
+    content.push_str("    if [ \"$DRY_RUN\" -eq 0 ]; then\n");
+    content.push_str("        echo -e \"${YELLOW}Note: Running without sudo. System policies will be skipped.${NC}\"\n");
+    content.push_str("        echo -e \"${YELLOW}Run with sudo for complete configuration.${NC}\"\n");
+    content.push_str("    fi\n");
     content.push_str("    SKIP_POLICIES=1\n");
     content.push_str("else\n");
     content.push_str("    SKIP_POLICIES=0\n");
@@ -77,84 +258,372 @@ fn add_sudo_check(content: &mut String) {
 }
 
 fn add_brave_process_check(content: &mut String) {
+    // Match the process name, as the command line of this script contains "brave" as well
     content.push_str("echo \"Checking if Brave is running...\"\n");
-    content.push_str("if pgrep -f brave > /dev/null; then\n");
+    content.push_str("if [ \"$DRY_RUN\" -eq 0 ] && pgrep -x brave > /dev/null; then\n");
     content.push_str("    echo -e \"${YELLOW}WARNING: Brave browser is running!${NC}\"\n");
     content.push_str("    echo \"Please close Brave browser before running this script.\"\n");
     content.push_str("    echo \"Press Enter to continue anyway, or Ctrl+C to exit.\"\n");
     content.push_str("    read\n");
-    content.push_str("    pkill -f brave 2>/dev/null\n");
+    content.push_str("    pkill -x brave 2>/dev/null\n");
     content.push_str("    sleep 2\n");
     content.push_str("fi\n");
     content.push_str("echo\n\n");
 }
 
-fn add_system_policies(content: &mut String, config: &Config, extensions: &[Extension], policy_path: &str) -> Result<(), DebloaterError> {
-    content.push_str("if [ \"$SKIP_POLICIES\" -eq 0 ]; then\n");
-    content.push_str("    echo -e \"${GREEN}Applying system policies...${NC}\"\n");
-    content.push_str(&format!("    mkdir -p \"$(dirname '{}')\"\n", policy_path));
+fn add_system_policies(content: &mut String, plan: &Plan) -> Result<(), DebloaterError> {
+    let policies = plan.policies_json()?;
+
+    // Policies under $HOME (a user Flatpak) are written without sudo too
+    content.push_str("for INSTALL in \"${!INSTALL_NAMES[@]}\"; do\n");
+    content.push_str("    POLICY_PATH=\"${INSTALL_POLICIES[$INSTALL]}\"\n");
+    content.push_str("    if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("        echo \"Policy file: $POLICY_PATH\"\n");
+    for (key, value) in get_preference_values(&policies) {
+        content.push_str(&format!("        show_json_change \"$POLICY_PATH\" {} {}\n", shell_quote(&key), shell_quote(&value.to_string())));
+    }
+    content.push_str("    elif [ \"$SKIP_POLICIES\" -eq 0 ] || [ \"${POLICY_PATH#\"$HOME\"/}\" != \"$POLICY_PATH\" ]; then\n");
+    content.push_str("        echo -e \"${GREEN}Applying ${INSTALL_NAMES[$INSTALL]} policies...${NC}\"\n");
+    content.push_str("        mkdir -p \"$(dirname \"$POLICY_PATH\")\"\n");
     
     // Create the JSON policy file
-    content.push_str(&format!("    cat << 'EOF' > '{}'\n", policy_path));
-    
-    // Generate JSON content
-    let mut final_config = config.clone();
-    if !extensions.is_empty() {
-        let extension_ids: Vec<String> = extensions.iter().map(|e| e.id.clone()).collect();
-        final_config.insert("ExtensionInstallForcelist".to_string(), ConfigValue::StringArray(extension_ids));
-    }
-    let config_json = serde_json::to_string_pretty(&final_config)?;
-    content.push_str(&config_json);
+    content.push_str("        cat << 'EOF' > \"$POLICY_PATH\"\n");
+    content.push_str(&serde_json::to_string_pretty(&policies)?);
     content.push_str("\nEOF\n");
     
-    content.push_str(&format!("    chmod 644 '{}'\n", policy_path));
-    content.push_str("    echo -e \"${GREEN}System policies applied successfully!${NC}\"\n");
-    content.push_str("else\n");
-    content.push_str("    echo -e \"${YELLOW}Skipping system policies (not running as sudo)${NC}\"\n");
-    content.push_str("fi\n");
+    content.push_str("        chmod 644 \"$POLICY_PATH\"\n");
+    content.push_str("        echo -e \"${GREEN}Policies written to $POLICY_PATH${NC}\"\n");
+    content.push_str("    else\n");
+    content.push_str("        echo -e \"${YELLOW}Skipping ${INSTALL_NAMES[$INSTALL]} policies (not running as sudo)${NC}\"\n");
+    content.push_str("    fi\n");
+    content.push_str("done\n");
     content.push_str("echo\n\n");
     
     Ok(())
 }
 
-fn add_user_preferences(content: &mut String, version_suffix: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
-    let search_provider = get_default_search_provider(preferences_config);
-    let dashboard_config = get_default_dashboard_config(preferences_config);
-    let experimental_features = get_default_experimental_features(preferences_config);
+/// Lists every native, Snap and Flatpak install in parallel `INSTALL_*` arrays, falling back to the
+/// native paths when none is found. Each install has its own policy file and user data directory.
+fn add_installation_detection(content: &mut String, version: &BraveVersion) {
+    let version_suffix = get_version_suffix(version);
+    let native = format!(
+        "add_installation native '{}' \"$HOME/.config\" \"${{XDG_CONFIG_HOME:-$HOME/.config}}\"\n",
+        get_linux_policy_path(version)
+    );
+
+    content.push_str("INSTALL_NAMES=()\n");
+    content.push_str("INSTALL_POLICIES=()\n");
+    content.push_str("INSTALL_DATA=()\n");
+    content.push_str("INSTALL_CONFIGS=()\n");
+    content.push_str("add_installation() {\n");
+    content.push_str("    INSTALL_NAMES+=(\"$1\")\n");
+    content.push_str("    INSTALL_POLICIES+=(\"$2\")\n");
+    content.push_str(&format!("    INSTALL_DATA+=(\"$3/BraveSoftware/{}\")\n", version_suffix));
+    content.push_str("    INSTALL_CONFIGS+=(\"$4\")\n");
+    content.push_str("}\n\n");
+
+    content.push_str(&format!(
+        "if command -v {} &> /dev/null || [ -d '{}' ]; then\n",
+        get_linux_native_binary(version),
+        get_linux_native_install_dir(version)
+    ));
+    content.push_str(&format!("    {}", native));
+    content.push_str("fi\n");
+    if has_linux_sandboxed_packages(version) {
+        let snap_config = format!("$HOME/snap/{}/current/.config", LINUX_SNAP_NAME);
+        content.push_str(&format!("if [ -d '/snap/{}' ]; then\n", LINUX_SNAP_NAME));
+        content.push_str(&format!(
+            "    add_installation Snap '/var/snap/{}/current/{}' \"{}\" \"{}\"\n",
+            LINUX_SNAP_NAME, LINUX_SANDBOX_POLICY_FILE, snap_config, snap_config
+        ));
+        content.push_str("fi\n");
+
+        // The system and user Flatpak share one data directory but read their policies from separate extensions
+        let flatpak_config = format!("$HOME/.var/app/{}/config", LINUX_FLATPAK_APP_ID);
+        content.push_str("FLATPAK_ARCH=$(flatpak --default-arch 2>/dev/null || uname -m)\n");
+        for (name, root) in [("system Flatpak", "/var/lib/flatpak"), ("user Flatpak", "$HOME/.local/share/flatpak")] {
+            content.push_str(&format!("if [ -d \"{}/app/{}\" ]; then\n", root, LINUX_FLATPAK_APP_ID));
+            content.push_str(&format!(
+                "    add_installation '{}' \"{}/extension/{}/$FLATPAK_ARCH/1/{}\" \"{}\" \"{}\"\n",
+                name, root, LINUX_FLATPAK_POLICY_EXTENSION, LINUX_SANDBOX_POLICY_FILE, flatpak_config, flatpak_config
+            ));
+            content.push_str("fi\n");
+        }
+    }
+    content.push_str("if [ ${#INSTALL_NAMES[@]} -eq 0 ]; then\n");
+    content.push_str(&format!("    {}", native));
+    content.push_str("fi\n");
+    content.push_str("for INSTALL in \"${!INSTALL_NAMES[@]}\"; do\n");
+    content.push_str("    echo \"Detected ${INSTALL_NAMES[$INSTALL]} Brave installation\"\n");
+    content.push_str("done\n");
+    content.push_str("echo\n\n");
+}
+
+/// Calls `function` once per user data directory with `BRAVE_DATA` and `BRAVE_CONFIG` set.
+fn add_installation_loop(content: &mut String, message: &str, function: &str) {
+    content.push_str("CONFIGURED_DATA=()\n");
+    content.push_str("for INSTALL in \"${!INSTALL_NAMES[@]}\"; do\n");
+    content.push_str("    BRAVE_DATA=\"${INSTALL_DATA[$INSTALL]}\"\n");
+    content.push_str("    BRAVE_CONFIG=\"${INSTALL_CONFIGS[$INSTALL]}\"\n");
+    content.push_str("    for CONFIGURED in \"${CONFIGURED_DATA[@]}\"; do\n");
+    content.push_str("        [ \"$CONFIGURED\" = \"$BRAVE_DATA\" ] && continue 2\n");
+    content.push_str("    done\n");
+    content.push_str("    CONFIGURED_DATA+=(\"$BRAVE_DATA\")\n");
+    content.push_str(&format!("    echo -e \"${{GREEN}}{} $BRAVE_DATA...${{NC}}\"\n", message));
+    content.push_str(&format!("    {}\n", function));
+    content.push_str("done\n\n");
+}
 
-    content.push_str("echo -e \"${GREEN}Modifying user preferences...${NC}\"\n");
+fn add_user_preferences(content: &mut String, plan: &Plan, version: &BraveVersion) -> Result<(), DebloaterError> {
+    let profile_changes = plan.profile_changes();
+
+    add_backup_snapshot(content, plan.backup_retention());
+    add_json_writer(content);
+    if plan.updates_preference_macs() {
+        add_preference_macs_update(content, plan.backs_up(UserFile::SecurePreferences));
+    }
+    for (i, changes) in profile_changes.iter().enumerate() {
+        add_preferences_modification(content, i, changes, plan)?;
+    }
+
+    content.push_str("configure_installation() {\n");
+    content.push_str("LOCAL_STATE=\"$BRAVE_DATA/Local State\"\n");
+    content.push_str(&format!("BACKUP_ROOT=\"$BRAVE_DATA/{}\"\n", BACKUP_DIR_NAME));
+    content.push_str("BACKUP_DIR=\"$BACKUP_ROOT/$BACKUP_TIMESTAMP\"\n\n");
     
-    // Check for Flatpak installation
-    content.push_str("if command -v flatpak &> /dev/null && flatpak list | grep -q com.brave.Browser; then\n");
-    content.push_str(&format!("    BRAVE_DATA=\"$HOME/.var/app/com.brave.Browser/config/BraveSoftware/{}\"\n", version_suffix));
-    content.push_str("    echo \"Detected Flatpak Brave installation\"\n");
-    content.push_str("else\n");
-    content.push_str(&format!("    BRAVE_DATA=\"$HOME/.config/BraveSoftware/{}\"\n", version_suffix));
-    content.push_str("    echo \"Detected native Brave installation\"\n");
+    // Create directories and backup existing files
+    content.push_str("if [ \"$DRY_RUN\" -eq 0 ]; then\n");
+    content.push_str("    mkdir -p \"$BRAVE_DATA\"\n");
+    if plan.backs_up(UserFile::LocalState) {
+        content.push_str("    backup_file \"$LOCAL_STATE\"\n");
+    }
     content.push_str("fi\n\n");
     
-    content.push_str("PREFS_FILE=\"$BRAVE_DATA/Default/Preferences\"\n");
-    content.push_str("LOCAL_STATE=\"$BRAVE_DATA/Local State\"\n\n");
-    
-    // Create directories if they don't exist
-    content.push_str("mkdir -p \"$BRAVE_DATA/Default\"\n\n");
-    
-    // Backup existing files
-    content.push_str("[ -f \"$PREFS_FILE\" ] && cp \"$PREFS_FILE\" \"$PREFS_FILE.backup\"\n");
-    content.push_str("[ -f \"$LOCAL_STATE\" ] && cp \"$LOCAL_STATE\" \"$LOCAL_STATE.backup\"\n\n");
-    
-    add_jq_installation_check(content);
-    add_preferences_modification(content, &search_provider, &dashboard_config);
-    add_local_state_modification(content, &experimental_features);
-    
-    content.push_str("echo -e \"${GREEN}User preferences applied successfully!${NC}\"\n");
-    content.push_str("echo\n\n");
+    add_profile_enumeration(content);
+    add_profile_preferences_dispatch(content, &profile_changes);
+    add_local_state_modification(content, &plan.local_state_changes())?;
+    content.push_str("[ \"$DRY_RUN\" -eq 1 ] || rotate_backups\n");
+    content.push_str("[ \"$DRY_RUN\" -eq 1 ] || echo -e \"${GREEN}User preferences applied successfully!${NC}\"\n");
+    content.push_str("echo\n");
+    add_launch_flags(content, get_linux_flags_file(version), &plan.launch_flags());
+    content.push_str("}\n\n");
+    add_installation_loop(content, "Modifying user preferences in", "configure_installation");
     
     Ok(())
 }
 
+fn add_launch_flags(content: &mut String, flags_file: &str, launch_flags: &[&str]) {
+    if launch_flags.is_empty() {
+        return;
+    }
+
+    // The Brave launcher reads one switch per line from the flags file
+    content.push_str("echo -e \"${GREEN}Adding launch flags...${NC}\"\n");
+    content.push_str(&format!("FLAGS_FILE=\"$BRAVE_CONFIG/{}\"\n", flags_file));
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo \"Launch flags file: $FLAGS_FILE\"\n");
+    content.push_str("else\n");
+    content.push_str("    mkdir -p \"$BRAVE_CONFIG\"\n");
+    content.push_str("    touch \"$FLAGS_FILE\"\n");
+    content.push_str("fi\n");
+    add_list_switch_functions(content);
+    content.push_str("for FLAG in");
+    for flag in launch_flags {
+        content.push_str(&format!(" {}", shell_quote(flag)));
+    }
+    content.push_str("; do\n");
+    content.push_str("    LINE=$(merged_launch_flag \"$FLAG\")\n");
+    content.push_str("    if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("        [ -f \"$FLAGS_FILE\" ] && grep -qxF -- \"$LINE\" \"$FLAGS_FILE\" && FLAG_STATE=present || FLAG_STATE=absent\n");
+    content.push_str("        print_change \"$LINE\" \"$FLAG_STATE\" present\n");
+    content.push_str("    elif grep -qxF -- \"$LINE\" \"$FLAGS_FILE\"; then\n");
+    content.push_str("        echo \"Launch flag already present: $LINE\"\n");
+    content.push_str("    else\n");
+    content.push_str("        SWITCH=$(list_switch \"$FLAG\")\n");
+    content.push_str("        if [ -n \"$SWITCH\" ]; then\n");
+    content.push_str("            awk -v prefix=\"$SWITCH=\" 'index($0, prefix) != 1' \"$FLAGS_FILE\" > \"$FLAGS_FILE.tmp\"\n");
+    content.push_str("            mv -f \"$FLAGS_FILE.tmp\" \"$FLAGS_FILE\"\n");
+    content.push_str("        fi\n");
+    content.push_str("        echo \"$LINE\" >> \"$FLAGS_FILE\"\n");
+    content.push_str("        echo \"Added launch flag: $LINE\"\n");
+    content.push_str("    fi\n");
+    content.push_str("done\n");
+    content.push_str("echo\n\n");
+}
+
+/// Defines `list_switch`, which prints the name of the list switch a flag sets, and `merged_launch_flag`,
+/// which prints the flags file line for a flag with the values of that switch already in `$FLAGS_FILE` merged in.
+fn add_list_switch_functions(content: &mut String) {
+    // Chromium only reads the last occurrence of a switch, so each list switch gets a single line
+    content.push_str("list_switch() {\n");
+    content.push_str("    [[ \"$1\" == *=* ]] || return 0\n");
+    content.push_str("    case \"${1%%=*}\" in\n");
+    content.push_str(&format!("        {}) echo \"${{1%%=*}}\" ;;\n", LIST_SWITCHES.join("|")));
+    content.push_str("    esac\n");
+    content.push_str("}\n");
+    content.push_str("merged_launch_flag() {\n");
+    content.push_str("    local SWITCH VALUE VALUES=()\n");
+    content.push_str("    SWITCH=$(list_switch \"$1\")\n");
+    content.push_str("    if [ -z \"$SWITCH\" ]; then\n");
+    content.push_str("        echo \"$1\"\n");
+    content.push_str("        return\n");
+    content.push_str("    fi\n");
+    content.push_str("    while IFS= read -r VALUE; do\n");
+    content.push_str("        [ -n \"$VALUE\" ] && [[ \" ${VALUES[*]} \" != *\" $VALUE \"* ]] && VALUES+=(\"$VALUE\")\n");
+    content.push_str("    done < <({ awk -v prefix=\"$SWITCH=\" 'index($0, prefix) == 1 { print substr($0, length(prefix) + 1) }' \"$FLAGS_FILE\" 2>/dev/null; echo \"${1#*=}\"; } | tr ',' '\\n')\n");
+    content.push_str("    (IFS=,; echo \"$SWITCH=${VALUES[*]}\")\n");
+    content.push_str("}\n");
+}
+
+fn add_backup_snapshot(content: &mut String, retention: usize) {
+    // Each run copies the files it changes into a new timestamped snapshot, listed in manifest.txt
+    // One timestamp for the whole run, so a snapshot has the same name in every installation
+    content.push_str(&format!("BACKUP_TIMESTAMP=$(date +{})\n", BACKUP_TIMESTAMP_FORMAT));
+    content.push_str(&format!("BACKUP_RETENTION={}\n\n", retention));
+    content.push_str("backup_file() {\n");
+    content.push_str("    RELATIVE_PATH=\"${1#$BRAVE_DATA/}\"\n");
+    content.push_str("    [ -f \"$1\" ] && [ ! -f \"$BACKUP_DIR/$RELATIVE_PATH\" ] || return 0\n");
+    content.push_str("    mkdir -p \"$(dirname \"$BACKUP_DIR/$RELATIVE_PATH\")\"\n");
+    content.push_str("    cp -p \"$1\" \"$BACKUP_DIR/$RELATIVE_PATH\"\n");
+    content.push_str("    echo \"$RELATIVE_PATH\" >> \"$BACKUP_DIR/manifest.txt\"\n");
+    content.push_str("}\n\n");
+    content.push_str("rotate_backups() {\n");
+    content.push_str("    [ -d \"$BACKUP_DIR\" ] && echo \"Backup saved to: $BACKUP_DIR\"\n");
+    // The oldest snapshot holds the files from before the first run and is never removed
+    content.push_str("    ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort | tail -n +2 | sort -r | tail -n +$((BACKUP_RETENTION + 1)) | while IFS= read -r SNAPSHOT; do\n");
+    content.push_str("        rm -rf \"${BACKUP_ROOT:?}/$SNAPSHOT\"\n");
+    content.push_str("    done\n");
+    content.push_str("}\n\n");
+}
+
+fn add_json_writer(content: &mut String) {
+    // Write to a temporary file next to the target and only rename it over the original once it is valid JSON
+    content.push_str("write_json_file() {\n");
+    content.push_str("    local TARGET=\"$1\" JSON=\"$2\" TMP_FILE\n");
+    content.push_str("    if ! TMP_FILE=$(mktemp \"$TARGET.XXXXXX\"); then\n");
+    content.push_str("        echo -e \"${RED}Error: could not create a temporary file for $TARGET, it was left untouched${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("    if [ -z \"$JSON\" ] || ! printf '%s\\n' \"$JSON\" > \"$TMP_FILE\" || [ ! -s \"$TMP_FILE\" ] || ! jq -e 'type == \"object\"' \"$TMP_FILE\" &> /dev/null; then\n");
+    content.push_str("        rm -f \"$TMP_FILE\"\n");
+    content.push_str("        echo -e \"${RED}Error: generated invalid JSON for $TARGET, it was left untouched${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("    if [ -f \"$TARGET\" ]; then\n");
+    content.push_str("        chown --reference=\"$TARGET\" \"$TMP_FILE\" 2>/dev/null\n");
+    content.push_str("        chmod --reference=\"$TARGET\" \"$TMP_FILE\"\n");
+    content.push_str("    fi\n");
+    content.push_str("    if ! mv -f \"$TMP_FILE\" \"$TARGET\"; then\n");
+    content.push_str("        rm -f \"$TMP_FILE\"\n");
+    content.push_str("        echo -e \"${RED}Error: could not replace $TARGET, it was left untouched${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("}\n\n");
+}
+
+/// Fails before anything is removed when no installation has the snapshot given with --restore.
+fn add_restore_check(content: &mut String) {
+    content.push_str(&format!("if [ -n \"$RESTORE\" ] && [ \"$RESTORE\" != {} ]; then\n", LATEST_SNAPSHOT));
+    content.push_str("    FOUND=0\n");
+    content.push_str("    for DATA in \"${INSTALL_DATA[@]}\"; do\n");
+    content.push_str(&format!("        [ -f \"$DATA/{}/$RESTORE/manifest.txt\" ] && FOUND=1\n", BACKUP_DIR_NAME));
+    content.push_str("    done\n");
+    content.push_str("    if [ \"$FOUND\" -eq 0 ]; then\n");
+    content.push_str("        echo -e \"${RED}Error: no installation has the backup snapshot '$RESTORE'${NC}\"\n");
+    content.push_str("        echo \"Available snapshots:\"\n");
+    content.push_str("        for DATA in \"${INSTALL_DATA[@]}\"; do\n");
+    content.push_str(&format!("            ls -1 \"$DATA/{}\" 2>/dev/null | sort -r | while IFS= read -r NAME; do echo \"  $DATA: $NAME\"; done\n", BACKUP_DIR_NAME));
+    content.push_str("        done\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("fi\n\n");
+}
+
+fn add_snapshot_selection(content: &mut String) {
+    // Use the snapshot given with --restore, or the oldest one, which holds the files from before the first run
+    content.push_str(&format!("BACKUP_ROOT=\"$BRAVE_DATA/{}\"\n", BACKUP_DIR_NAME));
+    content.push_str(&format!(
+        "if [ -n \"$RESTORE\" ] && [ \"$RESTORE\" != {} ] && [ ! -f \"$BACKUP_ROOT/$RESTORE/manifest.txt\" ]; then\n",
+        LATEST_SNAPSHOT
+    ));
+    content.push_str("    echo -e \"${YELLOW}No backup snapshot '$RESTORE' in $BACKUP_ROOT${NC}\"\n");
+    content.push_str("    echo \"Available snapshots:\"\n");
+    content.push_str("    ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort -r | sed 's/^/  /'\n");
+    content.push_str("    echo\n");
+    content.push_str("    return\n");
+    content.push_str("fi\n");
+    content.push_str("case \"$RESTORE\" in\n");
+    content.push_str("    \"\") SNAPSHOT_NAME=$(ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort | head -n 1) ;;\n");
+    content.push_str(&format!("    {}) SNAPSHOT_NAME=$(ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort -r | head -n 1) ;;\n", LATEST_SNAPSHOT));
+    content.push_str("    *) SNAPSHOT_NAME=\"$RESTORE\" ;;\n");
+    content.push_str("esac\n");
+    content.push_str("SNAPSHOT=\"$BACKUP_ROOT/$SNAPSHOT_NAME\"\n\n");
+}
+
+fn add_snapshot_restore(content: &mut String) {
+    content.push_str("if [ -n \"$SNAPSHOT_NAME\" ] && [ -f \"$SNAPSHOT/manifest.txt\" ]; then\n");
+    content.push_str("    echo \"Restoring backup snapshot: $SNAPSHOT_NAME\"\n");
+    content.push_str("    while IFS= read -r RELATIVE_PATH; do\n");
+    content.push_str("        if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("            echo \"Would restore $BRAVE_DATA/$RELATIVE_PATH\"\n");
+    content.push_str("            continue\n");
+    content.push_str("        fi\n");
+    content.push_str("        cp -p \"$SNAPSHOT/$RELATIVE_PATH\" \"$BRAVE_DATA/$RELATIVE_PATH\"\n");
+    content.push_str("        echo \"Restored $BRAVE_DATA/$RELATIVE_PATH\"\n");
+    content.push_str("    done < \"$SNAPSHOT/manifest.txt\"\n");
+    content.push_str("else\n");
+    content.push_str("    echo -e \"${YELLOW}No backup snapshot found in $BACKUP_ROOT${NC}\"\n");
+    content.push_str("fi\n");
+    content.push_str("echo\n\n");
+}
+
+fn add_launch_flags_removal(content: &mut String, flags_file: &str, launch_flags: &[&str]) {
+    if launch_flags.is_empty() {
+        return;
+    }
+
+    content.push_str("echo -e \"${GREEN}Removing launch flags...${NC}\"\n");
+    content.push_str(&format!("FLAGS_FILE=\"$BRAVE_CONFIG/{}\"\n", flags_file));
+    add_list_switch_functions(content);
+    // Only the values the debloat script added are taken out of a list switch, its other values stay
+    content.push_str("without_launch_flag() {\n");
+    content.push_str("    local SWITCH\n");
+    content.push_str("    SWITCH=$(list_switch \"$1\")\n");
+    content.push_str("    if [ -z \"$SWITCH\" ]; then\n");
+    content.push_str("        grep -vxF -- \"$1\" \"$FLAGS_FILE\"\n");
+    content.push_str("        return 0\n");
+    content.push_str("    fi\n");
+    content.push_str("    awk -v prefix=\"$SWITCH=\" -v remove=\",${1#*=},\" 'index($0, prefix) == 1 {\n");
+    content.push_str("        n = split(substr($0, length(prefix) + 1), values, \",\"); kept = \"\"\n");
+    content.push_str("        for (i = 1; i <= n; i++) if (values[i] != \"\" && index(remove, \",\" values[i] \",\") == 0) kept = kept (kept == \"\" ? \"\" : \",\") values[i]\n");
+    content.push_str("        if (kept == \"\") next\n");
+    content.push_str("        $0 = prefix kept\n");
+    content.push_str("    } { print }' \"$FLAGS_FILE\"\n");
+    content.push_str("}\n");
+    content.push_str("if [ -f \"$FLAGS_FILE\" ]; then\n");
+    content.push_str("    for FLAG in");
+    for flag in launch_flags {
+        content.push_str(&format!(" {}", shell_quote(flag)));
+    }
+    content.push_str("; do\n");
+    content.push_str("        if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("            without_launch_flag \"$FLAG\" | cmp -s - \"$FLAGS_FILE\" || echo \"Would remove launch flag: $FLAG\"\n");
+    content.push_str("            continue\n");
+    content.push_str("        fi\n");
+    content.push_str("        without_launch_flag \"$FLAG\" > \"$FLAGS_FILE.tmp\"\n");
+    content.push_str("        mv -f \"$FLAGS_FILE.tmp\" \"$FLAGS_FILE\"\n");
+    content.push_str("    done\n");
+    content.push_str("    [ \"$DRY_RUN\" -eq 1 ] || [ -s \"$FLAGS_FILE\" ] || rm -f \"$FLAGS_FILE\"\n");
+    content.push_str("fi\n");
+    content.push_str("echo\n\n");
+}
+
 fn add_jq_installation_check(content: &mut String) {
     content.push_str("if ! command -v jq &> /dev/null; then\n");
+    content.push_str("    if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("        echo -e \"${RED}Error: jq is required for --dry-run and --check${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
     content.push_str("    echo -e \"${YELLOW}jq not found. Attempting to install...${NC}\"\n");
     content.push_str("    if command -v apt-get &> /dev/null; then\n");
     content.push_str("        sudo apt-get update && sudo apt-get install -y jq\n");
@@ -176,92 +645,212 @@ fn add_jq_installation_check(content: &mut String) {
     content.push_str("fi\n\n");
 }
 
-fn add_preferences_modification(content: &mut String, search_provider: &crate::preferences::SearchProvider, dashboard_config: &crate::preferences::NewTabPage) {
-    // Modify preferences file
-    content.push_str("# Create or modify Preferences file\n");
-    content.push_str("if [ -f \"$PREFS_FILE\" ]; then\n");
-    content.push_str("    PREFS_JSON=$(cat \"$PREFS_FILE\")\n");
-    content.push_str("else\n");
-    content.push_str("    PREFS_JSON='{}'\n");
-    content.push_str("fi\n\n");
-    
-    // Update preferences using jq
-    content.push_str("PREFS_JSON=$(echo \"$PREFS_JSON\" | jq '\n");
-    
-    // Search provider
-    content.push_str("  .default_search_provider_data = {\n");
-    content.push_str(&format!("    \"keyword\": \"{}\",\n", search_provider.keyword));
-    content.push_str(&format!("    \"name\": \"{}\",\n", search_provider.name));
-    content.push_str(&format!("    \"search_url\": \"{}\"\n", search_provider.search_url));
-    content.push_str("  } |\n");
-    
-    // Brave preferences
-    content.push_str("  .brave = (.brave // {}) |\n");
-    content.push_str("  .brave.new_tab_page = (.brave.new_tab_page // {}) |\n");
-    content.push_str("  .brave.stats = (.brave.stats // {}) |\n");
-    content.push_str("  .brave.today = (.brave.today // {}) |\n");
-    
-    // Dashboard settings
-    add_dashboard_settings_jq(content, dashboard_config);
-    
-    content.push_str("  .brave.stats.enabled = false |\n");
-    content.push_str("  .brave.today.should_show_brave_today_widget = false\n");
-    content.push_str("')\n\n");
-    
-    content.push_str("echo \"$PREFS_JSON\" > \"$PREFS_FILE\"\n\n");
-}
+fn add_preference_macs_update(content: &mut String, backup: bool) {
+    let protected_preferences = get_protected_preferences(MODIFIED_PREFERENCES);
+    let hex_key = if PREF_HASH_SEED.is_empty() { "00" } else { PREF_HASH_SEED };
 
-fn add_dashboard_settings_jq(content: &mut String, dashboard_config: &crate::preferences::NewTabPage) {
-    if let Some(show_clock) = dashboard_config.show_clock {
-        content.push_str(&format!("  .brave.new_tab_page.show_clock = {} |\n", show_clock.to_string().to_lowercase()));
-    }
-    if let Some(show_bg) = dashboard_config.show_background_image {
-        content.push_str(&format!("  .brave.new_tab_page.show_background_image = {} |\n", show_bg.to_string().to_lowercase()));
+    // Brave resets protected preferences whose MAC no longer matches, so recompute
+    // HMAC-SHA256(seed, device ID + path + JSON value) for each one. The device ID is empty on Linux.
+    content.push_str("pref_hash_json() {\n");
+    content.push_str("    jq -cS 'walk(if type == \"object\" then with_entries(select(.value != {} and .value != [])) else . end)' | sed 's/</\\\\u003C/g'\n");
+    content.push_str("}\n\n");
+    content.push_str("pref_hash() {\n");
+    content.push_str(&format!("    printf '%s' \"$1\" | openssl dgst -sha256 -mac HMAC -macopt hexkey:{} | awk '{{print toupper($NF)}}'\n", hex_key));
+    content.push_str("}\n\n");
+    content.push_str("update_preference_macs() {\n");
+    content.push_str("    if ! command -v openssl &> /dev/null; then\n");
+    content.push_str(&format!("        echo -e \"${{YELLOW}}openssl not found, cannot update the MACs. Brave will revert these changes in $PREFS_FILE: {}${{NC}}\"\n", protected_preferences.join(" ")));
+    content.push_str("        return\n");
+    content.push_str("    fi\n");
+    content.push_str("    SECURE_PREFS_FILE=\"$(dirname \"$PREFS_FILE\")/Secure Preferences\"\n");
+    if backup {
+        content.push_str("    backup_file \"$SECURE_PREFS_FILE\"\n");
     }
-    if let Some(show_stats) = dashboard_config.show_stats {
-        content.push_str(&format!("  .brave.new_tab_page.show_stats = {} |\n", show_stats.to_string().to_lowercase()));
+    content.push_str("    for MACS_FILE in \"$PREFS_FILE\" \"$SECURE_PREFS_FILE\"; do\n");
+    content.push_str("        [ -f \"$MACS_FILE\" ] && jq -e '.protection.macs' \"$MACS_FILE\" &> /dev/null || continue\n");
+    content.push_str("        MACS_JSON=$(cat \"$MACS_FILE\")\n");
+    content.push_str("        for PREF_PATH in");
+    for path in &protected_preferences {
+        content.push_str(&format!(" '{}'", path));
     }
-    if let Some(show_shortcuts) = dashboard_config.show_shortcuts {
-        content.push_str(&format!("  .brave.new_tab_page.show_shortcuts = {} |\n", show_shortcuts.to_string().to_lowercase()));
+    content.push_str("; do\n");
+    content.push_str("            VALUE=$(jq --arg path \"$PREF_PATH\" 'getpath($path | split(\".\")) | select(. != null)' \"$PREFS_FILE\" | pref_hash_json)\n");
+    content.push_str("            MAC=$(pref_hash \"$PREF_PATH$VALUE\")\n");
+    content.push_str("            MACS_JSON=$(echo \"$MACS_JSON\" | jq --arg path \"$PREF_PATH\" --arg mac \"$MAC\" 'setpath([\"protection\", \"macs\"] + ($path | split(\".\")); $mac)')\n");
+    content.push_str("        done\n");
+    content.push_str("        if echo \"$MACS_JSON\" | jq -e '.protection.super_mac' &> /dev/null; then\n");
+    content.push_str("            SUPER_MAC=$(pref_hash \"$(echo \"$MACS_JSON\" | jq '.protection.macs' | pref_hash_json)\")\n");
+    content.push_str("            MACS_JSON=$(echo \"$MACS_JSON\" | jq --arg mac \"$SUPER_MAC\" '.protection.super_mac = $mac')\n");
+    content.push_str("        fi\n");
+    content.push_str("        write_json_file \"$MACS_FILE\" \"$MACS_JSON\"\n");
+    content.push_str("    done\n");
+    content.push_str("}\n\n");
+}
+
+fn add_profile_enumeration(content: &mut String) {
+    // Profiles known to Brave are listed in Local State, fall back to scanning the data directory
+    content.push_str("# Enumerate browser profiles\n");
+    content.push_str("PROFILES=()\n");
+    content.push_str("if [ -f \"$LOCAL_STATE\" ]; then\n");
+    content.push_str("    while IFS= read -r PROFILE; do\n");
+    content.push_str("        [ -n \"$PROFILE\" ] && PROFILES+=(\"$PROFILE\")\n");
+    content.push_str("    done < <(jq -r '.profile.info_cache // {} | keys[]' \"$LOCAL_STATE\" 2>/dev/null)\n");
+    content.push_str("fi\n");
+    content.push_str("if [ ${#PROFILES[@]} -eq 0 ]; then\n");
+    content.push_str("    while IFS= read -r PROFILE_DIR; do\n");
+    content.push_str("        PROFILES+=(\"$(basename \"$PROFILE_DIR\")\")\n");
+    content.push_str("    done < <(find \"$BRAVE_DATA\" -mindepth 1 -maxdepth 1 -type d \\( -name Default -o -name 'Profile *' \\) 2>/dev/null)\n");
+    content.push_str("fi\n");
+    content.push_str("[ ${#PROFILES[@]} -eq 0 ] && PROFILES=(\"Default\")\n\n");
+}
+
+fn add_profile_preferences_dispatch(content: &mut String, profile_changes: &[ProfileChanges]) {
+    content.push_str("for PROFILE in \"${PROFILES[@]}\"; do\n");
+    content.push_str("    PROFILE_NAME=$(jq -r --arg profile \"$PROFILE\" '.profile.info_cache[$profile].name // empty' \"$LOCAL_STATE\" 2>/dev/null)\n");
+    content.push_str("    PREFS_FILE=\"$BRAVE_DATA/$PROFILE/Preferences\"\n");
+
+    if let [ProfileChanges { profile: None, .. }] = profile_changes {
+        content.push_str("    echo \"Applying preferences to profile: $PROFILE\"\n");
+        content.push_str("    apply_preferences_0\n");
+    } else {
+        for (i, changes) in profile_changes.iter().enumerate() {
+            let keyword = if i == 0 { "if" } else { "elif" };
+            let profile = shell_quote(changes.profile.unwrap_or_default());
+            content.push_str(&format!("    {} [ \"$PROFILE\" = {} ] || [ \"$PROFILE_NAME\" = {} ]; then\n", keyword, profile, profile));
+            content.push_str("        echo \"Applying preferences to profile: $PROFILE\"\n");
+            content.push_str(&format!("        apply_preferences_{}\n", i));
+        }
+        content.push_str("    else\n");
+        content.push_str("        echo \"Skipping profile: $PROFILE\"\n");
+        content.push_str("    fi\n");
     }
-    if let Some(show_branded) = dashboard_config.show_branded_background_image {
-        content.push_str(&format!("  .brave.new_tab_page.show_branded_background_image = {} |\n", show_branded.to_string().to_lowercase()));
+
+    content.push_str("done\n\n");
+}
+
+fn add_preferences_modification(content: &mut String, index: usize, changes: &ProfileChanges, plan: &Plan) -> Result<(), DebloaterError> {
+    // Modify the Preferences file of the profile in $PREFS_FILE
+    content.push_str(&format!("apply_preferences_{}() {{\n", index));
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo \"Preferences file: $PREFS_FILE\"\n");
+    for (path, value) in &changes.preferences {
+        content.push_str(&format!("    show_json_change \"$PREFS_FILE\" {} {}\n", shell_quote(path), shell_quote(&value.to_string())));
     }
-    if let Some(show_cards) = dashboard_config.show_cards {
-        content.push_str(&format!("  .brave.new_tab_page.show_cards = {} |\n", show_cards.to_string().to_lowercase()));
+    content.push_str("    return\n");
+    content.push_str("fi\n\n");
+    content.push_str("mkdir -p \"$(dirname \"$PREFS_FILE\")\"\n");
+    if plan.backs_up(UserFile::Preferences) {
+        content.push_str("backup_file \"$PREFS_FILE\"\n");
     }
-    if let Some(show_search) = dashboard_config.show_search_widget {
-        content.push_str(&format!("  .brave.new_tab_page.show_search_widget = {} |\n", show_search.to_string().to_lowercase()));
+    content.push('\n');
+    content.push_str("if [ -f \"$PREFS_FILE\" ]; then\n");
+    content.push_str("    PREFS_JSON=$(cat \"$PREFS_FILE\")\n");
+    content.push_str("else\n");
+    content.push_str("    PREFS_JSON='{}'\n");
+    content.push_str("fi\n");
+    content.push_str(&format!("PREFS_JSON=$(echo \"$PREFS_JSON\" | {})\n", jq_set_paths(&changes.preferences)?));
+    content.push_str("write_json_file \"$PREFS_FILE\" \"$PREFS_JSON\"\n");
+    if plan.updates_preference_macs() {
+        content.push_str("update_preference_macs\n");
     }
-    if let Some(show_news) = dashboard_config.show_brave_news {
-        content.push_str(&format!("  .brave.new_tab_page.show_brave_news = {} |\n", show_news.to_string().to_lowercase()));
+    content.push_str("}\n\n");
+
+    Ok(())
+}
+
+/// Builds a jq command that sets each dotted path to its value. The changes are passed with
+/// --argjson so config values are never parsed as part of the jq program.
+fn jq_set_paths(changes: &[(&str, &Value)]) -> Result<String, DebloaterError> {
+    let changes: Vec<(Vec<&str>, &Value)> = changes.iter().map(|(path, value)| (path.split('.').collect(), *value)).collect();
+    Ok(format!(
+        "jq --argjson changes {} 'reduce $changes[] as $change (.; setpath($change[0]; $change[1]))'",
+        shell_quote(&serde_json::to_string(&changes)?)
+    ))
+}
+
+fn add_local_state_modification(content: &mut String, changes: &[(&str, &Value)]) -> Result<(), DebloaterError> {
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo \"Local State file: $LOCAL_STATE\"\n");
+    for (path, value) in changes {
+        content.push_str(&format!("    show_json_change \"$LOCAL_STATE\" {} {}\n", shell_quote(path), shell_quote(&value.to_string())));
     }
-    if let Some(show_together) = dashboard_config.show_together {
-        content.push_str(&format!("  .brave.new_tab_page.show_together = {} |\n", show_together.to_string().to_lowercase()));
+    content.push_str("else\n");
+
+    // Handle Local State file
+    content.push_str("    # Create or modify Local State file\n");
+    content.push_str("    if [ -f \"$LOCAL_STATE\" ]; then\n");
+    content.push_str("        LOCAL_JSON=$(cat \"$LOCAL_STATE\")\n");
+    content.push_str("    else\n");
+    content.push_str("        LOCAL_JSON='{}'\n");
+    content.push_str("    fi\n");
+    content.push_str(&format!("    LOCAL_JSON=$(echo \"$LOCAL_JSON\" | {})\n", jq_set_paths(changes)?));
+    content.push_str("    write_json_file \"$LOCAL_STATE\" \"$LOCAL_JSON\"\n");
+    content.push_str("fi\n");
+
+    Ok(())
+}
+
+/// Parses `--dry-run`, plus `--check` for debloat scripts or `--restore <timestamp|latest>` for revert scripts.
+/// Check mode implies dry run.
+fn add_script_options(content: &mut String, revert: bool) {
+    content.push_str("DRY_RUN=0\n");
+    content.push_str("CHECK=0\n");
+    content.push_str("FAILURES=0\n");
+    content.push_str("RESTORE=\"\"\n");
+    content.push_str("while [ $# -gt 0 ]; do\n");
+    content.push_str("    case \"$1\" in\n");
+    content.push_str("        --dry-run) DRY_RUN=1 ;;\n");
+    if revert {
+        content.push_str("        --restore) RESTORE=\"$2\"; shift ;;\n");
+    } else {
+        content.push_str("        --check) CHECK=1; DRY_RUN=1 ;;\n");
     }
+    content.push_str("    esac\n");
+    content.push_str("    shift\n");
+    content.push_str("done\n");
+    content.push_str("if [ \"$CHECK\" -eq 1 ]; then\n");
+    content.push_str("    echo -e \"${YELLOW}Check: comparing the current configuration against the generated one${NC}\"\n");
+    content.push_str("    echo\n");
+    content.push_str("elif [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo -e \"${YELLOW}Dry run: showing changes without applying them${NC}\"\n");
+    content.push_str("    echo\n");
+    content.push_str("fi\n\n");
 }
 
-fn add_local_state_modification(content: &mut String, experimental_features: &[String]) {
-    // Handle Local State file
-    content.push_str("# Create or modify Local State file\n");
-    content.push_str("if [ -f \"$LOCAL_STATE\" ]; then\n");
-    content.push_str("    LOCAL_JSON=$(cat \"$LOCAL_STATE\")\n");
+fn add_completion_message(content: &mut String, message: &str) {
+    content.push_str("if [ \"$CHECK\" -eq 1 ]; then\n");
+    content.push_str("    echo\n");
+    content.push_str("    if [ \"$FAILURES\" -gt 0 ]; then\n");
+    content.push_str("        echo -e \"${RED}$FAILURES setting(s) differ from the generated configuration${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("    echo -e \"${GREEN}All settings match the generated configuration${NC}\"\n");
+    content.push_str("elif [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo -e \"${GREEN}Dry run complete, no changes were made.${NC}\"\n");
     content.push_str("else\n");
-    content.push_str("    LOCAL_JSON='{}'\n");
-    content.push_str("fi\n\n");
-    
-    content.push_str("LOCAL_JSON=$(echo \"$LOCAL_JSON\" | jq '\n");
-    content.push_str("  .browser = (.browser // {}) |\n");
-    content.push_str("  .browser.enabled_labs_experiments = [\n");
-    for (i, feature) in experimental_features.iter().enumerate() {
-        content.push_str(&format!("    \"{}\"", feature));
-        if i < experimental_features.len() - 1 {
-            content.push_str(",");
-        }
-        content.push_str("\n");
-    }
-    content.push_str("  ]\n");
-    content.push_str("')\n\n");
-    
-    content.push_str("echo \"$LOCAL_JSON\" > \"$LOCAL_STATE\"\n");
-}
\ No newline at end of file
+    content.push_str(&format!("    echo -e \"${{GREEN}}{}${{NC}}\"\n", message));
+    content.push_str("    echo -e \"${GREEN}Please restart Brave browser for changes to take effect.${NC}\"\n");
+    content.push_str("fi\n");
+}
+
+fn add_change_preview(content: &mut String) {
+    // Print the current and new value of a dotted path, both as compact JSON, or PASS/FAIL in check mode
+    content.push_str("print_change() {\n");
+    content.push_str("    [ -n \"$2\" ] && CURRENT=\"$2\" || CURRENT=null\n");
+    content.push_str("    if [ \"$CHECK\" -eq 1 ]; then\n");
+    content.push_str("        if [ \"$CURRENT\" = \"$3\" ]; then\n");
+    content.push_str("            echo -e \"    ${GREEN}PASS${NC}  $1\"\n");
+    content.push_str("        else\n");
+    content.push_str("            echo -e \"    ${RED}FAIL${NC}  $1: $CURRENT (expected $3)\"\n");
+    content.push_str("            FAILURES=$((FAILURES + 1))\n");
+    content.push_str("        fi\n");
+    content.push_str("    elif [ \"$CURRENT\" = \"$3\" ]; then\n");
+    content.push_str("        echo \"    $1: $CURRENT (unchanged)\"\n");
+    content.push_str("    else\n");
+    content.push_str("        echo \"    $1: $CURRENT -> $3\"\n");
+    content.push_str("    fi\n");
+    content.push_str("}\n\n");
+    content.push_str("show_json_change() {\n");
+    content.push_str("    print_change \"$2\" \"$(jq -c --arg path \"$2\" 'getpath($path | split(\".\"))' \"$1\" 2>/dev/null)\" \"$3\"\n");
+    content.push_str("}\n\n");
+}
@@ -1,16 +1,25 @@
 use std::fs;
 use std::path::Path;
 
-use crate::cli::BraveVersion;
-use crate::config::{Config, Extension, ConfigValue};
+use serde_json::Value;
+
+use crate::cli::{BraveVersion, MacOSPolicyMode};
+use crate::config::ConfigValue;
 use crate::error::DebloaterError;
-use crate::platforms::{PlatformGenerator, get_macos_bundle_id, get_version_suffix};
-use crate::preferences::{PreferencesInputConfig, get_default_search_provider, get_default_dashboard_config, get_default_experimental_features};
+use crate::plan::{Plan, ProfileChanges, UserFile};
+use crate::platforms::escape::{shell_quote, xml_escape};
+use crate::platforms::{BACKUP_DIR_NAME, BACKUP_TIMESTAMP_FORMAT, LATEST_SNAPSHOT, PlatformGenerator, get_macos_app_name, get_macos_bundle_id, get_version_suffix, stable_uuid};
+use crate::preferences::{MODIFIED_PREFERENCES, get_preference_values, get_protected_preferences};
 
-pub struct MacOSGenerator;
+#[derive(Debug, Clone, Default)]
+pub struct MacOSGenerator {
+    pub policy_mode: MacOSPolicyMode,
+    /// Converts the policy plist to the binary format after writing it.
+    pub binary_plist: bool,
+}
 
 impl PlatformGenerator for MacOSGenerator {
-    fn generate_unified_script(&self, config: &Config, extensions: &[Extension], version: &BraveVersion, output_dir: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
+    fn generate_unified_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
         let filename = match version {
             BraveVersion::Normal => "brave_debloat_macos.sh",
             BraveVersion::Nightly => "brave_nightly_debloat_macos.sh",
@@ -25,14 +34,149 @@ impl PlatformGenerator for MacOSGenerator {
         content.push_str("# Run with sudo for system-wide changes\n\n");
         
         add_color_definitions(&mut content);
+        add_script_options(&mut content, false);
         add_header(&mut content);
         add_sudo_check(&mut content);
+        if plan.checks_process() {
+            add_brave_process_check(&mut content);
+        }
+        add_jq_installation_check(&mut content);
+        add_change_preview(&mut content);
+        add_system_policies(&mut content, plan, version, self)?;
+        add_user_preferences(&mut content, plan, version_suffix)?;
+        add_launch_flags(&mut content, version, &plan.launch_flags());
+        
+        add_completion_message(&mut content, "Configuration complete!");
+        
+        let output_path = Path::new(output_dir).join(filename);
+        fs::write(output_path, content)?;
+        
+        Ok(())
+    }
+
+    fn generate_revert_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let filename = match version {
+            BraveVersion::Normal => "brave_revert_macos.sh",
+            BraveVersion::Nightly => "brave_nightly_revert_macos.sh",
+        };
+        
+        let bundle_id = get_macos_bundle_id(version);
+        
+        let mut content = String::new();
+        content.push_str("#!/bin/zsh\n");
+        content.push_str("# Brave Browser Debloater Revert Script for macOS\n");
+        content.push_str("# This script removes the policies and restores the preferences changed by the debloat script\n");
+        content.push_str("# Run with sudo to remove system policies\n\n");
+        
+        add_color_definitions(&mut content);
+        content.push_str("echo -e \"${GREEN}Brave Browser Debloater Revert for macOS${NC}\"\n");
+        content.push_str("echo -e \"${GREEN}===========================================${NC}\"\n");
+        content.push_str("echo\n\n");
+        add_script_options(&mut content, true);
+        add_sudo_check(&mut content);
         add_brave_process_check(&mut content);
-        add_system_policies(&mut content, config, extensions, version)?;
-        add_user_preferences(&mut content, version_suffix, preferences_config)?;
+        content.push_str(&format!("BRAVE_DATA=\"$HOME/Library/Application Support/BraveSoftware/{}\"\n", get_version_suffix(version)));
+        add_snapshot_selection(&mut content);
+        
+        content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+        content.push_str(&format!("    [ -f /Library/Managed\\ Preferences/{0}.plist ] && echo \"Would remove policy file: /Library/Managed Preferences/{0}.plist\"\n", bundle_id));
+        content.push_str("elif [ \"$SKIP_POLICIES\" -eq 0 ]; then\n");
+        content.push_str("    echo -e \"${GREEN}Removing system policies...${NC}\"\n");
+        if let MacOSPolicyMode::Defaults = self.policy_mode {
+            content.push_str(&format!("    defaults delete '/Library/Managed Preferences/{}' 2>/dev/null\n", bundle_id));
+        }
+        content.push_str(&format!("    rm -f /Library/Managed\\ Preferences/{}.plist\n", bundle_id));
+        if let MacOSPolicyMode::Defaults = self.policy_mode {
+            content.push_str("    killall cfprefsd 2>/dev/null\n");
+        }
+        content.push_str("    echo -e \"${GREEN}System policies removed successfully!${NC}\"\n");
+        content.push_str("else\n");
+        content.push_str("    echo -e \"${YELLOW}Skipping system policies (not running as sudo)${NC}\"\n");
+        content.push_str("fi\n");
+        content.push_str("echo\n\n");
+        
+        content.push_str("echo -e \"${GREEN}Restoring user preferences...${NC}\"\n");
+        add_snapshot_restore(&mut content);
         
-        content.push_str("echo -e \"${GREEN}Configuration complete!${NC}\"\n");
-        content.push_str("echo -e \"${GREEN}Please restart Brave browser for changes to take effect.${NC}\"\n");
+        if !plan.launch_flags().is_empty() {
+            content.push_str("echo -e \"${GREEN}Removing launcher with launch flags...${NC}\"\n");
+            content.push_str(&format!("WRAPPER_APP=\"$HOME/Applications/{} (Debloated).app\"\n", get_macos_app_name(version)));
+            content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+            content.push_str("    [ -d \"$WRAPPER_APP\" ] && echo \"Would remove launcher: $WRAPPER_APP\"\n");
+            content.push_str("else\n");
+            content.push_str("    rm -rf \"$WRAPPER_APP\"\n");
+            content.push_str("fi\n");
+            content.push_str("echo\n\n");
+        }
+        
+        add_completion_message(&mut content, "Revert complete!");
+        
+        let output_path = Path::new(output_dir).join(filename);
+        fs::write(output_path, content)?;
+        
+        Ok(())
+    }
+}
+
+impl MacOSGenerator {
+    /// Writes a configuration profile with the policies, for `profiles install` or an MDM such as Jamf or Kandji.
+    /// `profile_name` names the profile and, with the version, gives it identifiers and UUIDs that stay the
+    /// same between runs, so a regenerated profile replaces the installed one.
+    pub fn generate_mobileconfig(&self, plan: &Plan, version: &BraveVersion, output_dir: &str, profile_name: &str) -> Result<(), DebloaterError> {
+        let filename = match version {
+            BraveVersion::Normal => "brave_debloat.mobileconfig",
+            BraveVersion::Nightly => "brave_nightly_debloat.mobileconfig",
+        };
+        
+        let bundle_id = get_macos_bundle_id(version);
+        let identifier = get_mobileconfig_identifier(version, profile_name);
+        let payload_identifier = format!("{}.{}", identifier, bundle_id);
+        let display_name = format!("{} Debloat ({})", get_macos_app_name(version), profile_name);
+        let description = format!(
+            "Brave browser policies from the {} configuration, generated by brave-debloater.",
+            profile_name
+        );
+        
+        let mut content = String::new();
+        content.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        content.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+        content.push_str("<plist version=\"1.0\">\n<dict>\n");
+        content.push_str("    <key>PayloadContent</key>\n");
+        content.push_str("    <array>\n");
+        content.push_str("        <dict>\n");
+        
+        // Custom settings payload: the policies are forced preferences of the Brave domain
+        content.push_str("            <key>PayloadContent</key>\n");
+        content.push_str("            <dict>\n");
+        content.push_str(&format!("                <key>{}</key>\n", bundle_id));
+        content.push_str("                <dict>\n");
+        content.push_str("                    <key>Forced</key>\n");
+        content.push_str("                    <array>\n");
+        content.push_str("                        <dict>\n");
+        content.push_str("                            <key>mcx_preference_settings</key>\n");
+        content.push_str("                            <dict>\n");
+        add_plist_policies(&mut content, plan, "                                ");
+        content.push_str("                            </dict>\n");
+        content.push_str("                        </dict>\n");
+        content.push_str("                    </array>\n");
+        content.push_str("                </dict>\n");
+        content.push_str("            </dict>\n");
+        add_payload_keys(&mut content, "            ", &display_name, &payload_identifier, "com.apple.ManagedClient.preferences");
+        content.push_str("            <key>PayloadEnabled</key>\n");
+        content.push_str("            <true/>\n");
+        content.push_str("        </dict>\n");
+        content.push_str("    </array>\n");
+        
+        add_payload_keys(&mut content, "    ", &display_name, &identifier, "Configuration");
+        content.push_str("    <key>PayloadDescription</key>\n");
+        content.push_str(&format!("    <string>{}</string>\n", xml_escape(&description)));
+        content.push_str("    <key>PayloadOrganization</key>\n");
+        content.push_str("    <string>brave-debloater</string>\n");
+        content.push_str("    <key>PayloadScope</key>\n");
+        content.push_str("    <string>System</string>\n");
+        content.push_str("    <key>PayloadRemovalDisallowed</key>\n");
+        content.push_str("    <false/>\n");
+        content.push_str("</dict>\n</plist>\n");
         
         let output_path = Path::new(output_dir).join(filename);
         fs::write(output_path, content)?;
@@ -41,6 +185,30 @@ impl PlatformGenerator for MacOSGenerator {
     }
 }
 
+/// Returns the PayloadIdentifier of the configuration profile, e.g. `com.brave.Browser.debloat.privacy-focused`.
+pub fn get_mobileconfig_identifier(version: &BraveVersion, profile_name: &str) -> String {
+    // Identifiers are reverse-DNS names, so anything but letters, digits, dots and hyphens becomes a hyphen
+    let name: String = profile_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' })
+        .collect();
+    format!("{}.debloat.{}", get_macos_bundle_id(version), name)
+}
+
+/// Writes the keys every payload needs, with a UUID derived from `identifier`.
+fn add_payload_keys(content: &mut String, indent: &str, display_name: &str, identifier: &str, payload_type: &str) {
+    content.push_str(&format!("{}<key>PayloadDisplayName</key>\n", indent));
+    content.push_str(&format!("{}<string>{}</string>\n", indent, xml_escape(display_name)));
+    content.push_str(&format!("{}<key>PayloadIdentifier</key>\n", indent));
+    content.push_str(&format!("{}<string>{}</string>\n", indent, xml_escape(identifier)));
+    content.push_str(&format!("{}<key>PayloadType</key>\n", indent));
+    content.push_str(&format!("{}<string>{}</string>\n", indent, payload_type));
+    content.push_str(&format!("{}<key>PayloadUUID</key>\n", indent));
+    content.push_str(&format!("{}<string>{}</string>\n", indent, stable_uuid(identifier)));
+    content.push_str(&format!("{}<key>PayloadVersion</key>\n", indent));
+    content.push_str(&format!("{}<integer>1</integer>\n", indent));
+}
+
 fn add_color_definitions(content: &mut String) {
     content.push_str("# Colors for output\n");
     content.push_str("RED='\\033[0;31m'\n");
@@ -57,8 +225,10 @@ fn add_header(content: &mut String) {
 
 fn add_sudo_check(content: &mut String) {
     content.push_str("if [ \"$EUID\" -ne 0 ]; then\n");
-    content.push_str("    echo -e \"${YELLOW}Note: Running without sudo. System policies will be skipped.${NC}\"\n");
-    content.push_str("    echo -e \"${YELLOW}Run with sudo for complete configuration.${NC}\"\n");
+    content.push_str("    if [ \"$DRY_RUN\" -eq 0 ]; then\n");
+    content.push_str("        echo -e \"${YELLOW}Note: Running without sudo. System policies will be skipped.${NC}\"\n");
+    content.push_str("        echo -e \"${YELLOW}Run with sudo for complete configuration.${NC}\"\n");
+    content.push_str("    fi\n");
     content.push_str("    SKIP_POLICIES=1\n");
     content.push_str("else\n");
     content.push_str("    SKIP_POLICIES=0\n");
@@ -68,7 +238,7 @@ fn add_sudo_check(content: &mut String) {
 
 fn add_brave_process_check(content: &mut String) {
     content.push_str("echo \"Checking if Brave is running...\"\n");
-    content.push_str("if pgrep -f \"Brave Browser\" > /dev/null; then\n");
+    content.push_str("if [ \"$DRY_RUN\" -eq 0 ] && pgrep -f \"Brave Browser\" > /dev/null; then\n");
     content.push_str("    echo -e \"${YELLOW}WARNING: Brave browser is running!${NC}\"\n");
     content.push_str("    echo \"Please close Brave browser before running this script.\"\n");
     content.push_str("    echo \"Press Enter to continue anyway, or Ctrl+C to exit.\"\n");
@@ -79,51 +249,32 @@ fn add_brave_process_check(content: &mut String) {
     content.push_str("echo\n\n");
 }
 
-fn add_system_policies(content: &mut String, config: &Config, extensions: &[Extension], version: &BraveVersion) -> Result<(), DebloaterError> {
-    content.push_str("if [ \"$SKIP_POLICIES\" -eq 0 ]; then\n");
-    content.push_str("    echo -e \"${GREEN}Applying system policies...${NC}\"\n");
-    
+fn add_system_policies(content: &mut String, plan: &Plan, version: &BraveVersion, options: &MacOSGenerator) -> Result<(), DebloaterError> {
     let bundle_id = get_macos_bundle_id(version);
-    
-    // Create managed preferences plist
+
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str(&format!("    echo \"Policy file: /Library/Managed Preferences/{}.plist\"\n", bundle_id));
+    for (key, value) in get_preference_values(&plan.policies_json()?) {
+        content.push_str(&format!("    show_plist_change '/Library/Managed Preferences/{}.plist' {} {}\n", bundle_id, shell_quote(&key), shell_quote(&value.to_string())));
+    }
+    content.push_str("elif [ \"$SKIP_POLICIES\" -eq 0 ]; then\n");
+    content.push_str("    echo -e \"${GREEN}Applying system policies...${NC}\"\n");
     content.push_str("    mkdir -p /Library/Managed\\ Preferences\n");
-    content.push_str(&format!("    cat << 'EOF' > /Library/Managed\\ Preferences/{}.plist\n", bundle_id));
-    content.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
-    content.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
-    content.push_str("<plist version=\"1.0\">\n<dict>\n");
-    
-    for (key, value) in config {
-        if key == "ExtensionInstallForcelist" {
-            // Handle extension list
-            content.push_str("    <key>ExtensionInstallForcelist</key>\n");
-            content.push_str("    <array>\n");
-            for ext in extensions {
-                content.push_str(&format!("        <string>{}</string>\n", ext.id));
-            }
-            content.push_str("    </array>\n");
-            continue;
-        }
-        if key == "ReportAppInventory" || key == "ReportWebsiteTelemetry" {
-            continue;
-        }
-        
-        content.push_str(&format!("    <key>{}</key>\n", key));
-        match value {
-            ConfigValue::Bool(b) => content.push_str(&format!("    <{}/>", if *b { "true" } else { "false" })),
-            ConfigValue::String(s) => content.push_str(&format!("    <string>{}</string>", s)),
-            ConfigValue::Number(n) => content.push_str(&format!("    <integer>{}</integer>", n)),
-            ConfigValue::StringArray(arr) => {
-                content.push_str("    <array>\n");
-                for item in arr {
-                    content.push_str(&format!("        <string>{}</string>\n", item));
-                }
-                content.push_str("    </array>");
+    match options.policy_mode {
+        MacOSPolicyMode::Plist => {
+            // Create managed preferences plist
+            content.push_str(&format!("    cat << 'EOF' > /Library/Managed\\ Preferences/{}.plist\n", bundle_id));
+            content.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            content.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+            content.push_str("<plist version=\"1.0\">\n<dict>\n");
+            add_plist_policies(content, plan, "    ");
+            content.push_str("</dict>\n</plist>\nEOF\n");
+            if options.binary_plist {
+                content.push_str(&format!("    plutil -convert binary1 /Library/Managed\\ Preferences/{}.plist\n", bundle_id));
             }
         }
-        content.push_str("\n");
+        MacOSPolicyMode::Defaults => add_defaults_policies(content, plan, bundle_id, options.binary_plist)?,
     }
-    
-    content.push_str("</dict>\n</plist>\nEOF\n");
     content.push_str(&format!("    chmod 644 /Library/Managed\\ Preferences/{}.plist\n", bundle_id));
     content.push_str("    echo -e \"${GREEN}System policies applied successfully!${NC}\"\n");
     content.push_str("else\n");
@@ -134,35 +285,233 @@ fn add_system_policies(content: &mut String, config: &Config, extensions: &[Exte
     Ok(())
 }
 
-fn add_user_preferences(content: &mut String, version_suffix: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
-    let search_provider = get_default_search_provider(preferences_config);
-    let dashboard_config = get_default_dashboard_config(preferences_config);
-    let experimental_features = get_default_experimental_features(preferences_config);
+/// Writes the policies key by key through cfprefsd with `defaults write`, then reads every key back
+/// from cfprefsd and stops if one does not match.
+fn add_defaults_policies(content: &mut String, plan: &Plan, bundle_id: &str, binary_plist: bool) -> Result<(), DebloaterError> {
+    content.push_str(&format!("    POLICY_DOMAIN='/Library/Managed Preferences/{}'\n", bundle_id));
+    // Start from an empty domain so policies removed from the config do not linger
+    content.push_str("    defaults delete \"$POLICY_DOMAIN\" 2>/dev/null\n");
+    for (key, value) in plan.policies() {
+        let typed_value = match value {
+            ConfigValue::Bool(b) => format!("-bool {}", b),
+            ConfigValue::String(s) => format!("-string {}", shell_quote(s)),
+            ConfigValue::Number(n) => format!("-int {}", n),
+            ConfigValue::StringArray(items) => {
+                let items: Vec<String> = items.iter().map(|item| shell_quote(item)).collect();
+                format!("-array {}", items.join(" ")).trim_end().to_string()
+            }
+        };
+        content.push_str(&format!("    defaults write \"$POLICY_DOMAIN\" {} {}\n", shell_quote(key), typed_value));
+    }
+
+    // cfprefsd caches preferences and writes them back when it exits, restarting it makes the new values take effect
+    content.push_str("    killall cfprefsd 2>/dev/null\n");
+    if binary_plist {
+        content.push_str("    plutil -convert binary1 \"$POLICY_DOMAIN.plist\"\n");
+    }
+    content.push_str("    POLICY_EXPORT=\"$(mktemp)\"\n");
+    content.push_str("    defaults export \"$POLICY_DOMAIN\" \"$POLICY_EXPORT\"\n");
+    content.push_str("    POLICY_ERRORS=0\n");
+    for (key, value) in get_preference_values(&plan.policies_json()?) {
+        content.push_str(&format!("    verify_policy \"$POLICY_EXPORT\" {} {}\n", shell_quote(&key), shell_quote(&value.to_string())));
+    }
+    content.push_str("    rm -f \"$POLICY_EXPORT\"\n");
+    content.push_str("    if [ \"$POLICY_ERRORS\" -gt 0 ]; then\n");
+    content.push_str("        echo -e \"${RED}Error: $POLICY_ERRORS policies did not read back as written${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    Ok(())
+}
+
+/// Writes every policy as a plist `<key>` and value, each line starting with `indent`.
+fn add_plist_policies(content: &mut String, plan: &Plan, indent: &str) {
+    for (key, value) in plan.policies() {
+        content.push_str(&format!("{}<key>{}</key>\n", indent, xml_escape(key)));
+        match value {
+            ConfigValue::Bool(b) => content.push_str(&format!("{}<{}/>", indent, if *b { "true" } else { "false" })),
+            ConfigValue::String(s) => content.push_str(&format!("{}<string>{}</string>", indent, xml_escape(s))),
+            ConfigValue::Number(n) => content.push_str(&format!("{}<integer>{}</integer>", indent, n)),
+            ConfigValue::StringArray(arr) => {
+                content.push_str(&format!("{}<array>\n", indent));
+                for item in arr {
+                    content.push_str(&format!("{}    <string>{}</string>\n", indent, xml_escape(item)));
+                }
+                content.push_str(&format!("{}</array>", indent));
+            }
+        }
+        content.push('\n');
+    }
+}
+
+fn add_user_preferences(content: &mut String, plan: &Plan, version_suffix: &str) -> Result<(), DebloaterError> {
+    let profile_changes = plan.profile_changes();
 
     content.push_str("echo -e \"${GREEN}Modifying user preferences...${NC}\"\n");
     content.push_str(&format!("BRAVE_DATA=\"$HOME/Library/Application Support/BraveSoftware/{}\"\n", version_suffix));
-    content.push_str("PREFS_FILE=\"$BRAVE_DATA/Default/Preferences\"\n");
     content.push_str("LOCAL_STATE=\"$BRAVE_DATA/Local State\"\n\n");
+    add_backup_snapshot(content, plan.backup_retention());
+    add_json_writer(content);
     
-    // Create directories if they don't exist
-    content.push_str("mkdir -p \"$BRAVE_DATA/Default\"\n\n");
-    
-    // Backup existing files
-    content.push_str("[ -f \"$PREFS_FILE\" ] && cp \"$PREFS_FILE\" \"$PREFS_FILE.backup\"\n");
-    content.push_str("[ -f \"$LOCAL_STATE\" ] && cp \"$LOCAL_STATE\" \"$LOCAL_STATE.backup\"\n\n");
+    // Create directories and backup existing files
+    content.push_str("if [ \"$DRY_RUN\" -eq 0 ]; then\n");
+    content.push_str("    mkdir -p \"$BRAVE_DATA\"\n");
+    if plan.backs_up(UserFile::LocalState) {
+        content.push_str("    backup_file \"$LOCAL_STATE\"\n");
+    }
+    content.push_str("fi\n\n");
     
-    add_jq_installation_check(content);
-    add_preferences_modification(content, &search_provider, &dashboard_config);
-    add_local_state_modification(content, &experimental_features);
+    for (i, changes) in profile_changes.iter().enumerate() {
+        add_preferences_modification(content, i, changes, plan)?;
+    }
+    add_profile_enumeration(content);
+    add_profile_preferences_dispatch(content, &profile_changes);
+    if plan.updates_preference_macs() {
+        add_protected_preferences_warning(content);
+    }
+    add_local_state_modification(content, &plan.local_state_changes())?;
+    content.push_str("[ \"$DRY_RUN\" -eq 1 ] || rotate_backups\n");
     
-    content.push_str("echo -e \"${GREEN}User preferences applied successfully!${NC}\"\n");
+    content.push_str("[ \"$DRY_RUN\" -eq 1 ] || echo -e \"${GREEN}User preferences applied successfully!${NC}\"\n");
     content.push_str("echo\n\n");
     
     Ok(())
 }
 
+fn add_launch_flags(content: &mut String, version: &BraveVersion, launch_flags: &[&str]) {
+    if launch_flags.is_empty() {
+        return;
+    }
+
+    let app_name = get_macos_app_name(version);
+    let bundle_id = get_macos_bundle_id(version);
+
+    // macOS has no flags file, so install a wrapper app that execs Brave with the flags.
+    // The wrapper is rewritten on every run, which keeps the flags from being duplicated.
+    content.push_str("echo -e \"${GREEN}Installing launcher with launch flags...${NC}\"\n");
+    content.push_str(&format!("WRAPPER_APP=\"$HOME/Applications/{} (Debloated).app\"\n", app_name));
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo \"Launcher: $WRAPPER_APP\"\n");
+    for flag in launch_flags {
+        // Look for the flag exactly as it is quoted in the launcher
+        content.push_str(&format!(
+            "    grep -qF -- {} \"$WRAPPER_APP/Contents/MacOS/launcher\" 2>/dev/null && FLAG_STATE=present || FLAG_STATE=absent\n",
+            shell_quote(&shell_quote(flag))
+        ));
+        content.push_str(&format!("    print_change {} \"$FLAG_STATE\" present\n", shell_quote(flag)));
+    }
+    content.push_str("else\n");
+    content.push_str("mkdir -p \"$WRAPPER_APP/Contents/MacOS\"\n");
+    content.push_str("cat << 'EOF' > \"$WRAPPER_APP/Contents/MacOS/launcher\"\n");
+    content.push_str("#!/bin/zsh\n");
+    content.push_str(&format!("exec \"/Applications/{0}.app/Contents/MacOS/{0}\"", app_name));
+    for flag in launch_flags {
+        content.push_str(&format!(" {}", shell_quote(flag)));
+    }
+    content.push_str(" \"$@\"\n");
+    content.push_str("EOF\n");
+    content.push_str("chmod 755 \"$WRAPPER_APP/Contents/MacOS/launcher\"\n");
+    content.push_str("cat << 'EOF' > \"$WRAPPER_APP/Contents/Info.plist\"\n");
+    content.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    content.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+    content.push_str("<plist version=\"1.0\">\n<dict>\n");
+    content.push_str("    <key>CFBundleExecutable</key>\n    <string>launcher</string>\n");
+    content.push_str(&format!("    <key>CFBundleIdentifier</key>\n    <string>{}.debloated</string>\n", bundle_id));
+    content.push_str(&format!("    <key>CFBundleName</key>\n    <string>{} (Debloated)</string>\n", app_name));
+    content.push_str("    <key>CFBundlePackageType</key>\n    <string>APPL</string>\n");
+    content.push_str("</dict>\n</plist>\nEOF\n");
+    content.push_str("echo \"Launch Brave through: $WRAPPER_APP\"\n");
+    content.push_str("fi\n");
+    content.push_str("echo\n\n");
+}
+
+fn add_backup_snapshot(content: &mut String, retention: usize) {
+    // Each run copies the files it changes into a new timestamped snapshot, listed in manifest.txt
+    content.push_str(&format!("BACKUP_ROOT=\"$BRAVE_DATA/{}\"\n", BACKUP_DIR_NAME));
+    content.push_str(&format!("BACKUP_DIR=\"$BACKUP_ROOT/$(date +{})\"\n", BACKUP_TIMESTAMP_FORMAT));
+    content.push_str(&format!("BACKUP_RETENTION={}\n\n", retention));
+    content.push_str("backup_file() {\n");
+    content.push_str("    RELATIVE_PATH=\"${1#$BRAVE_DATA/}\"\n");
+    content.push_str("    [ -f \"$1\" ] && [ ! -f \"$BACKUP_DIR/$RELATIVE_PATH\" ] || return 0\n");
+    content.push_str("    mkdir -p \"$(dirname \"$BACKUP_DIR/$RELATIVE_PATH\")\"\n");
+    content.push_str("    cp -p \"$1\" \"$BACKUP_DIR/$RELATIVE_PATH\"\n");
+    content.push_str("    echo \"$RELATIVE_PATH\" >> \"$BACKUP_DIR/manifest.txt\"\n");
+    content.push_str("}\n\n");
+    content.push_str("rotate_backups() {\n");
+    content.push_str("    [ -d \"$BACKUP_DIR\" ] && echo \"Backup saved to: $BACKUP_DIR\"\n");
+    // The oldest snapshot holds the files from before the first run and is never removed
+    content.push_str("    ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort | tail -n +2 | sort -r | tail -n +$((BACKUP_RETENTION + 1)) | while IFS= read -r SNAPSHOT; do\n");
+    content.push_str("        rm -rf \"${BACKUP_ROOT:?}/$SNAPSHOT\"\n");
+    content.push_str("    done\n");
+    content.push_str("}\n\n");
+}
+
+fn add_json_writer(content: &mut String) {
+    // Write to a temporary file next to the target and only rename it over the original once it is valid JSON
+    content.push_str("write_json_file() {\n");
+    content.push_str("    local TARGET=\"$1\" JSON=\"$2\" TMP_FILE\n");
+    content.push_str("    if ! TMP_FILE=$(mktemp \"$TARGET.XXXXXX\"); then\n");
+    content.push_str("        echo -e \"${RED}Error: could not create a temporary file for $TARGET, it was left untouched${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("    if [ -z \"$JSON\" ] || ! printf '%s\\n' \"$JSON\" > \"$TMP_FILE\" || [ ! -s \"$TMP_FILE\" ] || ! jq -e 'type == \"object\"' \"$TMP_FILE\" &> /dev/null; then\n");
+    content.push_str("        rm -f \"$TMP_FILE\"\n");
+    content.push_str("        echo -e \"${RED}Error: generated invalid JSON for $TARGET, it was left untouched${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("    if [ -f \"$TARGET\" ]; then\n");
+    content.push_str("        chown \"$(stat -f '%u:%g' \"$TARGET\")\" \"$TMP_FILE\" 2>/dev/null\n");
+    content.push_str("        chmod \"$(stat -f '%Lp' \"$TARGET\")\" \"$TMP_FILE\"\n");
+    content.push_str("    fi\n");
+    content.push_str("    if ! mv -f \"$TMP_FILE\" \"$TARGET\"; then\n");
+    content.push_str("        rm -f \"$TMP_FILE\"\n");
+    content.push_str("        echo -e \"${RED}Error: could not replace $TARGET, it was left untouched${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("}\n\n");
+}
+
+fn add_snapshot_selection(content: &mut String) {
+    // Use the snapshot given with --restore, or the oldest one, which holds the files from before the first run
+    content.push_str(&format!("BACKUP_ROOT=\"$BRAVE_DATA/{}\"\n", BACKUP_DIR_NAME));
+    content.push_str(&format!(
+        "if [ -n \"$RESTORE\" ] && [ \"$RESTORE\" != {} ] && [ ! -f \"$BACKUP_ROOT/$RESTORE/manifest.txt\" ]; then\n",
+        LATEST_SNAPSHOT
+    ));
+    content.push_str("    echo -e \"${RED}Error: no backup snapshot '$RESTORE' in $BACKUP_ROOT${NC}\"\n");
+    content.push_str("    echo \"Available snapshots:\"\n");
+    content.push_str("    ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort -r | sed 's/^/  /'\n");
+    content.push_str("    exit 1\n");
+    content.push_str("fi\n");
+    content.push_str("case \"$RESTORE\" in\n");
+    content.push_str("    \"\") RESTORE=$(ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort | head -n 1) ;;\n");
+    content.push_str(&format!("    {}) RESTORE=$(ls -1 \"$BACKUP_ROOT\" 2>/dev/null | sort -r | head -n 1) ;;\n", LATEST_SNAPSHOT));
+    content.push_str("esac\n");
+    content.push_str("SNAPSHOT=\"$BACKUP_ROOT/$RESTORE\"\n\n");
+}
+
+fn add_snapshot_restore(content: &mut String) {
+    content.push_str("if [ -n \"$RESTORE\" ] && [ -f \"$SNAPSHOT/manifest.txt\" ]; then\n");
+    content.push_str("    echo \"Restoring backup snapshot: $RESTORE\"\n");
+    content.push_str("    while IFS= read -r RELATIVE_PATH; do\n");
+    content.push_str("        if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("            echo \"Would restore $BRAVE_DATA/$RELATIVE_PATH\"\n");
+    content.push_str("            continue\n");
+    content.push_str("        fi\n");
+    content.push_str("        cp -p \"$SNAPSHOT/$RELATIVE_PATH\" \"$BRAVE_DATA/$RELATIVE_PATH\"\n");
+    content.push_str("        echo \"Restored $BRAVE_DATA/$RELATIVE_PATH\"\n");
+    content.push_str("    done < \"$SNAPSHOT/manifest.txt\"\n");
+    content.push_str("else\n");
+    content.push_str("    echo -e \"${YELLOW}No backup snapshot found in $BACKUP_ROOT${NC}\"\n");
+    content.push_str("fi\n");
+    content.push_str("echo\n\n");
+}
+
 fn add_jq_installation_check(content: &mut String) {
     content.push_str("if ! command -v jq &> /dev/null; then\n");
+    content.push_str("    if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("        echo -e \"${RED}Error: jq is required for --dry-run and --check${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
     content.push_str("    echo -e \"${YELLOW}jq not found. Installing via Homebrew...${NC}\"\n");
     content.push_str("    if command -v brew &> /dev/null; then\n");
     content.push_str("        brew install jq\n");
@@ -174,92 +523,192 @@ fn add_jq_installation_check(content: &mut String) {
     content.push_str("fi\n\n");
 }
 
-fn add_preferences_modification(content: &mut String, search_provider: &crate::preferences::SearchProvider, dashboard_config: &crate::preferences::NewTabPage) {
-    // Modify preferences file
-    content.push_str("# Create or modify Preferences file\n");
+fn add_protected_preferences_warning(content: &mut String) {
+    let protected_preferences = get_protected_preferences(MODIFIED_PREFERENCES);
+    if protected_preferences.is_empty() {
+        return;
+    }
+
+    // The MACs in protection.macs are keyed to a macOS device ID we cannot derive here,
+    // so Brave will detect these edits and restore the previous values.
+    content.push_str("echo -e \"${YELLOW}Note: Brave protects the following preferences and will revert changes to them:${NC}\"\n");
+    for path in &protected_preferences {
+        content.push_str(&format!("echo \"  - {}\"\n", path));
+    }
+    content.push_str("echo\n\n");
+}
+
+fn add_profile_enumeration(content: &mut String) {
+    // Profiles known to Brave are listed in Local State, fall back to scanning the data directory
+    content.push_str("# Enumerate browser profiles\n");
+    content.push_str("PROFILES=()\n");
+    content.push_str("if [ -f \"$LOCAL_STATE\" ]; then\n");
+    content.push_str("    while IFS= read -r PROFILE; do\n");
+    content.push_str("        [ -n \"$PROFILE\" ] && PROFILES+=(\"$PROFILE\")\n");
+    content.push_str("    done < <(jq -r '.profile.info_cache // {} | keys[]' \"$LOCAL_STATE\" 2>/dev/null)\n");
+    content.push_str("fi\n");
+    content.push_str("if [ ${#PROFILES[@]} -eq 0 ]; then\n");
+    content.push_str("    while IFS= read -r PROFILE_DIR; do\n");
+    content.push_str("        PROFILES+=(\"$(basename \"$PROFILE_DIR\")\")\n");
+    content.push_str("    done < <(find \"$BRAVE_DATA\" -mindepth 1 -maxdepth 1 -type d \\( -name Default -o -name 'Profile *' \\) 2>/dev/null)\n");
+    content.push_str("fi\n");
+    content.push_str("[ ${#PROFILES[@]} -eq 0 ] && PROFILES=(\"Default\")\n\n");
+}
+
+fn add_profile_preferences_dispatch(content: &mut String, profile_changes: &[ProfileChanges]) {
+    content.push_str("for PROFILE in \"${PROFILES[@]}\"; do\n");
+    content.push_str("    PROFILE_NAME=$(jq -r --arg profile \"$PROFILE\" '.profile.info_cache[$profile].name // empty' \"$LOCAL_STATE\" 2>/dev/null)\n");
+    content.push_str("    PREFS_FILE=\"$BRAVE_DATA/$PROFILE/Preferences\"\n");
+
+    if let [ProfileChanges { profile: None, .. }] = profile_changes {
+        content.push_str("    echo \"Applying preferences to profile: $PROFILE\"\n");
+        content.push_str("    apply_preferences_0\n");
+    } else {
+        for (i, changes) in profile_changes.iter().enumerate() {
+            let keyword = if i == 0 { "if" } else { "elif" };
+            let profile = shell_quote(changes.profile.unwrap_or_default());
+            content.push_str(&format!("    {} [ \"$PROFILE\" = {} ] || [ \"$PROFILE_NAME\" = {} ]; then\n", keyword, profile, profile));
+            content.push_str("        echo \"Applying preferences to profile: $PROFILE\"\n");
+            content.push_str(&format!("        apply_preferences_{}\n", i));
+        }
+        content.push_str("    else\n");
+        content.push_str("        echo \"Skipping profile: $PROFILE\"\n");
+        content.push_str("    fi\n");
+    }
+
+    content.push_str("done\n\n");
+}
+
+fn add_preferences_modification(content: &mut String, index: usize, changes: &ProfileChanges, plan: &Plan) -> Result<(), DebloaterError> {
+    // Modify the Preferences file of the profile in $PREFS_FILE
+    content.push_str(&format!("apply_preferences_{}() {{\n", index));
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo \"Preferences file: $PREFS_FILE\"\n");
+    for (path, value) in &changes.preferences {
+        content.push_str(&format!("    show_json_change \"$PREFS_FILE\" {} {}\n", shell_quote(path), shell_quote(&value.to_string())));
+    }
+    content.push_str("    return\n");
+    content.push_str("fi\n\n");
+    content.push_str("mkdir -p \"$(dirname \"$PREFS_FILE\")\"\n");
+    if plan.backs_up(UserFile::Preferences) {
+        content.push_str("backup_file \"$PREFS_FILE\"\n");
+    }
+    content.push('\n');
     content.push_str("if [ -f \"$PREFS_FILE\" ]; then\n");
     content.push_str("    PREFS_JSON=$(cat \"$PREFS_FILE\")\n");
     content.push_str("else\n");
     content.push_str("    PREFS_JSON='{}'\n");
-    content.push_str("fi\n\n");
-    
-    // Update preferences using jq
-    content.push_str("PREFS_JSON=$(echo \"$PREFS_JSON\" | jq '\n");
-    
-    // Search provider
-    content.push_str("  .default_search_provider_data = {\n");
-    content.push_str(&format!("    \"keyword\": \"{}\",\n", search_provider.keyword));
-    content.push_str(&format!("    \"name\": \"{}\",\n", search_provider.name));
-    content.push_str(&format!("    \"search_url\": \"{}\"\n", search_provider.search_url));
-    content.push_str("  } |\n");
-    
-    // Brave preferences
-    content.push_str("  .brave = (.brave // {}) |\n");
-    content.push_str("  .brave.new_tab_page = (.brave.new_tab_page // {}) |\n");
-    content.push_str("  .brave.stats = (.brave.stats // {}) |\n");
-    content.push_str("  .brave.today = (.brave.today // {}) |\n");
-    
-    // Dashboard settings
-    add_dashboard_settings_jq(content, dashboard_config);
-    
-    content.push_str("  .brave.stats.enabled = false |\n");
-    content.push_str("  .brave.today.should_show_brave_today_widget = false\n");
-    content.push_str("')\n\n");
-    
-    content.push_str("echo \"$PREFS_JSON\" > \"$PREFS_FILE\"\n\n");
+    content.push_str("fi\n");
+    content.push_str(&format!("PREFS_JSON=$(echo \"$PREFS_JSON\" | {})\n", jq_set_paths(&changes.preferences)?));
+    content.push_str("write_json_file \"$PREFS_FILE\" \"$PREFS_JSON\"\n");
+    content.push_str("}\n\n");
+
+    Ok(())
 }
 
-fn add_dashboard_settings_jq(content: &mut String, dashboard_config: &crate::preferences::NewTabPage) {
-    if let Some(show_clock) = dashboard_config.show_clock {
-        content.push_str(&format!("  .brave.new_tab_page.show_clock = {} |\n", show_clock.to_string().to_lowercase()));
-    }
-    if let Some(show_bg) = dashboard_config.show_background_image {
-        content.push_str(&format!("  .brave.new_tab_page.show_background_image = {} |\n", show_bg.to_string().to_lowercase()));
-    }
-    if let Some(show_stats) = dashboard_config.show_stats {
-        content.push_str(&format!("  .brave.new_tab_page.show_stats = {} |\n", show_stats.to_string().to_lowercase()));
-    }
-    if let Some(show_shortcuts) = dashboard_config.show_shortcuts {
-        content.push_str(&format!("  .brave.new_tab_page.show_shortcuts = {} |\n", show_shortcuts.to_string().to_lowercase()));
-    }
-    if let Some(show_branded) = dashboard_config.show_branded_background_image {
-        content.push_str(&format!("  .brave.new_tab_page.show_branded_background_image = {} |\n", show_branded.to_string().to_lowercase()));
-    }
-    if let Some(show_cards) = dashboard_config.show_cards {
-        content.push_str(&format!("  .brave.new_tab_page.show_cards = {} |\n", show_cards.to_string().to_lowercase()));
-    }
-    if let Some(show_search) = dashboard_config.show_search_widget {
-        content.push_str(&format!("  .brave.new_tab_page.show_search_widget = {} |\n", show_search.to_string().to_lowercase()));
-    }
-    if let Some(show_news) = dashboard_config.show_brave_news {
-        content.push_str(&format!("  .brave.new_tab_page.show_brave_news = {} |\n", show_news.to_string().to_lowercase()));
+/// Builds a jq command that sets each dotted path to its value. The changes are passed with
+/// --argjson so config values are never parsed as part of the jq program.
+fn jq_set_paths(changes: &[(&str, &Value)]) -> Result<String, DebloaterError> {
+    let changes: Vec<(Vec<&str>, &Value)> = changes.iter().map(|(path, value)| (path.split('.').collect(), *value)).collect();
+    Ok(format!(
+        "jq --argjson changes {} 'reduce $changes[] as $change (.; setpath($change[0]; $change[1]))'",
+        shell_quote(&serde_json::to_string(&changes)?)
+    ))
+}
+
+fn add_local_state_modification(content: &mut String, changes: &[(&str, &Value)]) -> Result<(), DebloaterError> {
+    content.push_str("if [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo \"Local State file: $LOCAL_STATE\"\n");
+    for (path, value) in changes {
+        content.push_str(&format!("    show_json_change \"$LOCAL_STATE\" {} {}\n", shell_quote(path), shell_quote(&value.to_string())));
     }
-    if let Some(show_together) = dashboard_config.show_together {
-        content.push_str(&format!("  .brave.new_tab_page.show_together = {} |\n", show_together.to_string().to_lowercase()));
+    content.push_str("else\n");
+
+    // Handle Local State file
+    content.push_str("    # Create or modify Local State file\n");
+    content.push_str("    if [ -f \"$LOCAL_STATE\" ]; then\n");
+    content.push_str("        LOCAL_JSON=$(cat \"$LOCAL_STATE\")\n");
+    content.push_str("    else\n");
+    content.push_str("        LOCAL_JSON='{}'\n");
+    content.push_str("    fi\n");
+    content.push_str(&format!("    LOCAL_JSON=$(echo \"$LOCAL_JSON\" | {})\n", jq_set_paths(changes)?));
+    content.push_str("    write_json_file \"$LOCAL_STATE\" \"$LOCAL_JSON\"\n");
+    content.push_str("fi\n");
+
+    Ok(())
+}
+
+/// Parses `--dry-run`, plus `--check` for debloat scripts or `--restore <timestamp|latest>` for revert scripts.
+/// Check mode implies dry run.
+fn add_script_options(content: &mut String, revert: bool) {
+    content.push_str("DRY_RUN=0\n");
+    content.push_str("CHECK=0\n");
+    content.push_str("FAILURES=0\n");
+    content.push_str("RESTORE=\"\"\n");
+    content.push_str("while [ $# -gt 0 ]; do\n");
+    content.push_str("    case \"$1\" in\n");
+    content.push_str("        --dry-run) DRY_RUN=1 ;;\n");
+    if revert {
+        content.push_str("        --restore) RESTORE=\"$2\"; shift ;;\n");
+    } else {
+        content.push_str("        --check) CHECK=1; DRY_RUN=1 ;;\n");
     }
+    content.push_str("    esac\n");
+    content.push_str("    shift\n");
+    content.push_str("done\n");
+    content.push_str("if [ \"$CHECK\" -eq 1 ]; then\n");
+    content.push_str("    echo -e \"${YELLOW}Check: comparing the current configuration against the generated one${NC}\"\n");
+    content.push_str("    echo\n");
+    content.push_str("elif [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo -e \"${YELLOW}Dry run: showing changes without applying them${NC}\"\n");
+    content.push_str("    echo\n");
+    content.push_str("fi\n\n");
 }
 
-fn add_local_state_modification(content: &mut String, experimental_features: &[String]) {
-    // Handle Local State file
-    content.push_str("# Create or modify Local State file\n");
-    content.push_str("if [ -f \"$LOCAL_STATE\" ]; then\n");
-    content.push_str("    LOCAL_JSON=$(cat \"$LOCAL_STATE\")\n");
+fn add_completion_message(content: &mut String, message: &str) {
+    content.push_str("if [ \"$CHECK\" -eq 1 ]; then\n");
+    content.push_str("    echo\n");
+    content.push_str("    if [ \"$FAILURES\" -gt 0 ]; then\n");
+    content.push_str("        echo -e \"${RED}$FAILURES setting(s) differ from the generated configuration${NC}\"\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    fi\n");
+    content.push_str("    echo -e \"${GREEN}All settings match the generated configuration${NC}\"\n");
+    content.push_str("elif [ \"$DRY_RUN\" -eq 1 ]; then\n");
+    content.push_str("    echo -e \"${GREEN}Dry run complete, no changes were made.${NC}\"\n");
     content.push_str("else\n");
-    content.push_str("    LOCAL_JSON='{}'\n");
-    content.push_str("fi\n\n");
-    
-    content.push_str("LOCAL_JSON=$(echo \"$LOCAL_JSON\" | jq '\n");
-    content.push_str("  .browser = (.browser // {}) |\n");
-    content.push_str("  .browser.enabled_labs_experiments = [\n");
-    for (i, feature) in experimental_features.iter().enumerate() {
-        content.push_str(&format!("    \"{}\"", feature));
-        if i < experimental_features.len() - 1 {
-            content.push_str(",");
-        }
-        content.push_str("\n");
-    }
-    content.push_str("  ]\n");
-    content.push_str("')\n\n");
-    
-    content.push_str("echo \"$LOCAL_JSON\" > \"$LOCAL_STATE\"\n");
-}
\ No newline at end of file
+    content.push_str(&format!("    echo -e \"${{GREEN}}{}${{NC}}\"\n", message));
+    content.push_str("    echo -e \"${GREEN}Please restart Brave browser for changes to take effect.${NC}\"\n");
+    content.push_str("fi\n");
+}
+
+fn add_change_preview(content: &mut String) {
+    // Print the current and new value of a dotted path, both as compact JSON, or PASS/FAIL in check mode
+    content.push_str("print_change() {\n");
+    content.push_str("    [ -n \"$2\" ] && CURRENT=\"$2\" || CURRENT=null\n");
+    content.push_str("    if [ \"$CHECK\" -eq 1 ]; then\n");
+    content.push_str("        if [ \"$CURRENT\" = \"$3\" ]; then\n");
+    content.push_str("            echo -e \"    ${GREEN}PASS${NC}  $1\"\n");
+    content.push_str("        else\n");
+    content.push_str("            echo -e \"    ${RED}FAIL${NC}  $1: $CURRENT (expected $3)\"\n");
+    content.push_str("            FAILURES=$((FAILURES + 1))\n");
+    content.push_str("        fi\n");
+    content.push_str("    elif [ \"$CURRENT\" = \"$3\" ]; then\n");
+    content.push_str("        echo \"    $1: $CURRENT (unchanged)\"\n");
+    content.push_str("    else\n");
+    content.push_str("        echo \"    $1: $CURRENT -> $3\"\n");
+    content.push_str("    fi\n");
+    content.push_str("}\n\n");
+    content.push_str("show_json_change() {\n");
+    content.push_str("    print_change \"$2\" \"$(jq -c --arg path \"$2\" 'getpath($path | split(\".\"))' \"$1\" 2>/dev/null)\" \"$3\"\n");
+    content.push_str("}\n\n");
+    content.push_str("show_plist_change() {\n");
+    content.push_str("    print_change \"$2\" \"$(plutil -extract \"$2\" json -o - \"$1\" 2>/dev/null | jq -c . 2>/dev/null)\" \"$3\"\n");
+    content.push_str("}\n\n");
+    content.push_str("verify_policy() {\n");
+    content.push_str("    ACTUAL=\"$(plutil -extract \"$2\" json -o - \"$1\" 2>/dev/null | jq -c . 2>/dev/null)\"\n");
+    content.push_str("    if [ \"$ACTUAL\" != \"$3\" ]; then\n");
+    content.push_str("        echo -e \"    ${RED}FAIL${NC}  $2: ${ACTUAL:-(not set)} (expected $3)\"\n");
+    content.push_str("        POLICY_ERRORS=$((POLICY_ERRORS + 1))\n");
+    content.push_str("    fi\n");
+    content.push_str("}\n\n");
+}
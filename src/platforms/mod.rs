@@ -1,14 +1,23 @@
 pub mod windows;
 pub mod macos;
 pub mod linux;
+pub mod escape;
+pub mod registry_pol;
+pub mod nix;
+pub mod ansible;
+pub mod package;
 
-use crate::cli::BraveVersion;
-use crate::config::{Config, Extension};
+use sha2::{Digest, Sha256};
+
+use crate::cli::{BraveVersion, Platform};
 use crate::error::DebloaterError;
-use crate::preferences::PreferencesInputConfig;
+use crate::plan::Plan;
 
+/// Renders a [`Plan`] as scripts for one platform.
 pub trait PlatformGenerator {
-    fn generate_unified_script(&self, config: &Config, extensions: &[Extension], version: &BraveVersion, output_dir: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError>;
+    fn generate_unified_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError>;
+    /// Generates a script that undoes exactly what the unified script applies.
+    fn generate_revert_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError>;
 }
 
 pub fn get_brave_registry_path(version: &BraveVersion) -> &'static str {
@@ -25,6 +34,13 @@ pub fn get_macos_bundle_id(version: &BraveVersion) -> &'static str {
     }
 }
 
+pub fn get_macos_app_name(version: &BraveVersion) -> &'static str {
+    match version {
+        BraveVersion::Normal => "Brave Browser",
+        BraveVersion::Nightly => "Brave Browser Nightly",
+    }
+}
+
 pub fn get_linux_policy_path(version: &BraveVersion) -> &'static str {
     match version {
         BraveVersion::Normal => "/etc/brave/policies/managed/brave.json",
@@ -32,9 +48,82 @@ pub fn get_linux_policy_path(version: &BraveVersion) -> &'static str {
     }
 }
 
+/// Install directory of the official `.deb`/`.rpm` packages.
+pub fn get_linux_native_install_dir(version: &BraveVersion) -> &'static str {
+    match version {
+        BraveVersion::Normal => "/opt/brave.com/brave",
+        BraveVersion::Nightly => "/opt/brave.com/brave-nightly",
+    }
+}
+
+/// Launcher installed on the PATH by the native packages.
+pub fn get_linux_native_binary(version: &BraveVersion) -> &'static str {
+    match version {
+        BraveVersion::Normal => "brave-browser",
+        BraveVersion::Nightly => "brave-browser-nightly",
+    }
+}
+
+/// Snap and Flatpak only publish the release channel, so Nightly is always a native install.
+pub fn has_linux_sandboxed_packages(version: &BraveVersion) -> bool {
+    matches!(version, BraveVersion::Normal)
+}
+
+pub const LINUX_SNAP_NAME: &str = "brave";
+
+pub const LINUX_FLATPAK_APP_ID: &str = "com.brave.Browser";
+
+/// Flatpak extension mounted as the managed policy directory of the Brave Flatpak. It is read from
+/// `<flatpak installation>/extension/<extension>/<arch>/1`.
+pub const LINUX_FLATPAK_POLICY_EXTENSION: &str = "com.brave.Browser.Policy.system-policies";
+
+/// Policy file inside the Snap data directory (`/var/snap/brave/current`) or the Flatpak policy extension.
+pub const LINUX_SANDBOX_POLICY_FILE: &str = "policies/managed/brave.json";
+
+/// Where Brave looks for the `initial_preferences` file it seeds new profiles from. Windows and Linux read it
+/// next to the browser binary, macOS from the user's Application Support directory.
+pub fn get_initial_preferences_path(platform: &Platform, version: &BraveVersion) -> String {
+    match platform {
+        Platform::Windows => format!("C:\\Program Files\\BraveSoftware\\{}\\Application\\initial_preferences", get_version_suffix(version)),
+        Platform::MacOS => format!(
+            "~/Library/Application Support/BraveSoftware/{}/{} Initial Preferences",
+            get_version_suffix(version),
+            get_macos_app_name(version)
+        ),
+        Platform::Linux => format!("{}/initial_preferences", get_linux_native_install_dir(version)),
+    }
+}
+
+/// Directory inside the user data directory holding the timestamped backup snapshots.
+pub const BACKUP_DIR_NAME: &str = "Debloater Backups";
+
+/// Name of a backup snapshot directory, in `date`/strftime syntax.
+pub const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Name passed to the revert scripts' restore option for the newest snapshot. Without it they restore the
+/// oldest one, which holds the files from before the first run.
+pub const LATEST_SNAPSHOT: &str = "latest";
+
+pub fn get_linux_flags_file(version: &BraveVersion) -> &'static str {
+    match version {
+        BraveVersion::Normal => "brave-flags.conf",
+        BraveVersion::Nightly => "brave-nightly-flags.conf",
+    }
+}
+
 pub fn get_version_suffix(version: &BraveVersion) -> &'static str {
     match version {
         BraveVersion::Normal => "Brave-Browser",
         BraveVersion::Nightly => "Brave-Browser-Nightly",
     }
-}
\ No newline at end of file
+}
+
+/// Derives a UUID from `name`, so regenerated files keep the same identifiers.
+pub fn stable_uuid(name: &str) -> String {
+    let mut bytes: [u8; 16] = Sha256::digest(name.as_bytes())[..16].try_into().expect("SHA-256 digests are 32 bytes");
+    // Mark it as a name-based UUID of the RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
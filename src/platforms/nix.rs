@@ -0,0 +1,64 @@
+use serde_json::Value;
+
+use crate::config::ConfigValue;
+use crate::plan::Plan;
+use crate::platforms::escape::nix_quote;
+
+/// Update URL of the Chrome Web Store, used for forced extensions that do not name their own.
+pub const WEB_STORE_UPDATE_URL: &str = "https://clients2.google.com/service/update2/crx";
+
+/// Renders a JSON value as a Nix expression. Nested lists and attribute sets are indented from `indent` levels.
+pub fn nix_value(value: &Value, indent: usize) -> String {
+    let padding = "  ".repeat(indent);
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        // A leading minus would be parsed as subtraction inside a list
+        Value::Number(n) if n.to_string().starts_with('-') => format!("({})", n),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => nix_quote(s),
+        Value::Array(items) if items.is_empty() => "[ ]".to_string(),
+        Value::Array(items) => {
+            let mut content = String::from("[\n");
+            for item in items {
+                content.push_str(&format!("{}  {}\n", padding, nix_value(item, indent + 1)));
+            }
+            content.push_str(&format!("{}]", padding));
+            content
+        }
+        Value::Object(map) if map.is_empty() => "{ }".to_string(),
+        Value::Object(map) => {
+            let mut content = String::from("{\n");
+            for (key, value) in map {
+                content.push_str(&format!("{}  {} = {};\n", padding, nix_attr_name(key), nix_value(value, indent + 1)));
+            }
+            content.push_str(&format!("{}}}", padding));
+            content
+        }
+    }
+}
+
+/// Returns the name as is when it is a plain Nix identifier, and quoted otherwise.
+pub fn nix_attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'));
+    if is_identifier && !matches!(name, "assert" | "else" | "if" | "in" | "inherit" | "let" | "or" | "rec" | "then" | "with") {
+        name.to_string()
+    } else {
+        nix_quote(name)
+    }
+}
+
+/// Splits the `ExtensionInstallForcelist` entries (`id` or `id;update URL`) into ID and update URL.
+pub fn get_forced_extensions(plan: &Plan) -> Vec<(&str, &str)> {
+    plan.policies()
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            ConfigValue::StringArray(items) if key == "ExtensionInstallForcelist" => Some(items),
+            _ => None,
+        })
+        .flatten()
+        .map(|item| item.split_once(';').unwrap_or((item, WEB_STORE_UPDATE_URL)))
+        .collect()
+}
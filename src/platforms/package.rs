@@ -0,0 +1,433 @@
+use sha2::{Digest, Sha256};
+
+use crate::error::DebloaterError;
+
+/// A regular file installed by a package, at an absolute path.
+#[derive(Debug, Clone)]
+pub struct PackageFile {
+    pub path: String,
+    pub mode: u32,
+    pub content: Vec<u8>,
+}
+
+/// Which Linux packages to build, and their version and maintainer.
+#[derive(Debug, Clone, Default)]
+pub struct PackageOptions {
+    pub deb: bool,
+    pub rpm: bool,
+    pub version: String,
+    pub maintainer: String,
+}
+
+/// Metadata shared by the `.deb` and `.rpm` packages.
+#[derive(Debug, Clone)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub maintainer: String,
+    /// Package that cannot be installed at the same time.
+    pub conflicts: String,
+    /// Package the files are meant for.
+    pub enhances: String,
+    pub summary: String,
+    pub description: String,
+    /// Modification time of every file and the build time, in seconds since the epoch.
+    pub mtime: u64,
+}
+
+/// Release of the generated `.rpm`, the version is taken from [`PackageInfo`] as is.
+pub const RPM_RELEASE: &str = "1";
+
+/// Checks that the version is valid for both dpkg and rpm and that no field breaks the control file.
+pub fn validate_package_info(info: &PackageInfo) -> Result<(), DebloaterError> {
+    let mut chars = info.version.chars();
+    let valid_version = chars.next().is_some_and(|c| c.is_ascii_digit())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '~'));
+    if !valid_version {
+        return Err(DebloaterError::InvalidPackageField(format!(
+            "version {:?} must start with a digit and contain only letters, digits, '.', '+' and '~'",
+            info.version
+        )));
+    }
+    if info.maintainer.is_empty() || info.maintainer.chars().any(char::is_control) {
+        return Err(DebloaterError::InvalidPackageField(format!("maintainer {:?} must be a single line", info.maintainer)));
+    }
+    Ok(())
+}
+
+pub fn get_deb_filename(info: &PackageInfo) -> String {
+    format!("{}_{}_all.deb", info.name, info.version)
+}
+
+pub fn get_rpm_filename(info: &PackageInfo) -> String {
+    format!("{}-{}-{}.noarch.rpm", info.name, info.version, RPM_RELEASE)
+}
+
+/// Builds a `.deb`: an `ar` archive of `debian-binary`, `control.tar.gz` and `data.tar.gz`.
+pub fn build_deb(info: &PackageInfo, files: &[PackageFile]) -> Vec<u8> {
+    let installed_size = files.iter().map(|file| file.content.len().div_ceil(1024)).sum::<usize>();
+    let mut control = String::new();
+    control.push_str(&format!("Package: {}\n", info.name));
+    control.push_str(&format!("Version: {}\n", info.version));
+    control.push_str("Architecture: all\n");
+    control.push_str(&format!("Maintainer: {}\n", info.maintainer));
+    control.push_str(&format!("Installed-Size: {}\n", installed_size));
+    control.push_str(&format!("Conflicts: {}\n", info.conflicts));
+    control.push_str(&format!("Enhances: {}\n", info.enhances));
+    control.push_str("Section: web\n");
+    control.push_str("Priority: optional\n");
+    control.push_str(&format!("Description: {}\n", info.summary));
+    control.push_str(&format!(" {}\n", info.description));
+
+    let mut control_files = vec![PackageFile { path: "/control".to_string(), mode: 0o644, content: control.into_bytes() }];
+    let conffiles: String = files.iter().filter(|file| is_config_file(file)).map(|file| format!("{}\n", file.path)).collect();
+    if !conffiles.is_empty() {
+        control_files.push(PackageFile { path: "/conffiles".to_string(), mode: 0o644, content: conffiles.into_bytes() });
+    }
+    let mut deb = Vec::from(*b"!<arch>\n");
+    add_ar_member(&mut deb, "debian-binary", b"2.0\n", info.mtime);
+    add_ar_member(&mut deb, "control.tar.gz", &gzip_stored(&tar_archive(&control_files, info.mtime)), info.mtime);
+    add_ar_member(&mut deb, "data.tar.gz", &gzip_stored(&tar_archive(files, info.mtime)), info.mtime);
+    deb
+}
+
+/// Files under `/etc` are configuration, which the package managers keep when the administrator edited them.
+fn is_config_file(file: &PackageFile) -> bool {
+    file.path.starts_with("/etc/")
+}
+
+fn add_ar_member(archive: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) {
+    archive.extend(format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, mtime, 0, 0, "100644", data.len()).into_bytes());
+    archive.extend(data);
+    if data.len() % 2 == 1 {
+        archive.push(b'\n');
+    }
+}
+
+/// Writes the files as a ustar archive with `./` paths, preceded by entries for their parent directories.
+fn tar_archive(files: &[PackageFile], mtime: u64) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directories: Vec<String> = vec!["./".to_string()];
+    for file in files {
+        let components: Vec<&str> = file.path.trim_start_matches('/').split('/').collect();
+        for depth in 1..components.len() {
+            let directory = format!("./{}/", components[..depth].join("/"));
+            if !directories.contains(&directory) {
+                directories.push(directory);
+            }
+        }
+    }
+    for directory in &directories {
+        archive.extend(tar_header(directory, 0o755, 0, mtime, b'5'));
+    }
+    for file in files {
+        archive.extend(tar_header(&format!(".{}", file.path), file.mode, file.content.len(), mtime, b'0'));
+        archive.extend(&file.content);
+        archive.resize(archive.len().next_multiple_of(512), 0);
+    }
+    // End of archive marker
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+fn tar_header(name: &str, mode: u32, size: usize, mtime: u64, typeflag: u8) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    let mut set = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    set(0, name.as_bytes());
+    set(100, format!("{:07o}\0", mode).as_bytes());
+    set(108, b"0000000\0");
+    set(116, b"0000000\0");
+    set(124, format!("{:011o}\0", size).as_bytes());
+    set(136, format!("{:011o}\0", mtime).as_bytes());
+    set(148, b"        ");
+    set(156, &[typeflag]);
+    set(257, b"ustar\x0000");
+    set(265, b"root");
+    set(297, b"root");
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Wraps the data in the gzip format using stored deflate blocks. The files are small, so they are not compressed.
+fn gzip_stored(data: &[u8]) -> Vec<u8> {
+    let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        gzip.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let length = block.len() as u16;
+        gzip.push(blocks.peek().is_none() as u8);
+        gzip.extend(length.to_le_bytes());
+        gzip.extend((!length).to_le_bytes());
+        gzip.extend(block);
+    }
+    gzip.extend(crc32(data).to_le_bytes());
+    gzip.extend((data.len() as u32).to_le_bytes());
+    gzip
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Value of an RPM header entry.
+enum RpmValue {
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    String(String),
+    StringArray(Vec<String>),
+    I18nString(String),
+}
+
+const RPMTAG_HEADERSIGNATURES: u32 = 62;
+const RPMTAG_HEADERIMMUTABLE: u32 = 63;
+const RPMTAG_HEADERI18NTABLE: u32 = 100;
+const RPMTAG_NAME: u32 = 1000;
+const RPMTAG_VERSION: u32 = 1001;
+const RPMTAG_RELEASE: u32 = 1002;
+const RPMTAG_SUMMARY: u32 = 1004;
+const RPMTAG_DESCRIPTION: u32 = 1005;
+const RPMTAG_BUILDTIME: u32 = 1006;
+const RPMTAG_SIZE: u32 = 1009;
+const RPMTAG_LICENSE: u32 = 1014;
+const RPMTAG_PACKAGER: u32 = 1015;
+const RPMTAG_GROUP: u32 = 1016;
+const RPMTAG_OS: u32 = 1021;
+const RPMTAG_ARCH: u32 = 1022;
+const RPMTAG_FILESIZES: u32 = 1028;
+const RPMTAG_FILEMODES: u32 = 1030;
+const RPMTAG_FILERDEVS: u32 = 1033;
+const RPMTAG_FILEMTIMES: u32 = 1034;
+const RPMTAG_FILEDIGESTS: u32 = 1035;
+const RPMTAG_FILELINKTOS: u32 = 1036;
+const RPMTAG_FILEFLAGS: u32 = 1037;
+const RPMTAG_FILEUSERNAME: u32 = 1039;
+const RPMTAG_FILEGROUPNAME: u32 = 1040;
+const RPMTAG_SOURCERPM: u32 = 1044;
+const RPMTAG_FILEVERIFYFLAGS: u32 = 1045;
+const RPMTAG_PROVIDENAME: u32 = 1047;
+const RPMTAG_REQUIREFLAGS: u32 = 1048;
+const RPMTAG_REQUIRENAME: u32 = 1049;
+const RPMTAG_REQUIREVERSION: u32 = 1050;
+const RPMTAG_CONFLICTFLAGS: u32 = 1053;
+const RPMTAG_CONFLICTNAME: u32 = 1054;
+const RPMTAG_CONFLICTVERSION: u32 = 1055;
+const RPMTAG_FILEDEVICES: u32 = 1095;
+const RPMTAG_FILEINODES: u32 = 1096;
+const RPMTAG_FILELANGS: u32 = 1097;
+const RPMTAG_PROVIDEFLAGS: u32 = 1112;
+const RPMTAG_PROVIDEVERSION: u32 = 1113;
+const RPMTAG_DIRINDEXES: u32 = 1116;
+const RPMTAG_BASENAMES: u32 = 1117;
+const RPMTAG_DIRNAMES: u32 = 1118;
+const RPMTAG_PAYLOADFORMAT: u32 = 1124;
+const RPMTAG_PAYLOADCOMPRESSOR: u32 = 1125;
+const RPMTAG_PAYLOADFLAGS: u32 = 1126;
+const RPMTAG_FILEDIGESTALGO: u32 = 5011;
+const RPMTAG_PAYLOADDIGEST: u32 = 5092;
+const RPMTAG_PAYLOADDIGESTALGO: u32 = 5093;
+const RPMSIGTAG_SHA256: u32 = 273;
+const RPMSIGTAG_SIZE: u32 = 1000;
+const RPMSIGTAG_PAYLOADSIZE: u32 = 1007;
+
+/// Features of rpm itself the package relies on, each required at `<=` the version that introduced it.
+const RPMLIB_REQUIREMENTS: &[(&str, &str)] =
+    &[("rpmlib(CompressedFileNames)", "3.0.4-1"), ("rpmlib(FileDigests)", "4.6.0-1"), ("rpmlib(PayloadFilesHavePrefix)", "4.0-1")];
+/// `RPMSENSE_RPMLIB | RPMSENSE_EQUAL | RPMSENSE_LESS`
+const RPMSENSE_RPMLIB_LESS_EQUAL: u32 = (1 << 24) | 0x08 | 0x02;
+const RPMSENSE_EQUAL: u32 = 0x08;
+const PGPHASHALGO_SHA256: u32 = 8;
+const RPMFILE_CONFIG: u32 = 1 << 0;
+const RPMFILE_NOREPLACE: u32 = 1 << 4;
+
+/// Builds a noarch `.rpm`: the lead, a signature header with the SHA-256 digest and sizes, the header and a
+/// gzip-framed `newc` cpio payload.
+pub fn build_rpm(info: &PackageInfo, files: &[PackageFile]) -> Vec<u8> {
+    let cpio = cpio_archive(files, info.mtime as u32);
+    let payload = gzip_stored(&cpio);
+
+    let mut dirnames: Vec<String> = Vec::new();
+    let mut dirindexes = Vec::new();
+    let mut basenames = Vec::new();
+    for file in files {
+        let (directory, basename) = file.path.rsplit_once('/').unwrap_or(("", &file.path));
+        let directory = format!("{}/", directory);
+        let index = dirnames.iter().position(|d| *d == directory).unwrap_or_else(|| {
+            dirnames.push(directory);
+            dirnames.len() - 1
+        });
+        dirindexes.push(index as u32);
+        basenames.push(basename.to_string());
+    }
+    let file_count = files.len();
+    let strings = |value: &str| vec![value.to_string(); file_count];
+    let epoch_version = format!("{}-{}", info.version, RPM_RELEASE);
+
+    let header = serialize_rpm_header(
+        RPMTAG_HEADERIMMUTABLE,
+        vec![
+            (RPMTAG_HEADERI18NTABLE, RpmValue::StringArray(vec!["C".to_string()])),
+            (RPMTAG_NAME, RpmValue::String(info.name.clone())),
+            (RPMTAG_VERSION, RpmValue::String(info.version.clone())),
+            (RPMTAG_RELEASE, RpmValue::String(RPM_RELEASE.to_string())),
+            (RPMTAG_SUMMARY, RpmValue::I18nString(info.summary.clone())),
+            (RPMTAG_DESCRIPTION, RpmValue::I18nString(info.description.clone())),
+            (RPMTAG_BUILDTIME, RpmValue::Int32(vec![info.mtime as u32])),
+            (RPMTAG_SIZE, RpmValue::Int32(vec![files.iter().map(|file| file.content.len() as u32).sum()])),
+            (RPMTAG_LICENSE, RpmValue::String("MIT".to_string())),
+            (RPMTAG_PACKAGER, RpmValue::String(info.maintainer.clone())),
+            (RPMTAG_GROUP, RpmValue::I18nString("Unspecified".to_string())),
+            (RPMTAG_OS, RpmValue::String("linux".to_string())),
+            (RPMTAG_ARCH, RpmValue::String("noarch".to_string())),
+            (RPMTAG_FILESIZES, RpmValue::Int32(files.iter().map(|file| file.content.len() as u32).collect())),
+            (RPMTAG_FILEMODES, RpmValue::Int16(files.iter().map(|file| (0o100000 | file.mode) as u16).collect())),
+            (RPMTAG_FILERDEVS, RpmValue::Int16(vec![0; file_count])),
+            (RPMTAG_FILEMTIMES, RpmValue::Int32(vec![info.mtime as u32; file_count])),
+            (RPMTAG_FILEDIGESTS, RpmValue::StringArray(files.iter().map(|file| format!("{:x}", Sha256::digest(&file.content))).collect())),
+            (RPMTAG_FILELINKTOS, RpmValue::StringArray(strings(""))),
+            (
+                RPMTAG_FILEFLAGS,
+                RpmValue::Int32(
+                    files.iter().map(|file| if is_config_file(file) { RPMFILE_CONFIG | RPMFILE_NOREPLACE } else { 0 }).collect(),
+                ),
+            ),
+            (RPMTAG_FILEUSERNAME, RpmValue::StringArray(strings("root"))),
+            (RPMTAG_FILEGROUPNAME, RpmValue::StringArray(strings("root"))),
+            // Its presence marks a binary package
+            (RPMTAG_SOURCERPM, RpmValue::String(format!("{}-{}.src.rpm", info.name, epoch_version))),
+            (RPMTAG_FILEVERIFYFLAGS, RpmValue::Int32(vec![u32::MAX; file_count])),
+            (RPMTAG_PROVIDENAME, RpmValue::StringArray(vec![info.name.clone()])),
+            (RPMTAG_REQUIREFLAGS, RpmValue::Int32(vec![RPMSENSE_RPMLIB_LESS_EQUAL; RPMLIB_REQUIREMENTS.len()])),
+            (RPMTAG_REQUIRENAME, RpmValue::StringArray(RPMLIB_REQUIREMENTS.iter().map(|(name, _)| name.to_string()).collect())),
+            (RPMTAG_REQUIREVERSION, RpmValue::StringArray(RPMLIB_REQUIREMENTS.iter().map(|(_, version)| version.to_string()).collect())),
+            (RPMTAG_CONFLICTFLAGS, RpmValue::Int32(vec![0])),
+            (RPMTAG_CONFLICTNAME, RpmValue::StringArray(vec![info.conflicts.clone()])),
+            (RPMTAG_CONFLICTVERSION, RpmValue::StringArray(vec![String::new()])),
+            (RPMTAG_FILEDEVICES, RpmValue::Int32(vec![1; file_count])),
+            (RPMTAG_FILEINODES, RpmValue::Int32((1..=file_count as u32).collect())),
+            (RPMTAG_FILELANGS, RpmValue::StringArray(strings(""))),
+            (RPMTAG_PROVIDEFLAGS, RpmValue::Int32(vec![RPMSENSE_EQUAL])),
+            (RPMTAG_PROVIDEVERSION, RpmValue::StringArray(vec![epoch_version])),
+            (RPMTAG_DIRINDEXES, RpmValue::Int32(dirindexes)),
+            (RPMTAG_BASENAMES, RpmValue::StringArray(basenames)),
+            (RPMTAG_DIRNAMES, RpmValue::StringArray(dirnames)),
+            (RPMTAG_PAYLOADFORMAT, RpmValue::String("cpio".to_string())),
+            (RPMTAG_PAYLOADCOMPRESSOR, RpmValue::String("gzip".to_string())),
+            // The gzip stream holds stored blocks, so the level is 0
+            (RPMTAG_PAYLOADFLAGS, RpmValue::String("0".to_string())),
+            (RPMTAG_FILEDIGESTALGO, RpmValue::Int32(vec![PGPHASHALGO_SHA256])),
+            (RPMTAG_PAYLOADDIGEST, RpmValue::StringArray(vec![format!("{:x}", Sha256::digest(&payload))])),
+            (RPMTAG_PAYLOADDIGESTALGO, RpmValue::Int32(vec![PGPHASHALGO_SHA256])),
+        ],
+    );
+    let signature = serialize_rpm_header(
+        RPMTAG_HEADERSIGNATURES,
+        vec![
+            (RPMSIGTAG_SHA256, RpmValue::String(format!("{:x}", Sha256::digest(&header)))),
+            (RPMSIGTAG_SIZE, RpmValue::Int32(vec![(header.len() + payload.len()) as u32])),
+            (RPMSIGTAG_PAYLOADSIZE, RpmValue::Int32(vec![cpio.len() as u32])),
+        ],
+    );
+
+    let mut rpm = Vec::new();
+    // Lead: magic, format 3.0, binary package, name, Linux, header-style signature
+    rpm.extend([0xed, 0xab, 0xee, 0xdb, 3, 0, 0, 0, 0, 0]);
+    let mut lead_name = [0u8; 66];
+    let full_name = format!("{}-{}-{}", info.name, info.version, RPM_RELEASE);
+    let length = full_name.len().min(65);
+    lead_name[..length].copy_from_slice(&full_name.as_bytes()[..length]);
+    rpm.extend(lead_name);
+    rpm.extend([0, 1, 0, 5]);
+    rpm.extend([0u8; 16]);
+    rpm.extend(&signature);
+    rpm.resize(rpm.len().next_multiple_of(8), 0);
+    rpm.extend(header);
+    rpm.extend(payload);
+    rpm
+}
+
+/// Serializes an RPM header, with its entries wrapped in the immutable region `region_tag`.
+fn serialize_rpm_header(region_tag: u32, mut entries: Vec<(u32, RpmValue)>) -> Vec<u8> {
+    entries.sort_by_key(|(tag, _)| *tag);
+    let mut index = Vec::new();
+    let mut data = Vec::new();
+    for (tag, value) in &entries {
+        let (value_type, count) = match value {
+            RpmValue::Int16(values) => {
+                data.resize(data.len().next_multiple_of(2), 0);
+                (3, values.len())
+            }
+            RpmValue::Int32(values) => {
+                data.resize(data.len().next_multiple_of(4), 0);
+                (4, values.len())
+            }
+            RpmValue::String(_) => (6, 1),
+            RpmValue::StringArray(values) => (8, values.len()),
+            RpmValue::I18nString(_) => (9, 1),
+        };
+        index.push((*tag, value_type, data.len() as i32, count as u32));
+        match value {
+            RpmValue::Int16(values) => values.iter().for_each(|value| data.extend(value.to_be_bytes())),
+            RpmValue::Int32(values) => values.iter().for_each(|value| data.extend(value.to_be_bytes())),
+            RpmValue::String(value) | RpmValue::I18nString(value) => {
+                data.extend(value.as_bytes());
+                data.push(0);
+            }
+            RpmValue::StringArray(values) => values.iter().for_each(|value| {
+                data.extend(value.as_bytes());
+                data.push(0);
+            }),
+        }
+    }
+
+    // The region entry points at a trailer that covers every entry, itself included
+    let entry_count = index.len() + 1;
+    index.insert(0, (region_tag, 7, data.len() as i32, 16));
+    data.extend(region_tag.to_be_bytes());
+    data.extend(7u32.to_be_bytes());
+    data.extend((-(entry_count as i32 * 16)).to_be_bytes());
+    data.extend(16u32.to_be_bytes());
+
+    let mut header = vec![0x8e, 0xad, 0xe8, 1, 0, 0, 0, 0];
+    header.extend((entry_count as u32).to_be_bytes());
+    header.extend((data.len() as u32).to_be_bytes());
+    for (tag, value_type, offset, count) in index {
+        header.extend(tag.to_be_bytes());
+        header.extend((value_type as u32).to_be_bytes());
+        header.extend(offset.to_be_bytes());
+        header.extend(count.to_be_bytes());
+    }
+    header.extend(data);
+    header
+}
+
+/// Writes the files as a `newc` cpio archive with `./` paths, as rpm expects its payload.
+fn cpio_archive(files: &[PackageFile], mtime: u32) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut add_entry = |inode: usize, mode: u32, name: &str, content: &[u8]| {
+        let fields = [inode as u32, mode, 0, 0, 1, mtime, content.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+        archive.extend(b"070701");
+        for field in fields {
+            archive.extend(format!("{:08x}", field).into_bytes());
+        }
+        archive.extend(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend(content);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    };
+    for (i, file) in files.iter().enumerate() {
+        add_entry(i + 1, 0o100000 | file.mode, &format!(".{}", file.path), &file.content);
+    }
+    add_entry(0, 0, "TRAILER!!!", &[]);
+    archive
+}
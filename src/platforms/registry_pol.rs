@@ -0,0 +1,103 @@
+use crate::cli::BraveVersion;
+use crate::config::ConfigValue;
+use crate::plan::Plan;
+use crate::platforms::get_brave_registry_path;
+
+/// Signature at the start of every `Registry.pol` file ("PReg").
+const PREG_SIGNATURE: u32 = 0x67655250;
+const PREG_VERSION: u32 = 1;
+
+const REG_SZ: u32 = 1;
+const REG_DWORD: u32 = 4;
+
+/// One entry of a `Registry.pol` file. Keys are relative to the hive the file applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolEntry {
+    Dword { key: String, name: String, data: u32 },
+    String { key: String, name: String, data: String },
+    /// `**del.<name>`: deletes one value when the policy is applied.
+    DeleteValue { key: String, name: String },
+    /// `**delvals.`: deletes every value of the key before the following entries are applied.
+    DeleteValues { key: String },
+}
+
+/// Returns the entries setting the same values as the batch script, with lists cleared before they are written.
+pub fn get_registry_pol_entries(plan: &Plan, version: &BraveVersion) -> Vec<PolEntry> {
+    let registry_path = get_brave_registry_path(version);
+
+    let mut entries = Vec::new();
+    for (key, value) in plan.policies() {
+        let (key, name) = (registry_path.to_string(), key.to_string());
+        match value {
+            ConfigValue::Bool(b) => entries.push(PolEntry::Dword { key, name, data: *b as u32 }),
+            ConfigValue::Number(n) => entries.push(PolEntry::Dword { key, name, data: *n as u32 }),
+            ConfigValue::String(s) => entries.push(PolEntry::String { key, name, data: s.clone() }),
+            ConfigValue::StringArray(items) => {
+                // Lists are subkeys with one numbered value per item
+                let key = format!("{}\\{}", key, name);
+                entries.push(PolEntry::DeleteValues { key: key.clone() });
+                for (i, item) in items.iter().enumerate() {
+                    entries.push(PolEntry::String { key: key.clone(), name: (i + 1).to_string(), data: item.clone() });
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// Returns `**del.` entries for every value written by [`get_registry_pol_entries`].
+pub fn get_registry_pol_removal_entries(plan: &Plan, version: &BraveVersion) -> Vec<PolEntry> {
+    let registry_path = get_brave_registry_path(version);
+
+    let mut entries = Vec::new();
+    for (key, value) in plan.policies() {
+        match value {
+            ConfigValue::StringArray(items) => {
+                let list_key = format!("{}\\{}", registry_path, key);
+                entries.extend((1..=items.len()).map(|i| PolEntry::DeleteValue { key: list_key.clone(), name: i.to_string() }));
+            }
+            _ => entries.push(PolEntry::DeleteValue { key: registry_path.to_string(), name: key.to_string() }),
+        }
+    }
+    entries
+}
+
+/// Serializes entries in the binary PReg format: a header followed by `[key;value;type;size;data]`
+/// records, with every string in UTF-16LE and every number a little-endian u32.
+pub fn serialize_registry_pol(entries: &[PolEntry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend(PREG_SIGNATURE.to_le_bytes());
+    bytes.extend(PREG_VERSION.to_le_bytes());
+
+    for entry in entries {
+        let (key, name, value_type, data) = match entry {
+            PolEntry::Dword { key, name, data } => (key, name.clone(), REG_DWORD, data.to_le_bytes().to_vec()),
+            PolEntry::String { key, name, data } => (key, name.clone(), REG_SZ, utf16_terminated(data)),
+            // Deletion markers carry a single space as their data, as written by the Group Policy editor
+            PolEntry::DeleteValue { key, name } => (key, format!("**del.{}", name), REG_SZ, utf16_terminated(" ")),
+            PolEntry::DeleteValues { key } => (key, "**delvals.".to_string(), REG_SZ, utf16_terminated(" ")),
+        };
+        bytes.extend(utf16("["));
+        bytes.extend(utf16_terminated(key));
+        bytes.extend(utf16(";"));
+        bytes.extend(utf16_terminated(&name));
+        bytes.extend(utf16(";"));
+        bytes.extend(value_type.to_le_bytes());
+        bytes.extend(utf16(";"));
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(utf16(";"));
+        bytes.extend(data);
+        bytes.extend(utf16("]"));
+    }
+    bytes
+}
+
+fn utf16(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+}
+
+fn utf16_terminated(value: &str) -> Vec<u8> {
+    let mut bytes = utf16(value);
+    bytes.extend([0, 0]);
+    bytes
+}
@@ -1,16 +1,26 @@
 use std::fs;
 use std::path::Path;
 
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::Value;
+
 use crate::cli::BraveVersion;
-use crate::config::{Config, Extension, ConfigValue};
+use crate::config::ConfigValue;
 use crate::error::DebloaterError;
-use crate::platforms::{PlatformGenerator, get_brave_registry_path, get_version_suffix};
-use crate::preferences::{PreferencesInputConfig, get_default_search_provider, get_default_dashboard_config, get_default_experimental_features};
+use crate::plan::{Plan, ProfileChanges, UserFile};
+use crate::platforms::escape::{batch_quote, batch_set, escape_argument, powershell_quote, reg_quote};
+use crate::platforms::registry_pol::{get_registry_pol_entries, get_registry_pol_removal_entries, serialize_registry_pol};
+use crate::platforms::{BACKUP_DIR_NAME, LATEST_SNAPSHOT, PlatformGenerator, get_brave_registry_path, get_version_suffix, stable_uuid};
+use crate::preferences::{LIST_SWITCHES, MODIFIED_PREFERENCES, PREF_HASH_SEED, get_protected_preferences};
+
+/// Longest Base64 text written on one batch line, well below cmd's limit of 8191 characters.
+const MAX_BATCH_CHUNK: usize = 7000;
 
 pub struct WindowsGenerator;
 
 impl PlatformGenerator for WindowsGenerator {
-    fn generate_unified_script(&self, config: &Config, extensions: &[Extension], version: &BraveVersion, output_dir: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
+    fn generate_unified_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
         let filename = match version {
             BraveVersion::Normal => "brave_debloat.bat",
             BraveVersion::Nightly => "brave_nightly_debloat.bat",
@@ -28,26 +38,70 @@ impl PlatformGenerator for WindowsGenerator {
         content.push_str("echo Brave Browser Debloater for Windows\n");
         content.push_str("echo ====================================\n\n");
         
-        content.push_str("REM Check for admin rights\n");
-        content.push_str("net session >nul 2>&1\n");
-        content.push_str("if %errorlevel% neq 0 (\n");
-        content.push_str("    echo This script must be run as Administrator!\n");
-        content.push_str("    pause\n");
-        content.push_str("    exit /b 1\n");
-        content.push_str(")\n\n");
+        add_script_options(&mut content, false);
+        add_admin_check(&mut content);
         
         // Check if Brave is running
-        add_brave_process_check(&mut content);
+        if plan.checks_process() {
+            add_brave_process_check(&mut content);
+        }
         
         // Generate registry entries
-        add_registry_policies(&mut content, config, extensions, version)?;
+        add_registry_policies(&mut content, plan, version);
         
         // Add user preferences modification
-        add_user_preferences_modification(&mut content, version_suffix, preferences_config)?;
+        add_user_preferences_modification(&mut content, plan, version_suffix);
+        
+        // Add launch flags to the Brave shortcuts
+        add_shortcut_launch_flags(&mut content, version_suffix, &plan.launch_flags());
+        
+        add_completion_message(&mut content, "Configuration complete!");
+        add_registry_preview_subroutines(&mut content);
+        
+        let output_path = Path::new(output_dir).join(filename);
+        fs::write(output_path, content)?;
+        
+        Ok(())
+    }
+
+    fn generate_revert_script(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let filename = match version {
+            BraveVersion::Normal => "brave_revert.bat",
+            BraveVersion::Nightly => "brave_nightly_revert.bat",
+        };
+        
+        let version_suffix = get_version_suffix(version);
+        
+        let mut content = String::new();
+        content.push_str("@echo off\n");
+        content.push_str("setlocal enabledelayedexpansion\n");
+        content.push_str("REM Brave Browser Debloater Revert Script for Windows\n");
+        content.push_str("REM This script removes the policies and restores the preferences changed by the debloat script\n");
+        content.push_str("REM Run as Administrator\n\n");
+        
+        content.push_str("echo Brave Browser Debloater Revert for Windows\n");
+        content.push_str("echo ===========================================\n\n");
+        
+        add_script_options(&mut content, true);
+        add_admin_check(&mut content);
+        add_brave_process_check(&mut content);
+        content.push_str(&format!("set \"BRAVE_DATA=%USERPROFILE%\\AppData\\Local\\BraveSoftware\\{}\\User Data\"\n", version_suffix));
+        add_snapshot_selection(&mut content);
+        add_registry_policies_removal(&mut content, plan, version);
+        
+        content.push_str("echo Restoring user preferences...\n");
+        add_snapshot_restore_powershell(&mut content);
+        
+        let launch_flags = plan.launch_flags();
+        if !launch_flags.is_empty() {
+            content.push_str("echo Removing launch flags from Brave shortcuts...\n");
+            add_shortcut_arguments_powershell(&mut content, version_suffix, &launch_flags, "foreach ($flag in $flags) { $arguments = Remove-LaunchFlag $arguments $flag }");
+            content.push_str("if \"%DRY_RUN%\"==\"0\" echo Launch flags removed successfully!\n");
+            content.push_str("echo.\n\n");
+        }
         
-        content.push_str("echo Configuration complete!\n");
-        content.push_str("echo Please restart Brave browser for changes to take effect.\n");
-        content.push_str("pause\n");
+        add_completion_message(&mut content, "Revert complete!");
+        add_registry_preview_subroutines(&mut content);
         
         let output_path = Path::new(output_dir).join(filename);
         fs::write(output_path, content)?;
@@ -56,10 +110,347 @@ impl PlatformGenerator for WindowsGenerator {
     }
 }
 
+impl WindowsGenerator {
+    /// Writes `.reg` files that set and remove the same policies as the batch scripts, for `regedit /s`.
+    pub fn generate_reg_files(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let (filename, revert_filename) = match version {
+            BraveVersion::Normal => ("brave_debloat.reg", "brave_revert.reg"),
+            BraveVersion::Nightly => ("brave_nightly_debloat.reg", "brave_nightly_revert.reg"),
+        };
+        
+        let mut content = String::new();
+        add_reg_policies(&mut content, plan, version);
+        write_reg_file(&Path::new(output_dir).join(filename), &content)?;
+        
+        let mut content = String::new();
+        add_reg_policies_removal(&mut content, plan, version);
+        write_reg_file(&Path::new(output_dir).join(revert_filename), &content)?;
+        
+        Ok(())
+    }
+
+    /// Writes the policies as Group Policy `Registry.pol` files, plus a GPO backup folder that GPMC can import if `gpo_backup` is set.
+    pub fn generate_registry_pol(&self, plan: &Plan, version: &BraveVersion, output_dir: &str, gpo_backup: bool) -> Result<(), DebloaterError> {
+        let (filename, revert_filename, backup_dirname) = match version {
+            BraveVersion::Normal => ("brave_debloat.pol", "brave_revert.pol", "brave_debloat_gpo"),
+            BraveVersion::Nightly => ("brave_nightly_debloat.pol", "brave_nightly_revert.pol", "brave_nightly_debloat_gpo"),
+        };
+        
+        let registry_pol = serialize_registry_pol(&get_registry_pol_entries(plan, version));
+        fs::write(Path::new(output_dir).join(filename), &registry_pol)?;
+        fs::write(Path::new(output_dir).join(revert_filename), serialize_registry_pol(&get_registry_pol_removal_entries(plan, version)))?;
+        
+        if gpo_backup {
+            write_gpo_backup(&Path::new(output_dir).join(backup_dirname), version, &registry_pol)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl WindowsGenerator {
+    /// Writes standalone PowerShell scripts that apply and revert the plan without cmd, with `-WhatIf` and `-Confirm` support.
+    pub fn generate_powershell_scripts(&self, plan: &Plan, version: &BraveVersion, output_dir: &str) -> Result<(), DebloaterError> {
+        let (filename, revert_filename) = match version {
+            BraveVersion::Normal => ("brave_debloat.ps1", "brave_revert.ps1"),
+            BraveVersion::Nightly => ("brave_nightly_debloat.ps1", "brave_nightly_revert.ps1"),
+        };
+        
+        let version_suffix = get_version_suffix(version);
+        let launch_flags = plan.launch_flags();
+        
+        let mut content = String::new();
+        add_powershell_header(&mut content, version_suffix, false);
+        content.push_str(&format!("$policyKey = {}\n", powershell_quote(&format!("HKLM:\\{}", get_brave_registry_path(version)))));
+        add_preferences_helpers_powershell(&mut content, plan);
+        add_policy_functions_powershell(&mut content);
+        content.push_str("\ntry {\n");
+        add_powershell_admin_check(&mut content);
+        if plan.checks_process() {
+            add_powershell_process_check(&mut content);
+        }
+        add_registry_policies_powershell(&mut content, plan);
+        content.push_str("Write-Host 'Modifying user preferences...'\n");
+        content.push_str("if (-not $dryRun) { New-Item -ItemType Directory -Force -Path $braveData | Out-Null }\n");
+        add_preferences_powershell(&mut content, plan, true);
+        content.push_str("if (-not $dryRun) { Write-Host 'User preferences applied successfully!' }\n");
+        content.push_str("Write-Host ''\n");
+        if !launch_flags.is_empty() {
+            content.push_str("Write-Host 'Adding launch flags to Brave shortcuts...'\n");
+            add_shortcut_update_powershell(&mut content, version_suffix, &launch_flags, "foreach ($flag in $flags) { $arguments = Add-LaunchFlag $arguments $flag }", true);
+            content.push_str("Write-Host ''\n");
+        }
+        add_powershell_error_handler(&mut content);
+        add_powershell_completion(&mut content, "Configuration complete!");
+        fs::write(Path::new(output_dir).join(filename), content)?;
+        
+        let mut content = String::new();
+        add_powershell_header(&mut content, version_suffix, true);
+        content.push_str(&format!("$policyKey = {}\n", powershell_quote(&format!("HKLM:\\{}", get_brave_registry_path(version)))));
+        content.push_str(&format!("$backupRoot = Join-Path $braveData '{}'\n", BACKUP_DIR_NAME));
+        add_policy_functions_powershell(&mut content);
+        content.push_str("\ntry {\n");
+        add_powershell_admin_check(&mut content);
+        add_powershell_process_check(&mut content);
+        add_snapshot_selection_powershell(&mut content);
+        add_registry_policies_removal_powershell(&mut content, plan);
+        content.push_str("Write-Host 'Restoring user preferences...'\n");
+        add_snapshot_copy_powershell(&mut content, true);
+        content.push_str("Write-Host ''\n");
+        if !launch_flags.is_empty() {
+            content.push_str("Write-Host 'Removing launch flags from Brave shortcuts...'\n");
+            add_shortcut_update_powershell(&mut content, version_suffix, &launch_flags, "foreach ($flag in $flags) { $arguments = Remove-LaunchFlag $arguments $flag }", true);
+            content.push_str("Write-Host ''\n");
+        }
+        add_powershell_error_handler(&mut content);
+        add_powershell_completion(&mut content, "Revert complete!");
+        fs::write(Path::new(output_dir).join(revert_filename), content)?;
+        
+        Ok(())
+    }
+}
+
+/// Starts a `.ps1` script: help, parameters and the `$dryRun`, `$check` and `$failures` state the shared code reads.
+fn add_powershell_header(content: &mut String, version_suffix: &str, revert: bool) {
+    content.push_str("<#\n");
+    content.push_str(".SYNOPSIS\n");
+    if revert {
+        content.push_str("Brave Browser Debloater Revert for Windows\n");
+        content.push_str(".DESCRIPTION\n");
+        content.push_str("Removes the policies and restores the preferences changed by the debloat script. Run as Administrator.\n");
+        content.push_str(&format!(
+            "Use -WhatIf to show the changes without applying them. The oldest backup snapshot is restored; use -Restore <timestamp> or -Restore {} to pick another.\n",
+            LATEST_SNAPSHOT
+        ));
+    } else {
+        content.push_str("Brave Browser Debloater for Windows\n");
+        content.push_str(".DESCRIPTION\n");
+        content.push_str("Applies the Brave policies and user preferences. Run as Administrator.\n");
+        content.push_str("Use -WhatIf to show the changes without applying them, or -Check to compare the current configuration against the generated one.\n");
+    }
+    content.push_str("#>\n");
+    content.push_str("[CmdletBinding(SupportsShouldProcess = $true)]\n");
+    if revert {
+        content.push_str("param([string]$Restore)\n\n");
+        content.push_str("$check = $false\n");
+        content.push_str("$dryRun = [bool]$WhatIfPreference\n");
+    } else {
+        content.push_str("param([switch]$Check)\n\n");
+        content.push_str("$dryRun = $WhatIfPreference -or $Check\n");
+    }
+    content.push_str("$failures = 0\n");
+    content.push_str("$ErrorActionPreference = 'Stop'\n");
+    content.push_str(&format!("$braveData = Join-Path $env:LOCALAPPDATA 'BraveSoftware\\{}\\User Data'\n", version_suffix));
+    content.push_str("$localStatePath = Join-Path $braveData 'Local State'\n");
+    if revert {
+        content.push_str("Write-Host 'Brave Browser Debloater Revert for Windows'\n");
+        content.push_str("Write-Host '==========================================='\n");
+    } else {
+        content.push_str("Write-Host 'Brave Browser Debloater for Windows'\n");
+        content.push_str("Write-Host '===================================='\n");
+    }
+    content.push_str("if ($Check) { Write-Host 'Check: comparing the current configuration against the generated one' }\n");
+    content.push_str("elseif ($dryRun) { Write-Host 'Dry run: showing changes without applying them' }\n");
+    content.push_str("Write-Host ''\n");
+}
+
+fn add_powershell_admin_check(content: &mut String) {
+    content.push_str("$principal = New-Object Security.Principal.WindowsPrincipal ([Security.Principal.WindowsIdentity]::GetCurrent())\n");
+    content.push_str("if (-not $dryRun -and -not $principal.IsInRole([Security.Principal.WindowsBuiltInRole]::Administrator)) {\n");
+    content.push_str("    throw 'This script must be run as Administrator!'\n");
+    content.push_str("}\n");
+}
+
+fn add_powershell_process_check(content: &mut String) {
+    content.push_str("Write-Host 'Checking if Brave is running...'\n");
+    content.push_str("$braveProcesses = @(Get-Process -Name brave -ErrorAction SilentlyContinue)\n");
+    content.push_str("if ($braveProcesses -and -not $dryRun) {\n");
+    content.push_str("    Write-Warning 'Brave browser is running!'\n");
+    content.push_str("    Write-Host 'Please close Brave browser before running this script.'\n");
+    content.push_str("    Read-Host 'Press Enter to close Brave and continue anyway, or Ctrl+C to exit' | Out-Null\n");
+    content.push_str("    $braveProcesses | Stop-Process -Force -ErrorAction SilentlyContinue\n");
+    content.push_str("    Start-Sleep -Seconds 2\n");
+    content.push_str("}\n");
+    content.push_str("Write-Host ''\n");
+}
+
+fn add_policy_functions_powershell(content: &mut String) {
+    content.push_str("function Get-PolicyValue($path, $name) {\n");
+    content.push_str("    $item = Get-ItemProperty -Path $path -Name $name -ErrorAction SilentlyContinue\n");
+    content.push_str("    if ($item) { return $item.$name }\n");
+    content.push_str("    return $null\n");
+    content.push_str("}\n");
+    content.push_str("function Set-Policy($path, $name, $value, $type) {\n");
+    content.push_str("    if ($dryRun) { Show-Change $name (ConvertTo-PrefHashJson (Get-PolicyValue $path $name)) (ConvertTo-PrefHashJson $value); return }\n");
+    content.push_str("    if (-not $PSCmdlet.ShouldProcess($path + '\\' + $name, 'Set policy')) { return }\n");
+    content.push_str("    if (-not (Test-Path $path)) { New-Item -Path $path -Force | Out-Null }\n");
+    content.push_str("    Set-ItemProperty -Path $path -Name $name -Value $value -Type $type\n");
+    content.push_str("}\n");
+    // Lists are subkeys with one numbered value per item, cleared first so no stale items remain
+    content.push_str("function Set-PolicyList($path, $items) {\n");
+    content.push_str("    if ($dryRun) {\n");
+    content.push_str("        $current = $null\n");
+    content.push_str("        if (Test-Path $path) { $key = Get-Item $path; $current = @($key.GetValueNames() | Sort-Object { [int]$_ } | ForEach-Object { $key.GetValue($_) }) }\n");
+    content.push_str("        Show-Change (Split-Path $path -Leaf) (ConvertTo-PrefHashJson $current) (ConvertTo-PrefHashJson $items)\n");
+    content.push_str("        return\n");
+    content.push_str("    }\n");
+    content.push_str("    if (-not $PSCmdlet.ShouldProcess($path, 'Set policy list')) { return }\n");
+    content.push_str("    if (Test-Path $path) { Remove-Item $path -Recurse -Force }\n");
+    content.push_str("    New-Item -Path $path -Force | Out-Null\n");
+    content.push_str("    for ($i = 0; $i -lt $items.Count; $i++) { Set-ItemProperty -Path $path -Name ($i + 1) -Value $items[$i] -Type String }\n");
+    content.push_str("}\n");
+    content.push_str("function Remove-Policy($path, $name) {\n");
+    content.push_str("    $current = Get-PolicyValue $path $name\n");
+    content.push_str("    if ($null -eq $current) { return }\n");
+    content.push_str("    if ($dryRun) { Write-Host ('    Would delete ' + $path + '\\' + $name + ': ' + $current); return }\n");
+    content.push_str("    if ($PSCmdlet.ShouldProcess($path + '\\' + $name, 'Remove policy')) { Remove-ItemProperty -Path $path -Name $name }\n");
+    content.push_str("}\n");
+}
+
+fn add_registry_policies_powershell(content: &mut String, plan: &Plan) {
+    content.push_str("Write-Host 'Applying Brave policies via registry...'\n");
+    content.push_str("if ($dryRun) { Write-Host ('Registry key: ' + $policyKey) }\n");
+    for (key, value) in plan.policies() {
+        let name = powershell_quote(key);
+        match value {
+            ConfigValue::Bool(b) => content.push_str(&format!("Set-Policy $policyKey {} {} DWord\n", name, *b as u8)),
+            ConfigValue::Number(n) => content.push_str(&format!("Set-Policy $policyKey {} {} DWord\n", name, n)),
+            ConfigValue::String(s) => content.push_str(&format!("Set-Policy $policyKey {} {} String\n", name, powershell_quote(s))),
+            ConfigValue::StringArray(items) => content.push_str(&format!(
+                "Set-PolicyList ($policyKey + '\\' + {}) @({})\n",
+                name,
+                items.iter().map(|item| powershell_quote(item)).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+    content.push_str("if (-not $dryRun) { Write-Host 'Registry policies applied successfully!' }\n");
+    content.push_str("Write-Host ''\n");
+}
+
+fn add_registry_policies_removal_powershell(content: &mut String, plan: &Plan) {
+    content.push_str("Write-Host 'Removing Brave policies from registry...'\n");
+
+    // Only the values written by the debloat script, other policies are left alone
+    for (key, value) in plan.policies() {
+        match value {
+            ConfigValue::StringArray(items) => {
+                for i in 1..=items.len() {
+                    content.push_str(&format!("Remove-Policy ($policyKey + '\\' + {}) '{}'\n", powershell_quote(key), i));
+                }
+            }
+            _ => content.push_str(&format!("Remove-Policy $policyKey {}\n", powershell_quote(key))),
+        }
+    }
+    content.push_str("if (-not $dryRun) { Write-Host 'Registry policies removed successfully!' }\n");
+    content.push_str("Write-Host ''\n");
+}
+
+fn add_snapshot_selection_powershell(content: &mut String) {
+    // Fail before changing anything when the snapshot given with -Restore does not exist
+    content.push_str(&format!(
+        "if ($Restore -and $Restore -ne '{}' -and -not (Test-Path (Join-Path (Join-Path $backupRoot $Restore) 'manifest.txt'))) {{\n",
+        LATEST_SNAPSHOT
+    ));
+    content.push_str("    Write-Host 'Available snapshots:'\n");
+    content.push_str("    Get-ChildItem $backupRoot -Directory -ErrorAction SilentlyContinue | Sort-Object Name -Descending | ForEach-Object { Write-Host ('  ' + $_.Name) }\n");
+    content.push_str("    throw ('no backup snapshot ' + $Restore + ' in ' + $backupRoot)\n");
+    content.push_str("}\n");
+}
+
+fn add_powershell_error_handler(content: &mut String) {
+    content.push_str("} catch {\n");
+    content.push_str("    Write-Host ('Error: ' + $_.Exception.Message) -ForegroundColor Red\n");
+    content.push_str("    exit 1\n");
+    content.push_str("}\n\n");
+}
+
+fn add_powershell_completion(content: &mut String, message: &str) {
+    content.push_str("if ($check) {\n");
+    content.push_str("    Write-Host ''\n");
+    content.push_str("    if ($failures -gt 0) {\n");
+    content.push_str("        Write-Host ([string]$failures + ' settings differ from the generated configuration.') -ForegroundColor Red\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    }\n");
+    content.push_str("    Write-Host 'All settings match the generated configuration.' -ForegroundColor Green\n");
+    content.push_str("} elseif ($dryRun) {\n");
+    content.push_str("    Write-Host 'Dry run complete, no changes were made.'\n");
+    content.push_str("} else {\n");
+    content.push_str(&format!("    Write-Host '{}' -ForegroundColor Green\n", message));
+    content.push_str("    Write-Host 'Please restart Brave browser for changes to take effect.'\n");
+    content.push_str("}\n");
+}
+
+/// Writes a GPO backup with the computer policy `registry_pol`, in the layout GPMC and `Import-GPO` read.
+fn write_gpo_backup(backup_root: &Path, version: &BraveVersion, registry_pol: &[u8]) -> Result<(), DebloaterError> {
+    let display_name = match version {
+        BraveVersion::Normal => "Brave Debloat",
+        BraveVersion::Nightly => "Brave Nightly Debloat",
+    };
+    // The identifiers are derived from the name so that reimporting a regenerated backup updates the same GPO
+    let gpo_id = format!("{{{}}}", stable_uuid(&format!("{} GPO", display_name)));
+    let backup_id = format!("{{{}}}", stable_uuid(&format!("{} GPO backup", display_name)));
+    let backup_time = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string();
+
+    let backup_dir = backup_root.join(&backup_id);
+    let gpo_dir = backup_dir.join("DomainSysvol").join("GPO");
+    fs::create_dir_all(gpo_dir.join("Machine"))?;
+    fs::create_dir_all(gpo_dir.join("User"))?;
+    fs::write(gpo_dir.join("Machine").join("registry.pol"), registry_pol)?;
+
+    let mut content = String::new();
+    content.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    content.push_str("<GroupPolicyBackupScheme bkp:version=\"2.0\" bkp:type=\"GroupPolicyBackupTemplate\" xmlns:bkp=\"http://www.microsoft.com/GroupPolicy/GPOOperations\" xmlns=\"http://www.microsoft.com/GroupPolicy/GPOOperations\">\n");
+    content.push_str("  <GroupPolicyObject>\n");
+    content.push_str("    <SecurityGroups/>\n");
+    content.push_str("    <FilePaths/>\n");
+    content.push_str("    <GroupPolicyCoreSettings>\n");
+    content.push_str(&format!("      <ID><![CDATA[{}]]></ID>\n", gpo_id));
+    content.push_str(&format!("      <DisplayName><![CDATA[{}]]></DisplayName>\n", display_name));
+    content.push_str("      <Options><![CDATA[0]]></Options>\n");
+    content.push_str("      <UserVersionNumber><![CDATA[0]]></UserVersionNumber>\n");
+    content.push_str("      <MachineVersionNumber><![CDATA[1]]></MachineVersionNumber>\n");
+    // The Registry client-side extension and the Administrative Templates editor
+    content.push_str("      <MachineExtensionGuids><![CDATA[[{35378EAC-683F-11D2-A89A-00C04FBBCFA2}{D02B1F72-3407-48AE-BA88-E8213C6761F1}]]]></MachineExtensionGuids>\n");
+    content.push_str("      <UserExtensionGuids/>\n");
+    content.push_str("      <WMIFilter/>\n");
+    content.push_str("    </GroupPolicyCoreSettings>\n");
+    content.push_str("    <GroupPolicyExtension bkp:ID=\"{35378EAC-683F-11D2-A89A-00C04FBBCFA2}\" bkp:DescName=\"Registry\">\n");
+    content.push_str("      <FSObjectFile bkp:Path=\"%GPO_MACH_FSPATH%\\registry.pol\" bkp:Location=\"DomainSysvol\\GPO\\Machine\\registry.pol\"/>\n");
+    content.push_str("    </GroupPolicyExtension>\n");
+    content.push_str("  </GroupPolicyObject>\n");
+    content.push_str("</GroupPolicyBackupScheme>\n");
+    fs::write(backup_dir.join("Backup.xml"), content)?;
+
+    let mut content = String::new();
+    content.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    content.push_str(&format!("<BackupInst xmlns=\"http://www.microsoft.com/GroupPolicy/GPOOperations/Manifest\"><GPOGuid><![CDATA[{}]]></GPOGuid><GPODomain><![CDATA[]]></GPODomain><GPODomainGuid><![CDATA[]]></GPODomainGuid><GPODomainController><![CDATA[]]></GPODomainController><BackupTime><![CDATA[{}]]></BackupTime><ID><![CDATA[{}]]></ID><Comment><![CDATA[Generated by brave-debloater]]></Comment><GPODisplayName><![CDATA[{}]]></GPODisplayName></BackupInst>\n", gpo_id, backup_time, backup_id, display_name));
+    fs::write(backup_dir.join("bkupInfo.xml"), &content)?;
+
+    // GPMC lists the backups of a folder from its manifest
+    let mut manifest = String::new();
+    manifest.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    manifest.push_str("<Backups xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns=\"http://www.microsoft.com/GroupPolicy/GPOOperations/Manifest\" xmlns:mfst=\"http://www.microsoft.com/GroupPolicy/GPOOperations/Manifest\" mfst:version=\"1.0\">\n");
+    manifest.push_str(&format!("  {}", content.trim_start_matches("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n")));
+    manifest.push_str("</Backups>\n");
+    fs::write(backup_root.join("manifest.xml"), manifest)?;
+
+    Ok(())
+}
+
+fn add_admin_check(content: &mut String) {
+    content.push_str("REM Check for admin rights\n");
+    content.push_str("net session >nul 2>&1\n");
+    content.push_str("if %errorlevel% neq 0 if \"%DRY_RUN%\"==\"0\" (\n");
+    content.push_str("    echo This script must be run as Administrator!\n");
+    content.push_str("    pause\n");
+    content.push_str("    exit /b 1\n");
+    content.push_str(")\n\n");
+}
+
 fn add_brave_process_check(content: &mut String) {
     content.push_str("echo Checking if Brave is running...\n");
     content.push_str("tasklist /fi \"imagename eq brave.exe\" 2>nul | find /i \"brave.exe\" >nul\n");
-    content.push_str("if not errorlevel 1 (\n");
+    content.push_str("if not errorlevel 1 if \"%DRY_RUN%\"==\"0\" (\n");
     content.push_str("    echo WARNING: Brave browser is running!\n");
     content.push_str("    echo Please close Brave browser before running this script.\n");
     content.push_str("    echo Press any key to continue anyway, or Ctrl+C to exit.\n");
@@ -69,147 +460,823 @@ fn add_brave_process_check(content: &mut String) {
     content.push_str(")\n\n");
 }
 
-fn add_registry_policies(content: &mut String, config: &Config, extensions: &[Extension], version: &BraveVersion) -> Result<(), DebloaterError> {
+fn add_registry_policies(content: &mut String, plan: &Plan, version: &BraveVersion) {
     content.push_str("echo Applying Brave policies via registry...\n");
     
     let registry_path = get_brave_registry_path(version);
     
-    for (key, value) in config {
-        if key == "ExtensionInstallForcelist" {
-            continue; // Handle separately
+    content.push_str("if \"%DRY_RUN%\"==\"1\" goto :registry_preview\n");
+    // Names and data are read back with delayed expansion, so a quote in them cannot move the redirections into quotes
+    for (key, value) in plan.policies() {
+        if !matches!(value, ConfigValue::StringArray(_)) {
+            content.push_str(&format!("{}\n", batch_set("REG_NAME", &escape_argument(key))));
         }
-        
-        let reg_value = match value {
-            ConfigValue::Bool(b) => format!("reg add \"HKEY_LOCAL_MACHINE\\{}\" /v \"{}\" /t REG_DWORD /d {} /f >nul 2>&1\n", registry_path, key, if *b { 1 } else { 0 }),
-            ConfigValue::String(s) => format!("reg add \"HKEY_LOCAL_MACHINE\\{}\" /v \"{}\" /t REG_SZ /d \"{}\" /f >nul 2>&1\n", registry_path, key, s),
-            ConfigValue::Number(n) => format!("reg add \"HKEY_LOCAL_MACHINE\\{}\" /v \"{}\" /t REG_DWORD /d {} /f >nul 2>&1\n", registry_path, key, n),
-            ConfigValue::StringArray(_) => continue,
+        match value {
+            ConfigValue::Bool(b) => content.push_str(&format!(
+                "reg add \"HKEY_LOCAL_MACHINE\\{}\" /v \"!REG_NAME!\" /t REG_DWORD /d {} /f >nul 2>&1\n",
+                registry_path,
+                if *b { 1 } else { 0 }
+            )),
+            ConfigValue::String(s) => {
+                content.push_str(&format!("{}\n", batch_set("REG_DATA", &escape_argument(s))));
+                content.push_str(&format!(
+                    "reg add \"HKEY_LOCAL_MACHINE\\{}\" /v \"!REG_NAME!\" /t REG_SZ /d \"!REG_DATA!\" /f >nul 2>&1\n",
+                    registry_path
+                ));
+            }
+            ConfigValue::Number(n) => content.push_str(&format!(
+                "reg add \"HKEY_LOCAL_MACHINE\\{}\" /v \"!REG_NAME!\" /t REG_DWORD /d {} /f >nul 2>&1\n",
+                registry_path, n
+            )),
+            ConfigValue::StringArray(items) => {
+                // Lists are subkeys with one numbered value per item, cleared first so no stale items remain
+                let list_path = format!("HKEY_LOCAL_MACHINE\\{}\\{}", registry_path, key);
+                content.push_str(&format!("{}\n", batch_set("REG_KEY", &escape_argument(&list_path))));
+                content.push_str("reg delete \"!REG_KEY!\" /f >nul 2>&1\n");
+                for (i, item) in items.iter().enumerate() {
+                    content.push_str(&format!("{}\n", batch_set("REG_DATA", &escape_argument(item))));
+                    content.push_str(&format!("reg add \"!REG_KEY!\" /v \"{}\" /t REG_SZ /d \"!REG_DATA!\" /f >nul 2>&1\n", i + 1));
+                }
+            }
+        }
+    }
+    
+    content.push_str("echo Registry policies applied successfully!\n");
+    content.push_str("goto :registry_done\n");
+    
+    // Show the registry data as printed by reg query
+    content.push_str(":registry_preview\n");
+    content.push_str(&format!("echo Registry key: HKEY_LOCAL_MACHINE\\{}\n", registry_path));
+    for (key, value) in plan.policies() {
+        let data = match value {
+            ConfigValue::Bool(b) => format!("0x{:x}", *b as u8),
+            ConfigValue::Number(n) => format!("0x{:x}", n),
+            ConfigValue::String(s) => s.clone(),
+            ConfigValue::StringArray(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    add_registry_preview(content, &format!("HKEY_LOCAL_MACHINE\\{}\\{}", registry_path, key), &(i + 1).to_string(), item);
+                }
+                continue;
+            }
         };
-        content.push_str(&reg_value);
+        add_registry_preview(content, &format!("HKEY_LOCAL_MACHINE\\{}", registry_path), key, &data);
     }
+    content.push_str(":registry_done\n");
+    content.push_str("echo.\n\n");
+}
+
+fn add_registry_policies_removal(content: &mut String, plan: &Plan, version: &BraveVersion) {
+    content.push_str("echo Removing Brave policies from registry...\n");
     
-    // Handle ExtensionInstallForcelist
-    if !extensions.is_empty() {
-        for (i, ext) in extensions.iter().enumerate() {
-            content.push_str(&format!("reg add \"HKEY_LOCAL_MACHINE\\{}\\ExtensionInstallForcelist\" /v \"{}\" /t REG_SZ /d \"{}\" /f >nul 2>&1\n", registry_path, i + 1, ext.id));
+    let registry_path = get_brave_registry_path(version);
+    
+    // Only the values written by add_registry_policies, other policies are left alone
+    let mut values: Vec<(String, String)> = Vec::new();
+    for (key, value) in plan.policies() {
+        match value {
+            ConfigValue::StringArray(items) => values.extend((1..=items.len()).map(|i| (format!("{}\\{}", registry_path, key), i.to_string()))),
+            _ => values.push((registry_path.to_string(), key.to_string())),
         }
     }
     
-    content.push_str("echo Registry policies applied successfully!\n\n");
+    content.push_str("if \"%DRY_RUN%\"==\"1\" goto :registry_preview\n");
+    for (path, key) in &values {
+        content.push_str(&format!("{}\n", batch_set("REG_KEY", &escape_argument(&format!("HKEY_LOCAL_MACHINE\\{}", path)))));
+        content.push_str(&format!("{}\n", batch_set("REG_NAME", &escape_argument(key))));
+        content.push_str("reg delete \"!REG_KEY!\" /v \"!REG_NAME!\" /f >nul 2>&1\n");
+    }
+    content.push_str("echo Registry policies removed successfully!\n");
+    content.push_str("goto :registry_done\n");
+    content.push_str(":registry_preview\n");
+    for (path, key) in &values {
+        content.push_str(&format!("{}\n", batch_set("REG_NAME", key)));
+        content.push_str(&format!("call :show_reg_removal {}\n", batch_quote(&format!("HKEY_LOCAL_MACHINE\\{}", path))));
+    }
+    content.push_str(":registry_done\n");
+    content.push_str("echo.\n\n");
+}
+
+fn add_reg_policies(content: &mut String, plan: &Plan, version: &BraveVersion) {
+    let key_path = format!("HKEY_LOCAL_MACHINE\\{}", get_brave_registry_path(version));
+
+    content.push_str("Windows Registry Editor Version 5.00\n\n");
+    content.push_str(&format!("[{}]\n", key_path));
+    for (key, value) in plan.policies() {
+        let data = match value {
+            ConfigValue::Bool(b) => format!("dword:{:08x}", *b as u32),
+            ConfigValue::Number(n) => format!("dword:{:08x}", *n as u32),
+            ConfigValue::String(s) => reg_string_data(s),
+            ConfigValue::StringArray(_) => continue,
+        };
+        content.push_str(&format!("{}={}\n", reg_quote(key), data));
+    }
     
+    // Lists are subkeys with one numbered value per item, deleted first so no stale items remain
+    for (key, value) in plan.policies() {
+        let ConfigValue::StringArray(items) = value else {
+            continue;
+        };
+        content.push_str(&format!("\n[-{}\\{}]\n", key_path, key));
+        if items.is_empty() {
+            continue;
+        }
+        content.push_str(&format!("\n[{}\\{}]\n", key_path, key));
+        for (i, item) in items.iter().enumerate() {
+            content.push_str(&format!("\"{}\"={}\n", i + 1, reg_string_data(item)));
+        }
+    }
+}
+
+fn add_reg_policies_removal(content: &mut String, plan: &Plan, version: &BraveVersion) {
+    let key_path = format!("HKEY_LOCAL_MACHINE\\{}", get_brave_registry_path(version));
+
+    // Only the values written by add_reg_policies, other policies are left alone
+    content.push_str("Windows Registry Editor Version 5.00\n\n");
+    content.push_str(&format!("[{}]\n", key_path));
+    for (key, value) in plan.policies() {
+        if !matches!(value, ConfigValue::StringArray(_)) {
+            content.push_str(&format!("{}=-\n", reg_quote(key)));
+        }
+    }
+    for (key, value) in plan.policies() {
+        let ConfigValue::StringArray(items) = value else {
+            continue;
+        };
+        if items.is_empty() {
+            continue;
+        }
+        content.push_str(&format!("\n[{}\\{}]\n", key_path, key));
+        for i in 1..=items.len() {
+            content.push_str(&format!("\"{}\"=-\n", i));
+        }
+    }
+}
+
+/// Formats a REG_SZ value for a `.reg` file. Strings with line breaks are written as `hex(1):` UTF-16LE data.
+fn reg_string_data(value: &str) -> String {
+    if !value.contains(['\r', '\n']) {
+        return reg_quote(value);
+    }
+    let bytes: Vec<String> = value
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("hex(1):{}", bytes.join(","))
+}
+
+/// Writes a `.reg` file as regedit expects it: UTF-16LE with a byte order mark and CRLF line endings.
+fn write_reg_file(path: &Path, content: &str) -> Result<(), DebloaterError> {
+    let mut bytes = vec![0xFF, 0xFE];
+    for unit in content.replace('\n', "\r\n").encode_utf16() {
+        bytes.extend(unit.to_le_bytes());
+    }
+    fs::write(path, bytes)?;
     Ok(())
 }
 
-fn add_user_preferences_modification(content: &mut String, version_suffix: &str, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
+fn add_user_preferences_modification(content: &mut String, plan: &Plan, version_suffix: &str) {
     content.push_str("echo Modifying user preferences...\n");
     content.push_str(&format!("set \"BRAVE_DATA=%USERPROFILE%\\AppData\\Local\\BraveSoftware\\{}\\User Data\"\n", version_suffix));
-    content.push_str("set \"PREFS_FILE=%BRAVE_DATA%\\Default\\Preferences\"\n");
     content.push_str("set \"LOCAL_STATE=%BRAVE_DATA%\\Local State\"\n\n");
     
     // Create directories if they don't exist
-    content.push_str("if not exist \"%BRAVE_DATA%\\Default\" mkdir \"%BRAVE_DATA%\\Default\"\n\n");
-    
-    // Backup existing files
-    content.push_str("if exist \"%PREFS_FILE%\" copy \"%PREFS_FILE%\" \"%PREFS_FILE%.backup\" >nul 2>&1\n");
-    content.push_str("if exist \"%LOCAL_STATE%\" copy \"%LOCAL_STATE%\" \"%LOCAL_STATE%.backup\" >nul 2>&1\n\n");
+    content.push_str("if \"%DRY_RUN%\"==\"0\" if not exist \"%BRAVE_DATA%\" mkdir \"%BRAVE_DATA%\"\n\n");
     
     // Generate the user preferences modification using PowerShell
-    add_windows_preferences_powershell(content, preferences_config)?;
+    add_windows_preferences_powershell(content, plan);
+}
+
+fn add_windows_preferences_powershell(content: &mut String, plan: &Plan) {
+    // Create PowerShell script embedded in batch
+    content.push_str("echo Modifying Preferences files...\n");
+    let mut script = String::new();
+    script.push_str("$braveData = $env:BRAVE_DATA\n");
+    script.push_str("$localStatePath = $env:LOCAL_STATE\n");
+    script.push_str("$dryRun = $env:DRY_RUN -eq '1'\n");
+    script.push_str("$check = $env:CHECK -eq '1'\n");
+    script.push_str("$failures = 0\n");
     
-    Ok(())
+    add_preferences_helpers_powershell(&mut script, plan);
+    add_preferences_powershell(&mut script, plan, false);
+    
+    script.push_str("exit $failures\n");
+    add_encoded_powershell(content, &script);
+    content.push_str("if \"%CHECK%\"==\"0\" if errorlevel 1 exit /b 1\n");
+    content.push_str("if \"%CHECK%\"==\"1\" set /a FAILURES+=%errorlevel%\n");
+    content.push_str("if \"%DRY_RUN%\"==\"0\" echo User preferences applied successfully!\n");
+    content.push_str("echo.\n\n");
 }
 
-fn add_windows_preferences_powershell(content: &mut String, preferences_config: Option<&PreferencesInputConfig>) -> Result<(), DebloaterError> {
-    let search_provider = get_default_search_provider(preferences_config);
-    let dashboard_config = get_default_dashboard_config(preferences_config);
-    let experimental_features = get_default_experimental_features(preferences_config);
+/// Defines the PowerShell functions used to back up, edit and preview the user data files.
+fn add_preferences_helpers_powershell(content: &mut String, plan: &Plan) {
+    add_backup_snapshot_powershell(content, plan.backup_retention());
+    add_json_editing_powershell(content);
+    add_preference_macs_powershell(content, plan.backs_up(UserFile::SecurePreferences));
+    add_change_preview_powershell(content);
+}
 
-    // Create PowerShell script embedded in batch
-    content.push_str("echo Modifying Preferences file...\n");
-    content.push_str("powershell -ExecutionPolicy Bypass -Command \"\n");
-    content.push_str("$prefsPath = '%PREFS_FILE%';\n");
-    content.push_str("$localStatePath = '%LOCAL_STATE%';\n");
+/// Applies the Preferences of every profile and Local State. `native` code runs from a `.ps1` file,
+/// where writes are confirmed through ShouldProcess, instead of being run from a batch file.
+fn add_preferences_powershell(content: &mut String, plan: &Plan, native: bool) {
+    let profile_changes = plan.profile_changes();
+
+    add_profile_enumeration_powershell(content);
+    if plan.backs_up(UserFile::LocalState) {
+        content.push_str("if (-not $dryRun) { Backup-File $localStatePath }\n");
+    }
+    
+    content.push_str("foreach ($profileDir in $profiles) {\n");
+    content.push_str("$profileName = if ($profileInfo) { $profileInfo.$profileDir.name } else { $null }\n");
+    content.push_str("$prefsPath = Join-Path (Join-Path $braveData $profileDir) 'Preferences'\n");
+    add_profile_selection_powershell(content, &profile_changes);
+    content.push_str("Write-Host ('Applying preferences to profile: ' + $profileDir)\n");
+    content.push_str("if ($dryRun) {\n");
+    content.push_str("    Write-Host ('Preferences file: ' + $prefsPath)\n");
+    content.push_str("    $prefs = if (Test-Path $prefsPath) { Read-JsonText $prefsPath | ConvertFrom-Json } else { $null }\n");
+    for (i, changes) in profile_changes.iter().enumerate() {
+        content.push_str(&format!("    {} ($preferenceSet -eq {}) {{\n", if i == 0 { "if" } else { "elseif" }, i));
+        for (path, value) in &changes.preferences {
+            content.push_str(&format!("        Show-PrefChange $prefs {} {}\n", powershell_quote(path), powershell_literal(value)));
+        }
+        content.push_str("    }\n");
+    }
+    content.push_str("    continue\n");
+    content.push_str("}\n");
+    if native {
+        content.push_str("if (-not $PSCmdlet.ShouldProcess($prefsPath, 'Update preferences')) { continue }\n");
+    }
+    content.push_str("New-Item -ItemType Directory -Force -Path (Split-Path $prefsPath) | Out-Null\n");
     content.push_str("if (Test-Path $prefsPath) {\n");
-    content.push_str("    $prefs = Get-Content $prefsPath -Raw | ConvertFrom-Json\n");
+    if plan.backs_up(UserFile::Preferences) {
+        content.push_str("    Backup-File $prefsPath\n");
+    }
+    content.push_str("    $prefsJson = Read-JsonText $prefsPath\n");
     content.push_str("} else {\n");
-    content.push_str("    $prefs = @{}\n");
+    content.push_str("    $prefsJson = '{}'\n");
     content.push_str("}\n");
     
-    // Add search provider
-    content.push_str("if (-not $prefs.default_search_provider_data) { $prefs.default_search_provider_data = @{} }\n");
-    content.push_str(&format!("$prefs.default_search_provider_data.keyword = '{}'\n", search_provider.keyword));
-    content.push_str(&format!("$prefs.default_search_provider_data.name = '{}'\n", search_provider.name));
-    content.push_str(&format!("$prefs.default_search_provider_data.search_url = '{}'\n", search_provider.search_url));
-    
-    // Add brave preferences
-    content.push_str("if (-not $prefs.brave) { $prefs.brave = @{} }\n");
-    content.push_str("if (-not $prefs.brave.new_tab_page) { $prefs.brave.new_tab_page = @{} }\n");
-    content.push_str("if (-not $prefs.brave.stats) { $prefs.brave.stats = @{} }\n");
-    content.push_str("if (-not $prefs.brave.today) { $prefs.brave.today = @{} }\n");
-    
-    // Dashboard settings
-    add_dashboard_settings_powershell(content, &dashboard_config);
-    
-    content.push_str("$prefs.brave.stats.enabled = $false\n");
-    content.push_str("$prefs.brave.today.should_show_brave_today_widget = $false\n");
+    // Preferences of the selected profile group, protected ones are also kept for Secure Preferences
+    let protected_preferences = get_protected_preferences(MODIFIED_PREFERENCES);
+    content.push_str("$protectedValues = @{}\n");
+    for (i, changes) in profile_changes.iter().enumerate() {
+        content.push_str(&format!("{} ($preferenceSet -eq {}) {{\n", if i == 0 { "if" } else { "elseif" }, i));
+        for (path, value) in &changes.preferences {
+            content.push_str(&format!("$prefsJson = Set-JsonValue $prefsJson {} {}\n", powershell_quote(path), powershell_quote(&value.to_string())));
+            if protected_preferences.contains(path) {
+                content.push_str(&format!("$protectedValues[{}] = {}\n", powershell_quote(path), powershell_quote(&value.to_string())));
+            }
+        }
+        content.push_str("}\n");
+    }
     
     // Save preferences
-    content.push_str("$prefs | ConvertTo-Json -Depth 10 | Set-Content $prefsPath -Encoding UTF8\n");
+    content.push_str("Write-JsonFile $prefsPath $prefsJson\n");
+    if plan.updates_preference_macs() {
+        content.push_str("Update-PreferenceMacs $prefsPath $protectedValues\n");
+    }
+    content.push_str("}\n");
     
     // Handle Local State file
-    add_local_state_powershell(content, &experimental_features);
-    
-    content.push_str("\"\n");
-    content.push_str("echo User preferences applied successfully!\n\n");
-    
-    Ok(())
+    add_local_state_powershell(content, &plan.local_state_changes(), native);
 }
 
-fn add_dashboard_settings_powershell(content: &mut String, dashboard_config: &crate::preferences::NewTabPage) {
-    if let Some(show_clock) = dashboard_config.show_clock {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_clock = ${}\n", show_clock.to_string().to_lowercase()));
+fn add_profile_enumeration_powershell(content: &mut String) {
+    // Profiles known to Brave are listed in Local State, fall back to scanning the data directory
+    content.push_str("$profileInfo = $null\n");
+    content.push_str("if (Test-Path $localStatePath) { $profileInfo = (Read-JsonText $localStatePath | ConvertFrom-Json).profile.info_cache }\n");
+    content.push_str("$profiles = @()\n");
+    content.push_str("if ($profileInfo) { $profiles = @($profileInfo.PSObject.Properties | ForEach-Object { $_.Name }) }\n");
+    content.push_str("if (-not $profiles) { $profiles = @(Get-ChildItem $braveData -Directory -ErrorAction SilentlyContinue | Where-Object { $_.Name -eq 'Default' -or $_.Name -like 'Profile *' } | ForEach-Object { $_.Name }) }\n");
+    content.push_str("if (-not $profiles) { $profiles = @('Default') }\n");
+}
+
+fn add_preference_macs_powershell(content: &mut String, backup: bool) {
+    let protected_preferences = get_protected_preferences(MODIFIED_PREFERENCES);
+    let seed: Vec<String> = (0..PREF_HASH_SEED.len())
+        .step_by(2)
+        .map(|i| format!("0x{}", &PREF_HASH_SEED[i..i + 2]))
+        .collect();
+
+    // Brave resets protected preferences whose MAC no longer matches, so recompute
+    // HMAC-SHA256(seed, device ID + path + JSON value) for each one. The device ID is the machine SID,
+    // which LookupAccountName returns for the computer name. The user SID would give the domain SID for domain accounts.
+    content.push_str("try { $deviceId = (New-Object System.Security.Principal.NTAccount($env:COMPUTERNAME)).Translate([System.Security.Principal.SecurityIdentifier]).Value } catch { $deviceId = $null }\n");
+    content.push_str(&format!("$hmac = New-Object System.Security.Cryptography.HMACSHA256 (,[byte[]]@({}))\n", seed.join(",")));
+    content.push_str(&format!("$protectedPreferences = @({})\n", protected_preferences.iter().map(|p| format!("'{}'", p)).collect::<Vec<_>>().join(", ")));
+    content.push_str("function Get-PrefHash($message) {\n");
+    content.push_str("    -join ($hmac.ComputeHash([Text.Encoding]::UTF8.GetBytes($message)) | ForEach-Object { $_.ToString('X2') })\n");
+    content.push_str("}\n");
+    content.push_str("function Get-PrefValue($object, $path) {\n");
+    content.push_str("    foreach ($key in $path.Split('.')) { if ($null -eq $object) { return $null }; $object = $object.$key }\n");
+    content.push_str("    return $object\n");
+    content.push_str("}\n");
+    // Serializes like Chromium's pref hash calculator: sorted keys, no empty containers, '<' escaped
+    content.push_str("function ConvertTo-PrefHashJson($value) {\n");
+    content.push_str("    $quote = [string][char]34\n");
+    content.push_str("    if ($null -eq $value) { return 'null' }\n");
+    content.push_str("    if ($value -is [bool]) { if ($value) { return 'true' } else { return 'false' } }\n");
+    content.push_str("    if ($value -is [string]) {\n");
+    content.push_str("        $escaped = $value.Replace('\\', '\\\\').Replace($quote, '\\' + $quote).Replace([string][char]10, '\\n').Replace([string][char]13, '\\r').Replace([string][char]9, '\\t').Replace('<', '\\u003C')\n");
+    content.push_str("        return $quote + $escaped + $quote\n");
+    content.push_str("    }\n");
+    content.push_str("    if ($value -is [System.Collections.IDictionary] -or $value -is [System.Management.Automation.PSCustomObject]) {\n");
+    content.push_str("        $entries = @{}\n");
+    content.push_str("        if ($value -is [System.Collections.IDictionary]) { foreach ($key in $value.Keys) { $entries[$key] = $value[$key] } }\n");
+    content.push_str("        else { foreach ($property in $value.PSObject.Properties) { $entries[$property.Name] = $property.Value } }\n");
+    content.push_str("        $names = [string[]]@($entries.Keys)\n");
+    content.push_str("        [Array]::Sort($names, [StringComparer]::Ordinal)\n");
+    content.push_str("        $parts = @()\n");
+    content.push_str("        foreach ($name in $names) {\n");
+    content.push_str("            $json = ConvertTo-PrefHashJson $entries[$name]\n");
+    content.push_str("            if ($json -ne '{}' -and $json -ne '[]') { $parts += $quote + $name + $quote + ':' + $json }\n");
+    content.push_str("        }\n");
+    content.push_str("        return '{' + ($parts -join ',') + '}'\n");
+    content.push_str("    }\n");
+    content.push_str("    if ($value -is [System.Collections.IEnumerable]) { return '[' + (@($value | ForEach-Object { ConvertTo-PrefHashJson $_ }) -join ',') + ']' }\n");
+    content.push_str("    return $value.ToString([Globalization.CultureInfo]::InvariantCulture)\n");
+    content.push_str("}\n");
+    // Protected values live in Secure Preferences next to their MACs, so they are written there as well
+    // and every MAC is computed from the value in its own file
+    content.push_str("function Update-PreferenceMacs($prefsPath, $protectedValues) {\n");
+    content.push_str("    $securePrefsPath = Join-Path (Split-Path $prefsPath) 'Secure Preferences'\n");
+    content.push_str("    if ($null -eq $deviceId) {\n");
+    content.push_str("        Write-Warning ('Could not determine the machine SID, cannot update the MACs. Brave will revert these changes in ' + $prefsPath + ': ' + ($protectedPreferences -join ', '))\n");
+    content.push_str("        return\n");
+    content.push_str("    }\n");
+    if backup {
+        content.push_str("    Backup-File $securePrefsPath\n");
     }
-    if let Some(show_bg) = dashboard_config.show_background_image {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_background_image = ${}\n", show_bg.to_string().to_lowercase()));
+    content.push_str("    foreach ($macsPath in @($prefsPath, $securePrefsPath)) {\n");
+    content.push_str("        if (-not (Test-Path $macsPath)) { continue }\n");
+    content.push_str("        $macsJson = Read-JsonText $macsPath\n");
+    content.push_str("        if ($null -eq ($macsJson | ConvertFrom-Json).protection.macs) { continue }\n");
+    content.push_str("        if ($macsPath -eq $securePrefsPath) {\n");
+    content.push_str("            foreach ($path in $protectedValues.Keys) { $macsJson = Set-JsonValue $macsJson $path $protectedValues[$path] }\n");
+    content.push_str("        }\n");
+    content.push_str("        $prefs = $macsJson | ConvertFrom-Json\n");
+    content.push_str("        foreach ($path in $protectedPreferences) {\n");
+    content.push_str("            $value = Get-PrefValue $prefs $path\n");
+    content.push_str("            $valueJson = if ($null -eq $value) { '' } else { ConvertTo-PrefHashJson $value }\n");
+    content.push_str("            $macsJson = Set-JsonValue $macsJson ('protection.macs.' + $path) (ConvertTo-JsonString (Get-PrefHash ($deviceId + $path + $valueJson)))\n");
+    content.push_str("        }\n");
+    content.push_str("        $protection = ($macsJson | ConvertFrom-Json).protection\n");
+    content.push_str("        if ($protection.super_mac) { $macsJson = Set-JsonValue $macsJson 'protection.super_mac' (ConvertTo-JsonString (Get-PrefHash ($deviceId + (ConvertTo-PrefHashJson $protection.macs)))) }\n");
+    content.push_str("        Write-JsonFile $macsPath $macsJson\n");
+    content.push_str("    }\n");
+    content.push_str("}\n");
+}
+
+fn add_profile_selection_powershell(content: &mut String, profile_changes: &[ProfileChanges]) {
+    if let [ProfileChanges { profile: None, .. }] = profile_changes {
+        content.push_str("$preferenceSet = 0\n");
+        return;
     }
-    if let Some(show_stats) = dashboard_config.show_stats {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_stats = ${}\n", show_stats.to_string().to_lowercase()));
+
+    for (i, changes) in profile_changes.iter().enumerate() {
+        let profile = powershell_quote(changes.profile.unwrap_or_default());
+        content.push_str(&format!(
+            "{} ($profileDir -eq {} -or $profileName -eq {}) {{ $preferenceSet = {} }}\n",
+            if i == 0 { "if" } else { "elseif" },
+            profile,
+            profile,
+            i
+        ));
     }
-    if let Some(show_shortcuts) = dashboard_config.show_shortcuts {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_shortcuts = ${}\n", show_shortcuts.to_string().to_lowercase()));
+    content.push_str("else { Write-Host ('Skipping profile: ' + $profileDir); continue }\n");
+}
+
+fn add_shortcut_launch_flags(content: &mut String, version_suffix: &str, launch_flags: &[&str]) {
+    if launch_flags.is_empty() {
+        return;
     }
-    if let Some(show_branded) = dashboard_config.show_branded_background_image {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_branded_background_image = ${}\n", show_branded.to_string().to_lowercase()));
+
+    content.push_str("echo Adding launch flags to Brave shortcuts...\n");
+    add_shortcut_arguments_powershell(content, version_suffix, launch_flags, "foreach ($flag in $flags) { $arguments = Add-LaunchFlag $arguments $flag }");
+    content.push_str("if \"%CHECK%\"==\"1\" set /a FAILURES+=%errorlevel%\n");
+    content.push_str("if \"%DRY_RUN%\"==\"0\" echo Launch flags applied successfully!\n");
+    content.push_str("echo.\n\n");
+}
+
+/// Rewrites the arguments of every Start Menu and desktop shortcut to brave.exe with `update`,
+/// which edits `$arguments` using `$flags`.
+fn add_shortcut_arguments_powershell(content: &mut String, version_suffix: &str, launch_flags: &[&str], update: &str) {
+    let mut script = String::new();
+    script.push_str("$dryRun = $env:DRY_RUN -eq '1'\n");
+    script.push_str("$check = $env:CHECK -eq '1'\n");
+    script.push_str("$failures = 0\n");
+    add_shortcut_update_powershell(&mut script, version_suffix, launch_flags, update, false);
+    script.push_str("exit $failures\n");
+    add_encoded_powershell(content, &script);
+}
+
+fn add_shortcut_update_powershell(content: &mut String, version_suffix: &str, launch_flags: &[&str], update: &str, native: bool) {
+    content.push_str("$flags = @(\n");
+    for (i, flag) in launch_flags.iter().enumerate() {
+        content.push_str(&format!("    {}", powershell_quote(flag)));
+        if i < launch_flags.len() - 1 {
+            content.push(',');
+        }
+        content.push('\n');
     }
-    if let Some(show_cards) = dashboard_config.show_cards {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_cards = ${}\n", show_cards.to_string().to_lowercase()));
+    content.push_str(")\n");
+    add_launch_flag_functions_powershell(content);
+    content.push_str("$shell = New-Object -ComObject WScript.Shell\n");
+    content.push_str("$locations = @('Programs', 'CommonPrograms', 'Desktop', 'CommonDesktopDirectory') | ForEach-Object { [Environment]::GetFolderPath($_) }\n");
+    content.push_str("foreach ($location in $locations) {\n");
+    content.push_str("    if (-not (Test-Path $location)) { continue }\n");
+    content.push_str("    Get-ChildItem -Path $location -Filter *.lnk -Recurse -ErrorAction SilentlyContinue | ForEach-Object {\n");
+    content.push_str("        $shortcut = $shell.CreateShortcut($_.FullName)\n");
+    content.push_str(&format!("        if ($shortcut.TargetPath -like '*\\{}\\Application\\brave.exe') {{\n", version_suffix));
+    content.push_str("            $arguments = @(Split-Arguments $shortcut.Arguments)\n");
+    content.push_str(&format!("            {}\n", update));
+    content.push_str("            if ($check) {\n");
+    content.push_str("                if (($arguments -join ' ') -eq $shortcut.Arguments) { Write-Host ('    PASS  ' + $_.FullName) }\n");
+    content.push_str("                else { Write-Host ('    FAIL  ' + $_.FullName + ': ' + $shortcut.Arguments + ' (expected ' + ($arguments -join ' ') + ')'); $script:failures++ }\n");
+    content.push_str("                return\n");
+    content.push_str("            }\n");
+    content.push_str("            if ($dryRun) { Write-Host ('Would set arguments of ' + $_.FullName + ': ' + $shortcut.Arguments + ' -> ' + ($arguments -join ' ')); return }\n");
+    if native {
+        content.push_str("            if (-not $PSCmdlet.ShouldProcess($_.FullName, 'Set shortcut arguments')) { return }\n");
     }
-    if let Some(show_search) = dashboard_config.show_search_widget {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_search_widget = ${}\n", show_search.to_string().to_lowercase()));
+    content.push_str("            $shortcut.Arguments = $arguments -join ' '\n");
+    content.push_str("            $shortcut.Save()\n");
+    content.push_str("            Write-Host ('Updated ' + $_.FullName)\n");
+    content.push_str("        }\n");
+    content.push_str("    }\n");
+    content.push_str("}\n");
+}
+
+/// Defines `Split-Arguments`, which splits shortcut arguments on spaces outside double quotes, and
+/// `Add-LaunchFlag`/`Remove-LaunchFlag`, which keep a single occurrence of every list switch.
+fn add_launch_flag_functions_powershell(content: &mut String) {
+    let list_switches: Vec<String> = LIST_SWITCHES.iter().map(|s| powershell_quote(s)).collect();
+    content.push_str(&format!("$listSwitches = @({})\n", list_switches.join(", ")));
+    content.push_str("function Split-Arguments($text) {\n");
+    content.push_str("    $arguments = @(); $current = ''; $quoted = $false\n");
+    content.push_str("    foreach ($c in \"$text\".ToCharArray()) {\n");
+    content.push_str("        if ($c -eq [char]34) { $quoted = -not $quoted }\n");
+    content.push_str("        if ($c -eq ' ' -and -not $quoted) { if ($current.Length) { $arguments += $current }; $current = '' } else { $current += $c }\n");
+    content.push_str("    }\n");
+    content.push_str("    if ($current.Length) { $arguments += $current }\n");
+    content.push_str("    return ,$arguments\n");
+    content.push_str("}\n");
+    // Chromium only reads the last occurrence of a switch, so the values of a list switch are merged into its first one
+    content.push_str("function Add-LaunchFlag($arguments, $flag) {\n");
+    content.push_str("    $name = $flag.Split('=')[0]\n");
+    content.push_str("    if ($flag -notlike '*=*' -or $listSwitches -notcontains $name) {\n");
+    content.push_str("        if ($arguments -notcontains $flag) { $arguments += $flag }\n");
+    content.push_str("        return ,@($arguments)\n");
+    content.push_str("    }\n");
+    content.push_str("    $values = New-Object System.Collections.Generic.List[string]\n");
+    content.push_str("    $result = @(); $index = -1\n");
+    content.push_str("    foreach ($argument in @($arguments) + $flag) {\n");
+    content.push_str("        if ($argument -like ($name + '=*')) {\n");
+    content.push_str("            foreach ($value in $argument.Substring($name.Length + 1).Split(',')) { if ($value -and -not $values.Contains($value)) { $values.Add($value) } }\n");
+    content.push_str("            if ($index -lt 0) { $index = $result.Count; $result += $null }\n");
+    content.push_str("        } else { $result += $argument }\n");
+    content.push_str("    }\n");
+    content.push_str("    $result[$index] = $name + '=' + ($values -join ',')\n");
+    content.push_str("    return ,$result\n");
+    content.push_str("}\n");
+    content.push_str("function Remove-LaunchFlag($arguments, $flag) {\n");
+    content.push_str("    $name = $flag.Split('=')[0]\n");
+    content.push_str("    if ($flag -notlike '*=*' -or $listSwitches -notcontains $name) { return ,@($arguments | Where-Object { $_ -ne $flag }) }\n");
+    content.push_str("    $removed = $flag.Substring($name.Length + 1).Split(',')\n");
+    content.push_str("    $result = @()\n");
+    content.push_str("    foreach ($argument in $arguments) {\n");
+    content.push_str("        if ($argument -notlike ($name + '=*')) { $result += $argument; continue }\n");
+    content.push_str("        $values = @($argument.Substring($name.Length + 1).Split(',') | Where-Object { $_ -and $removed -notcontains $_ })\n");
+    content.push_str("        if ($values) { $result += $name + '=' + ($values -join ',') }\n");
+    content.push_str("    }\n");
+    content.push_str("    return ,$result\n");
+    content.push_str("}\n");
+}
+
+fn add_local_state_powershell(content: &mut String, changes: &[(&str, &Value)], native: bool) {
+    content.push_str("if ($dryRun) {\n");
+    content.push_str("    Write-Host ('Local State file: ' + $localStatePath)\n");
+    content.push_str("    $localState = if (Test-Path $localStatePath) { Read-JsonText $localStatePath | ConvertFrom-Json } else { $null }\n");
+    for (path, value) in changes {
+        content.push_str(&format!("    Show-PrefChange $localState {} {}\n", powershell_quote(path), powershell_literal(value)));
     }
-    if let Some(show_news) = dashboard_config.show_brave_news {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_brave_news = ${}\n", show_news.to_string().to_lowercase()));
+    if native {
+        content.push_str("} elseif ($PSCmdlet.ShouldProcess($localStatePath, 'Update Local State')) {\n");
+    } else {
+        content.push_str("} else {\n");
     }
-    if let Some(show_together) = dashboard_config.show_together {
-        content.push_str(&format!("$prefs.brave.new_tab_page.show_together = ${}\n", show_together.to_string().to_lowercase()));
+    content.push_str("if (Test-Path $localStatePath) {\n");
+    content.push_str("    $localStateJson = Read-JsonText $localStatePath\n");
+    content.push_str("} else {\n");
+    content.push_str("    $localStateJson = '{}'\n");
+    content.push_str("}\n");
+    for (path, value) in changes {
+        content.push_str(&format!("$localStateJson = Set-JsonValue $localStateJson {} {}\n", powershell_quote(path), powershell_quote(&value.to_string())));
+    }
+    content.push_str("Write-JsonFile $localStatePath $localStateJson\n");
+    content.push_str("Remove-OldBackups\n");
+    content.push_str("}\n");
+}
+/// Parses `/dryrun`, plus `/check` for debloat scripts or `/restore <timestamp|latest>` for revert scripts.
+/// Check mode implies dry run.
+fn add_script_options(content: &mut String, revert: bool) {
+    content.push_str("set \"DRY_RUN=0\"\n");
+    content.push_str("set \"CHECK=0\"\n");
+    content.push_str("set \"FAILURES=0\"\n");
+    content.push_str("set \"RESTORE=\"\n");
+    content.push_str("if /i \"%~1\"==\"/dryrun\" set \"DRY_RUN=1\"\n");
+    if revert {
+        content.push_str("if /i \"%~1\"==\"/restore\" set \"RESTORE=%~2\"\n");
+        content.push_str("if /i \"%~2\"==\"/dryrun\" set \"DRY_RUN=1\"\n");
+        content.push_str("if /i \"%~3\"==\"/dryrun\" set \"DRY_RUN=1\"\n");
+    } else {
+        content.push_str("if /i \"%~1\"==\"/check\" set \"CHECK=1\"\n");
+        content.push_str("if \"%CHECK%\"==\"1\" set \"DRY_RUN=1\"\n");
+        content.push_str("if \"%CHECK%\"==\"1\" echo Check: comparing the current configuration against the generated one\n");
     }
+    content.push_str("if \"%DRY_RUN%%CHECK%\"==\"10\" echo Dry run: showing changes without applying them\n\n");
 }
 
-fn add_local_state_powershell(content: &mut String, experimental_features: &[String]) {
-    content.push_str("if (Test-Path $localStatePath) {\n");
-    content.push_str("    $localState = Get-Content $localStatePath -Raw | ConvertFrom-Json\n");
+fn add_completion_message(content: &mut String, message: &str) {
+    content.push_str("if \"%CHECK%\"==\"1\" goto :check_summary\n");
+    content.push_str("if \"%DRY_RUN%\"==\"1\" (\n");
+    content.push_str("    echo Dry run complete, no changes were made.\n");
+    content.push_str(") else (\n");
+    content.push_str(&format!("    echo {}\n", message));
+    content.push_str("    echo Please restart Brave browser for changes to take effect.\n");
+    content.push_str(")\n");
+    content.push_str("pause\n");
+    content.push_str("exit /b 0\n\n");
+
+    // No pause in check mode so it can run unattended
+    content.push_str(":check_summary\n");
+    content.push_str("echo.\n");
+    content.push_str("if \"%FAILURES%\"==\"0\" (\n");
+    content.push_str("    echo All settings match the generated configuration.\n");
+    content.push_str("    exit /b 0\n");
+    content.push_str(")\n");
+    content.push_str("echo %FAILURES% settings differ from the generated configuration.\n");
+    content.push_str("exit /b 1\n\n");
+}
+
+fn add_registry_preview(content: &mut String, key_path: &str, name: &str, data: &str) {
+    content.push_str(&format!("{}\n", batch_set("REG_NAME", name)));
+    content.push_str(&format!("{}\n", batch_set("REG_DATA", data)));
+    content.push_str(&format!("call :show_reg_change \"{}\"\n", key_path));
+}
+
+fn add_registry_preview_subroutines(content: &mut String) {
+    // Prints the current data of the registry value REG_NAME next to REG_DATA. Both are read with
+    // delayed expansion, so cmd never parses the config values again.
+    content.push_str(":show_reg_change\n");
+    content.push_str("set \"CURRENT=(not set)\"\n");
+    content.push_str("for /f \"tokens=2,*\" %%A in ('reg query \"%~1\" /v \"!REG_NAME!\" 2^>nul ^| find \"REG_\"') do set \"CURRENT=%%B\"\n");
+    content.push_str("if \"%CHECK%\"==\"1\" goto :check_reg_value\n");
+    content.push_str("if not \"!CURRENT!\"==\"!REG_DATA!\" goto :show_reg_changed\n");
+    content.push_str("echo     !REG_NAME!: !CURRENT! (unchanged)\n");
+    content.push_str("exit /b\n");
+    content.push_str(":show_reg_changed\n");
+    content.push_str("echo     !REG_NAME!: !CURRENT! -^> !REG_DATA!\n");
+    content.push_str("exit /b\n");
+    content.push_str(":check_reg_value\n");
+    content.push_str("if not \"!CURRENT!\"==\"!REG_DATA!\" goto :check_reg_failed\n");
+    content.push_str("echo     PASS  !REG_NAME!\n");
+    content.push_str("exit /b\n");
+    content.push_str(":check_reg_failed\n");
+    content.push_str("echo     FAIL  !REG_NAME!: !CURRENT! (expected !REG_DATA!)\n");
+    content.push_str("set /a FAILURES+=1\n");
+    content.push_str("exit /b\n\n");
+
+    content.push_str(":show_reg_removal\n");
+    content.push_str("for /f \"tokens=2,*\" %%A in ('reg query \"%~1\" /v \"!REG_NAME!\" 2^>nul ^| find \"REG_\"') do echo     Would delete %~1\\!REG_NAME!: %%B\n");
+    content.push_str("exit /b\n");
+}
+
+fn add_change_preview_powershell(content: &mut String) {
+    // Prints the current and new value, both as compact JSON, or PASS/FAIL in check mode
+    content.push_str("function Show-Change($name, $current, $new) {\n");
+    content.push_str("    if ($check) {\n");
+    content.push_str("        if ($current -eq $new) { Write-Host ('    PASS  ' + $name) }\n");
+    content.push_str("        else { Write-Host ('    FAIL  ' + $name + ': ' + $current + ' (expected ' + $new + ')'); $script:failures++ }\n");
+    content.push_str("    }\n");
+    content.push_str("    elseif ($current -eq $new) { Write-Host ('    ' + $name + ': ' + $current + ' (unchanged)') }\n");
+    content.push_str("    else { Write-Host ('    ' + $name + ': ' + $current + ' -> ' + $new) }\n");
+    content.push_str("}\n");
+    content.push_str("function Show-PrefChange($object, $path, $value) {\n");
+    content.push_str("    Show-Change $path (ConvertTo-PrefHashJson (Get-PrefValue $object $path)) (ConvertTo-PrefHashJson $value)\n");
+    content.push_str("}\n");
+}
+
+/// Formats a JSON value as a PowerShell expression.
+fn powershell_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "$null".to_string(),
+        Value::Bool(b) => format!("${}", b),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => powershell_quote(s),
+        Value::Array(items) => format!("@({})", items.iter().map(powershell_literal).collect::<Vec<_>>().join(", ")),
+        Value::Object(map) => format!(
+            "[pscustomobject]@{{ {} }}",
+            map.iter().map(|(k, v)| format!("{} = {}", powershell_quote(k), powershell_literal(v))).collect::<Vec<_>>().join("; ")
+        ),
+    }
+}
+
+fn add_backup_snapshot_powershell(content: &mut String, retention: usize) {
+    // Each run copies the files it changes into a new timestamped snapshot, listed in manifest.txt
+    content.push_str(&format!("$backupRoot = Join-Path $braveData '{}'\n", BACKUP_DIR_NAME));
+    content.push_str("$backupDir = Join-Path $backupRoot (Get-Date -Format 'yyyyMMdd-HHmmss')\n");
+    content.push_str(&format!("$backupRetention = {}\n", retention));
+    content.push_str("function Backup-File($path) {\n");
+    content.push_str("    if (-not (Test-Path $path)) { return }\n");
+    content.push_str("    $relativePath = $path.Substring($braveData.Length).TrimStart('\\')\n");
+    content.push_str("    $target = Join-Path $backupDir $relativePath\n");
+    content.push_str("    if (Test-Path $target) { return }\n");
+    content.push_str("    New-Item -ItemType Directory -Force -Path (Split-Path $target) | Out-Null\n");
+    content.push_str("    Copy-Item $path $target -Force\n");
+    content.push_str("    Add-Content (Join-Path $backupDir 'manifest.txt') $relativePath\n");
+    content.push_str("}\n");
+    content.push_str("function Remove-OldBackups {\n");
+    content.push_str("    if (Test-Path $backupDir) { Write-Host ('Backup saved to: ' + $backupDir) }\n");
+    // The oldest snapshot holds the files from before the first run and is never removed
+    content.push_str("    Get-ChildItem $backupRoot -Directory -ErrorAction SilentlyContinue | Sort-Object Name | Select-Object -Skip 1 |\n");
+    content.push_str("        Sort-Object Name -Descending | Select-Object -Skip $backupRetention | Remove-Item -Recurse -Force\n");
+    content.push_str("}\n");
+}
+
+/// Defines `Read-JsonText`, `Set-JsonValue` and `Write-JsonFile`, which edit JSON files as text.
+///
+/// Only the targeted values are replaced or inserted, so the rest of the file keeps its exact text:
+/// nothing is lost to a depth limit or a number conversion. Files are written as UTF-8 without a BOM, as Brave does.
+pub fn add_json_editing_powershell(content: &mut String) {
+    content.push_str("$jsonStructure = [char[]]'\"{}[]'\n");
+    content.push_str("$jsonStringEnd = [char[]]'\"\\'\n");
+    content.push_str("$jsonScalarEnd = [char[]](\",}] `t`r`n\")\n");
+    content.push_str("function Read-JsonText($path) {\n");
+    content.push_str("    return [System.IO.File]::ReadAllText($path)\n");
+    content.push_str("}\n");
+    content.push_str("function ConvertTo-JsonString($value) {\n");
+    content.push_str("    return '\"' + [regex]::Replace($value, '[\"\\\\\\x00-\\x1f]', { param($m) '\\u{0:x4}' -f [int][char]$m.Value }) + '\"'\n");
+    content.push_str("}\n");
+    content.push_str("function Skip-JsonSpace($text, $i) {\n");
+    content.push_str("    while ($i -lt $text.Length -and [char]::IsWhiteSpace($text[$i])) { $i++ }\n");
+    content.push_str("    return $i\n");
+    content.push_str("}\n");
+    content.push_str("function Skip-JsonString($text, $i) {\n");
+    content.push_str("    $i++\n");
+    content.push_str("    while ($true) {\n");
+    content.push_str("        $i = $text.IndexOfAny($jsonStringEnd, $i)\n");
+    content.push_str("        if ($i -lt 0) { throw 'unterminated JSON string' }\n");
+    content.push_str("        if ($text[$i] -eq '\"') { return $i + 1 }\n");
+    content.push_str("        $i += 2\n");
+    content.push_str("    }\n");
+    content.push_str("}\n");
+    // Returns the index after the value starting at $i, jumping from one structural character to the next
+    content.push_str("function Skip-JsonValue($text, $i) {\n");
+    content.push_str("    $depth = 0\n");
+    content.push_str("    do {\n");
+    content.push_str("        $c = $text[$i]\n");
+    content.push_str("        if ($c -eq '\"') { $i = Skip-JsonString $text $i }\n");
+    content.push_str("        elseif ($c -eq '{' -or $c -eq '[') { $depth++; $i++ }\n");
+    content.push_str("        elseif ($c -eq '}' -or $c -eq ']') { $depth--; $i++ }\n");
+    content.push_str("        elseif ($depth -gt 0) { $i = $text.IndexOfAny($jsonStructure, $i) }\n");
+    content.push_str("        else { $i = $text.IndexOfAny($jsonScalarEnd, $i); if ($i -lt 0) { $i = $text.Length } }\n");
+    content.push_str("        if ($i -lt 0) { throw 'unexpected end of JSON' }\n");
+    content.push_str("    } while ($depth -gt 0)\n");
+    content.push_str("    return $i\n");
+    content.push_str("}\n");
+    // Finds the value of $key in the object starting at $i, or the closing brace to insert it before
+    content.push_str("function Find-JsonMember($text, $i, $key) {\n");
+    content.push_str("    $i = Skip-JsonSpace $text ($i + 1)\n");
+    content.push_str("    $empty = $true\n");
+    content.push_str("    while ($text[$i] -ne '}') {\n");
+    content.push_str("        if ($text[$i] -ne '\"') { throw ('invalid JSON object at character ' + $i) }\n");
+    content.push_str("        $nameEnd = Skip-JsonString $text $i\n");
+    content.push_str("        $name = $text.Substring($i + 1, $nameEnd - $i - 2)\n");
+    content.push_str("        if ($name.Contains('\\')) { $name = [regex]::Unescape($name) }\n");
+    content.push_str("        $start = Skip-JsonSpace $text ((Skip-JsonSpace $text $nameEnd) + 1)\n");
+    content.push_str("        $end = Skip-JsonValue $text $start\n");
+    content.push_str("        if ($name -ceq $key) { return @{ Found = $true; Start = $start; End = $end } }\n");
+    content.push_str("        $empty = $false\n");
+    content.push_str("        $i = Skip-JsonSpace $text $end\n");
+    content.push_str("        if ($text[$i] -eq ',') { $i = Skip-JsonSpace $text ($i + 1) }\n");
+    content.push_str("    }\n");
+    content.push_str("    return @{ Found = $false; Start = $i; Empty = $empty }\n");
+    content.push_str("}\n");
+    content.push_str("function New-JsonNesting($keys, $from, $value) {\n");
+    content.push_str("    for ($j = $keys.Length - 1; $j -ge $from; $j--) { $value = '{' + (ConvertTo-JsonString $keys[$j]) + ':' + $value + '}' }\n");
+    content.push_str("    return $value\n");
+    content.push_str("}\n");
+    // Sets the dotted $path to the JSON text $value, creating missing objects and replacing values that are not objects
+    content.push_str("function Set-JsonValue($text, $path, $value) {\n");
+    content.push_str("    $keys = $path.Split('.')\n");
+    content.push_str("    $i = Skip-JsonSpace $text 0\n");
+    content.push_str("    for ($k = 0; $k -lt $keys.Length; $k++) {\n");
+    content.push_str("        $member = Find-JsonMember $text $i $keys[$k]\n");
+    content.push_str("        if (-not $member.Found) {\n");
+    content.push_str("            $json = (ConvertTo-JsonString $keys[$k]) + ':' + (New-JsonNesting $keys ($k + 1) $value)\n");
+    content.push_str("            if (-not $member.Empty) { $json = ',' + $json }\n");
+    content.push_str("            return $text.Substring(0, $member.Start) + $json + $text.Substring($member.Start)\n");
+    content.push_str("        }\n");
+    content.push_str("        if ($k -eq $keys.Length - 1 -or $text[$member.Start] -ne '{') {\n");
+    content.push_str("            return $text.Substring(0, $member.Start) + (New-JsonNesting $keys ($k + 1) $value) + $text.Substring($member.End)\n");
+    content.push_str("        }\n");
+    content.push_str("        $i = $member.Start\n");
+    content.push_str("    }\n");
+    content.push_str("}\n");
+
+    // Write to a temporary file next to the target and only replace the original once it is valid JSON.
+    // File.Replace keeps the owner and ACL of the original.
+    content.push_str("function Write-JsonFile($path, $json) {\n");
+    content.push_str("    $tmpPath = $path + '.tmp'\n");
+    content.push_str("    try {\n");
+    content.push_str("        [System.IO.File]::WriteAllText($tmpPath, $json, (New-Object System.Text.UTF8Encoding $false))\n");
+    content.push_str("        if ((Get-Item $tmpPath).Length -eq 0) { throw 'the temporary file is empty' }\n");
+    content.push_str("        $parsed = Read-JsonText $tmpPath | ConvertFrom-Json -ErrorAction Stop\n");
+    content.push_str("        if ($parsed -isnot [System.Management.Automation.PSCustomObject]) { throw 'the generated JSON is not an object' }\n");
+    content.push_str("        if (Test-Path $path) { [System.IO.File]::Replace($tmpPath, $path, $null) } else { Move-Item $tmpPath $path -ErrorAction Stop }\n");
+    content.push_str("    } catch {\n");
+    content.push_str("        Remove-Item $tmpPath -Force -ErrorAction SilentlyContinue\n");
+    content.push_str("        Write-Host ('Error: could not write ' + $path + ', it was left untouched: ' + $_)\n");
+    content.push_str("        exit 1\n");
+    content.push_str("    }\n");
+    content.push_str("}\n");
+}
+
+fn add_snapshot_selection(content: &mut String) {
+    // Fail before changing anything when the snapshot given with /restore does not exist
+    content.push_str(&format!("set \"BACKUP_ROOT=%BRAVE_DATA%\\{}\"\n", BACKUP_DIR_NAME));
+    content.push_str("if \"%RESTORE%\"==\"\" goto :snapshot_selected\n");
+    content.push_str(&format!("if /i \"%RESTORE%\"==\"{}\" goto :snapshot_selected\n", LATEST_SNAPSHOT));
+    content.push_str("if exist \"%BACKUP_ROOT%\\%RESTORE%\\manifest.txt\" goto :snapshot_selected\n");
+    content.push_str("echo Error: no backup snapshot %RESTORE% in %BACKUP_ROOT%\n");
+    content.push_str("echo Available snapshots:\n");
+    content.push_str("dir /b /ad /o-n \"%BACKUP_ROOT%\" 2>nul\n");
+    content.push_str("exit /b 1\n");
+    content.push_str(":snapshot_selected\n\n");
+}
+
+/// Restores the snapshot given with `/restore`, or the oldest one.
+fn add_snapshot_restore_powershell(content: &mut String) {
+    let mut script = String::new();
+    script.push_str("$braveData = $env:BRAVE_DATA\n");
+    script.push_str("$backupRoot = $env:BACKUP_ROOT\n");
+    script.push_str("$restore = $env:RESTORE\n");
+    script.push_str("$dryRun = $env:DRY_RUN -eq '1'\n");
+    add_snapshot_copy_powershell(&mut script, false);
+    add_encoded_powershell(content, &script);
+    content.push_str("echo.\n\n");
+}
+
+fn add_snapshot_copy_powershell(content: &mut String, native: bool) {
+    // Without a snapshot name, the oldest one holds the files from before the first run
+    content.push_str("$snapshots = @(Get-ChildItem $backupRoot -Directory -ErrorAction SilentlyContinue | Sort-Object Name | Select-Object -ExpandProperty Name)\n");
+    content.push_str("if (-not $restore) { $restore = $snapshots | Select-Object -First 1 }\n");
+    content.push_str(&format!("elseif ($restore -eq '{}') {{ $restore = $snapshots | Select-Object -Last 1 }}\n", LATEST_SNAPSHOT));
+    content.push_str("$snapshot = if ($restore) { Join-Path $backupRoot $restore } else { $null }\n");
+    content.push_str("if (-not $snapshot -or -not (Test-Path (Join-Path $snapshot 'manifest.txt'))) {\n");
+    content.push_str("    Write-Host ('No backup snapshot found in ' + $backupRoot)\n");
     content.push_str("} else {\n");
-    content.push_str("    $localState = @{}\n");
+    content.push_str("    Write-Host ('Restoring backup snapshot: ' + $restore)\n");
+    content.push_str("    foreach ($relativePath in Get-Content (Join-Path $snapshot 'manifest.txt')) {\n");
+    content.push_str("        $target = Join-Path $braveData $relativePath\n");
+    content.push_str("        if ($dryRun) { Write-Host ('Would restore ' + $target); continue }\n");
+    if native {
+        content.push_str("        if (-not $PSCmdlet.ShouldProcess($target, 'Restore from backup')) { continue }\n");
+    }
+    content.push_str("        Copy-Item (Join-Path $snapshot $relativePath) $target -Force\n");
+    content.push_str("        Write-Host ('Restored ' + $target)\n");
+    content.push_str("    }\n");
     content.push_str("}\n");
-    content.push_str("if (-not $localState.browser) { $localState.browser = @{} }\n");
-    content.push_str("$localState.browser.enabled_labs_experiments = @(\n");
-    for (i, feature) in experimental_features.iter().enumerate() {
-        content.push_str(&format!("    '{}'", feature));
-        if i < experimental_features.len() - 1 {
-            content.push_str(",");
-        }
-        content.push_str("\n");
+}
+
+/// Runs a PowerShell script from a batch file as `-EncodedCommand`, so cmd never parses its code or values.
+/// The script reads the batch variables through `$env:`. Scripts too long for one batch line are stored
+/// in numbered environment variables and run by a short encoded loader.
+fn add_encoded_powershell(content: &mut String, script: &str) {
+    let encoded = encode_powershell(script);
+    if encoded.len() <= MAX_BATCH_CHUNK {
+        content.push_str(&format!("powershell -NoProfile -ExecutionPolicy Bypass -EncodedCommand {}\n", encoded));
+        return;
     }
-    content.push_str(")\n");
-    content.push_str("$localState | ConvertTo-Json -Depth 10 | Set-Content $localStatePath -Encoding UTF8\n");
-}
\ No newline at end of file
+
+    // Base64 needs no escaping in batch files
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(MAX_BATCH_CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        content.push_str(&format!("set \"PS_SCRIPT_{}={}\"\n", i + 1, String::from_utf8_lossy(chunk)));
+    }
+    // Dot-sourcing keeps the script's variables in the top-level scope, as when it runs directly
+    let loader = format!(
+        "$encoded = -join (1..{} | ForEach-Object {{ [Environment]::GetEnvironmentVariable('PS_SCRIPT_' + $_) }})\n\
+         . ([scriptblock]::Create([Text.Encoding]::Unicode.GetString([Convert]::FromBase64String($encoded))))\n",
+        chunks.len()
+    );
+    content.push_str(&format!("powershell -NoProfile -ExecutionPolicy Bypass -EncodedCommand {}\n", encode_powershell(&loader)));
+}
+
+/// Encodes a script for `-EncodedCommand`: UTF-16LE, then Base64.
+fn encode_powershell(script: &str) -> String {
+    let bytes: Vec<u8> = script.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+    BASE64.encode(bytes)
+}
@@ -0,0 +1,145 @@
+use thiserror::Error;
+
+use super::types::ExperimentalFeature;
+
+/// Options of a `FEATURE_VALUE_TYPE`/`ENABLE_DISABLE_VALUE` flag, in `chrome://flags` order.
+const FEATURE_OPTIONS: &[&str] = &["Default", "Enabled", "Disabled"];
+
+/// Options of a `SINGLE_VALUE` flag.
+const SWITCH_OPTIONS: &[&str] = &["Enabled"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagKind {
+    /// `SINGLE_VALUE` flags are stored by bare name and can only be enabled.
+    Switch,
+    /// Stored as `name@index` of the selected option.
+    Options,
+}
+
+#[derive(Debug)]
+pub struct FlagDefinition {
+    pub name: &'static str,
+    pub kind: FlagKind,
+    pub options: &'static [&'static str],
+}
+
+impl FlagDefinition {
+    pub fn is_switch(&self) -> bool {
+        self.kind == FlagKind::Switch
+    }
+
+    pub fn option_index(&self, option: &str) -> Option<usize> {
+        self.options.iter().position(|o| o.eq_ignore_ascii_case(option))
+    }
+}
+
+/// Known `chrome://flags` internal names shipped with Brave.
+pub static FLAG_CATALOG: &[FlagDefinition] = &[
+    // Brave
+    FlagDefinition { name: "brave-adblock-cname-uncloaking", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-collapse-blocked-elements", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-cookie-list-default", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-cookie-list-opt-in", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-default-1p-blocking", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-experimental-list-default", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-mobile-notifications-list-default", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-adblock-scriptlet-debug-logs", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-ai-chat", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-de-amp", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-debounce", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-domain-block", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-extension-network-blocking", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-forget-first-party-storage", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-ntp-branded-wallpaper-demo", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-playlist", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-reduce-language", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-rewards-verbose-logging", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-speedreader", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "brave-vpn", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    // Chromium
+    FlagDefinition { name: "back-forward-cache", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "enable-experimental-web-platform-features", kind: FlagKind::Switch, options: SWITCH_OPTIONS },
+    FlagDefinition { name: "enable-gpu-rasterization", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "enable-parallel-downloading", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "enable-quic", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "enable-vulkan", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "enable-webrtc-hide-local-ips-with-mdns", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "enable-zero-copy", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "ignore-gpu-blocklist", kind: FlagKind::Switch, options: SWITCH_OPTIONS },
+    FlagDefinition { name: "overlay-scrollbars", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "ozone-platform-hint", kind: FlagKind::Options, options: &["Default", "Auto", "X11", "Wayland"] },
+    FlagDefinition { name: "smooth-scrolling", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "strict-origin-isolation", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+    FlagDefinition { name: "tab-hover-card-images", kind: FlagKind::Options, options: FEATURE_OPTIONS },
+];
+
+#[derive(Error, Debug)]
+pub enum FlagWarning {
+    #[error("unknown experimental flag '{0}'")]
+    UnknownFlag(String),
+    #[error("flag '{flag}' has no option '{option}' (available: {available})")]
+    UnknownOption { flag: String, option: String, available: String },
+    #[error("flag '{flag}' has no option index {index} (available: 0-{max})")]
+    InvalidIndex { flag: String, index: String, max: usize },
+    #[error("flag '{0}' needs an option index, e.g. '{0}@1'")]
+    MissingIndex(String),
+    #[error("flag '{0}' is a switch and does not take an option index")]
+    UnexpectedIndex(String),
+}
+
+pub fn find_flag(name: &str) -> Option<&'static FlagDefinition> {
+    FLAG_CATALOG.iter().find(|f| f.name == name)
+}
+
+/// Compiles an experimental feature into its `enabled_labs_experiments` entry.
+///
+/// Raw entries are always kept so flags missing from the catalog still work, but
+/// structured entries that cannot be resolved are dropped.
+pub fn compile_experimental_feature(feature: &ExperimentalFeature) -> (Option<String>, Option<FlagWarning>) {
+    match feature {
+        ExperimentalFeature::Raw(entry) => (Some(entry.clone()), validate_raw_entry(entry)),
+        ExperimentalFeature::Flag { flag, option } => {
+            let Some(definition) = find_flag(flag) else {
+                return (None, Some(FlagWarning::UnknownFlag(flag.clone())));
+            };
+            match definition.option_index(option) {
+                Some(_) if definition.is_switch() => (Some(definition.name.to_string()), None),
+                Some(index) => (Some(format!("{}@{}", definition.name, index)), None),
+                None => (
+                    None,
+                    Some(FlagWarning::UnknownOption {
+                        flag: flag.clone(),
+                        option: option.clone(),
+                        available: definition.options.join(", "),
+                    }),
+                ),
+            }
+        }
+    }
+}
+
+/// Checks a raw `name` or `name@index` entry against the catalog.
+pub fn validate_raw_entry(entry: &str) -> Option<FlagWarning> {
+    let (name, index) = match entry.split_once('@') {
+        Some((name, index)) => (name, Some(index)),
+        None => (entry, None),
+    };
+
+    let Some(definition) = find_flag(name) else {
+        return Some(FlagWarning::UnknownFlag(name.to_string()));
+    };
+
+    match index {
+        None if definition.is_switch() => None,
+        None => Some(FlagWarning::MissingIndex(name.to_string())),
+        Some(_) if definition.is_switch() => Some(FlagWarning::UnexpectedIndex(name.to_string())),
+        Some(index) => match index.parse::<usize>() {
+            Ok(i) if i < definition.options.len() => None,
+            _ => Some(FlagWarning::InvalidIndex {
+                flag: name.to_string(),
+                index: index.to_string(),
+                max: definition.options.len() - 1,
+            }),
+        },
+    }
+}
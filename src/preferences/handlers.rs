@@ -1,3 +1,6 @@
+use serde_json::Value;
+
+use super::flags::compile_experimental_feature;
 use super::types::*;
 
 pub fn get_default_search_provider(prefs_config: Option<&PreferencesInputConfig>) -> SearchProvider {
@@ -27,8 +30,166 @@ pub fn get_default_dashboard_config(prefs_config: Option<&PreferencesInputConfig
         })
 }
 
+/// The preference set applied to one profile, or to every profile when `profile` is `None`.
+#[derive(Debug, Clone)]
+pub struct ProfilePreferenceSet {
+    pub profile: Option<String>,
+    pub search_provider: SearchProvider,
+    pub dashboard: NewTabPage,
+}
+
+pub fn get_profile_preference_sets(prefs_config: Option<&PreferencesInputConfig>) -> Vec<ProfilePreferenceSet> {
+    let search_provider = get_default_search_provider(prefs_config);
+    let dashboard = get_default_dashboard_config(prefs_config);
+
+    let profiles = prefs_config.map(|p| p.profiles.as_slice()).unwrap_or_default();
+    if profiles.is_empty() {
+        return vec![ProfilePreferenceSet { profile: None, search_provider, dashboard }];
+    }
+
+    profiles
+        .iter()
+        .map(|p| ProfilePreferenceSet {
+            profile: Some(p.name.clone()),
+            search_provider: p.search_engine.clone().unwrap_or_else(|| search_provider.clone()),
+            dashboard: p.dashboard.clone().unwrap_or_else(|| dashboard.clone()),
+        })
+        .collect()
+}
+
+/// Builds the Preferences changes of a preference set from the shared preferences model.
+pub fn get_user_preferences(preference_set: &ProfilePreferenceSet) -> UserPreferences {
+    UserPreferences {
+        default_search_provider_data: Some(DefaultSearchProviderData {
+            template_url_data: Some(TemplateUrlData::from(&preference_set.search_provider)),
+        }),
+        brave: Some(BravePreferences {
+            new_tab_page: Some(preference_set.dashboard.clone()),
+            stats: Some(BraveStats { enabled: Some(false) }),
+            today: Some(BraveToday { should_show_brave_today_widget: Some(false) }),
+        }),
+        browser: None,
+    }
+}
+
+/// Dictionaries that are written whole instead of key by key, so that no key of the previous value
+/// (such as the `prepopulate_id` of a built-in search engine) is mixed into the new one.
+pub const REPLACED_PREFERENCES: &[&str] = &["default_search_provider_data.template_url_data"];
+
+/// Flattens preference changes into dotted leaf paths, keeping lists and [`REPLACED_PREFERENCES`] whole.
+pub fn get_preference_values(value: &Value) -> Vec<(String, Value)> {
+    let mut values = Vec::new();
+    collect_preference_values(value, String::new(), &mut values);
+    values
+}
+
+/// Builds the nested object described by dotted preference paths, the inverse of [`get_preference_values`].
+pub fn nest_preference_values(values: &[(&str, &Value)]) -> Value {
+    let mut root = serde_json::Map::new();
+    for (path, value) in values {
+        let mut keys: Vec<&str> = path.split('.').collect();
+        let last = keys.pop().unwrap_or_default();
+        let mut map = &mut root;
+        for key in keys {
+            let child = map.entry(key.to_string()).or_insert_with(|| Value::Object(serde_json::Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(serde_json::Map::new());
+            }
+            map = child.as_object_mut().expect("replaced by an object above");
+        }
+        map.insert(last.to_string(), (*value).clone());
+    }
+    Value::Object(root)
+}
+
+fn collect_preference_values(value: &Value, path: String, values: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) if !REPLACED_PREFERENCES.contains(&path.as_str()) => {
+            for (key, child) in map {
+                let child_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                collect_preference_values(child, child_path, values);
+            }
+        }
+        _ => values.push((path, value.clone())),
+    }
+}
+
 pub fn get_default_experimental_features(prefs_config: Option<&PreferencesInputConfig>) -> Vec<String> {
+    let Some(prefs_config) = prefs_config else {
+        return vec!["brave-adblock-experimental-list-default@1".to_string()];
+    };
+
+    let mut entries: Vec<String> = Vec::new();
+    for feature in &prefs_config.experimental_features {
+        let (entry, warning) = compile_experimental_feature(feature);
+        if let Some(warning) = warning {
+            eprintln!("Warning: {}", warning);
+        }
+        // A raw entry and a catalog flag can name the same entry
+        if let Some(entry) = entry.filter(|entry| !entries.contains(entry)) {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+pub const DEFAULT_BACKUP_RETENTION: usize = 5;
+
+pub fn get_backup_retention(prefs_config: Option<&PreferencesInputConfig>) -> usize {
     prefs_config
-        .map(|p| p.experimental_features.clone())
-        .unwrap_or_else(|| vec!["brave-adblock-experimental-list-default@1".to_string()])
+        .and_then(|p| p.backup_retention)
+        .unwrap_or(DEFAULT_BACKUP_RETENTION)
+        .max(1)
+}
+
+/// Switches whose value is a comma-separated list. Chromium only reads the last occurrence of a switch,
+/// so the values of a repeated list switch are merged into one.
+pub const LIST_SWITCHES: &[&str] = &["--enable-features", "--disable-features", "--enable-blink-features", "--disable-blink-features"];
+
+/// Returns the name of the list switch set by `flag`, e.g. `--disable-features` for `--disable-features=A,B`.
+pub fn get_list_switch(flag: &str) -> Option<&str> {
+    let (name, _) = flag.trim().split_once('=')?;
+    LIST_SWITCHES.contains(&name).then_some(name)
+}
+
+/// Adds `flag` to `flags` unless it is already there. The values of a list switch are merged into its first
+/// occurrence instead, and later occurrences are folded into it.
+pub fn add_launch_flag(flags: &mut Vec<String>, flag: &str) {
+    let flag = flag.trim();
+    if flag.is_empty() {
+        return;
+    }
+    let Some(name) = get_list_switch(flag) else {
+        if !flags.iter().any(|f| f.trim() == flag) {
+            flags.push(flag.to_string());
+        }
+        return;
+    };
+
+    let occurrences: Vec<usize> = (0..flags.len()).filter(|&i| get_list_switch(&flags[i]) == Some(name)).collect();
+    let mut values: Vec<&str> = Vec::new();
+    for value in occurrences.iter().map(|&i| flags[i].trim()).chain([flag]).flat_map(|f| f[name.len() + 1..].split(',')) {
+        if !value.is_empty() && !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    let merged = format!("{}={}", name, values.join(","));
+
+    match occurrences.split_first() {
+        Some((&first, rest)) => {
+            flags[first] = merged;
+            for &i in rest.iter().rev() {
+                flags.remove(i);
+            }
+        }
+        None => flags.push(merged),
+    }
+}
+
+pub fn get_default_launch_flags(prefs_config: Option<&PreferencesInputConfig>) -> Vec<String> {
+    let mut flags: Vec<String> = Vec::new();
+    for flag in prefs_config.map(|p| p.launch_flags.as_slice()).unwrap_or_default() {
+        add_launch_flag(&mut flags, flag);
+    }
+    flags
 }
\ No newline at end of file
@@ -1,5 +1,9 @@
 pub mod types;
 pub mod handlers;
+pub mod flags;
+pub mod protection;
 
 pub use types::*;
-pub use handlers::*;
\ No newline at end of file
+pub use handlers::*;
+pub use flags::*;
+pub use protection::*;
\ No newline at end of file
@@ -0,0 +1,76 @@
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+/// Preferences written by the generated scripts, as dotted paths.
+pub const MODIFIED_PREFERENCES: &[&str] = &[
+    "default_search_provider_data.template_url_data",
+    "brave.new_tab_page",
+    "brave.stats.enabled",
+    "brave.today.should_show_brave_today_widget",
+];
+
+/// Chromium tracked preferences whose values are protected by an HMAC in `protection.macs`.
+pub const PROTECTED_PREFERENCES: &[&str] = &[
+    "browser.show_home_button",
+    "default_search_provider_data.template_url_data",
+    "extensions.settings",
+    "google.services.account_id",
+    "homepage",
+    "homepage_is_newtabpage",
+    "pinned_tabs",
+    "search_provider_overrides",
+    "session.restore_on_startup",
+    "session.startup_urls",
+];
+
+/// Hex-encoded HMAC key, empty for Chromium builds that are not Google Chrome branded.
+pub const PREF_HASH_SEED: &str = "";
+
+/// Returns the protected preferences overlapping any of the given paths.
+pub fn get_protected_preferences(paths: &[&str]) -> Vec<&'static str> {
+    PROTECTED_PREFERENCES
+        .iter()
+        .copied()
+        .filter(|protected| paths.iter().any(|path| paths_overlap(path, protected)))
+        .collect()
+}
+
+fn paths_overlap(a: &str, b: &str) -> bool {
+    let is_prefix = |prefix: &str, path: &str| path == prefix || path.starts_with(&format!("{}.", prefix));
+    is_prefix(a, b) || is_prefix(b, a)
+}
+
+/// Serializes a value the way Chromium's pref hash calculator does: sorted keys,
+/// no empty dictionaries or lists, and `<` escaped.
+pub fn pref_hash_json(value: &Value) -> String {
+    let mut value = value.clone();
+    remove_empty_containers(&mut value);
+    value.to_string().replace('<', "\\u003C")
+}
+
+fn remove_empty_containers(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for child in map.values_mut() {
+                remove_empty_containers(child);
+            }
+            map.retain(|_, child| !matches!(child, Value::Object(m) if m.is_empty()) && !matches!(child, Value::Array(a) if a.is_empty()));
+        }
+        Value::Array(items) => items.iter_mut().for_each(remove_empty_containers),
+        _ => {}
+    }
+}
+
+/// Computes the `protection.macs` entry of a preference, `value` is `None` when the preference is unset.
+pub fn calculate_mac(device_id: &str, path: &str, value: Option<&Value>) -> String {
+    let seed: Vec<u8> = (0..PREF_HASH_SEED.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&PREF_HASH_SEED[i..i + 2], 16).ok())
+        .collect();
+    let mut mac = Hmac::<Sha256>::new_from_slice(&seed).expect("HMAC accepts keys of any length");
+    mac.update(device_id.as_bytes());
+    mac.update(path.as_bytes());
+    mac.update(value.map(pref_hash_json).unwrap_or_default().as_bytes());
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02X}", b)).collect()
+}
@@ -7,62 +7,134 @@ pub struct SearchProvider {
     pub search_url: String,
 }
 
+/// A search engine as Chromium stores it in `default_search_provider_data.template_url_data`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateUrlData {
+    pub keyword: String,
+    pub short_name: String,
+    pub url: String,
+}
+
+impl From<&SearchProvider> for TemplateUrlData {
+    fn from(provider: &SearchProvider) -> Self {
+        TemplateUrlData {
+            keyword: provider.keyword.clone(),
+            short_name: provider.name.clone(),
+            url: provider.search_url.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DefaultSearchProviderData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_url_data: Option<TemplateUrlData>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NewTabPage {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_clock: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_background_image: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_stats: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_shortcuts: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_branded_background_image: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_cards: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_search_widget: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_brave_news: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub show_together: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BraveStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub enabled: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BraveToday {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub should_show_brave_today_widget: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BravePreferences {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub new_tab_page: Option<NewTabPage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stats: Option<BraveStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub today: Option<BraveToday>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BrowserPreferences {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub enabled_labs_experiments: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserPreferences {
-    pub default_search_provider_data: Option<SearchProvider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_search_provider_data: Option<DefaultSearchProviderData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub brave: Option<BravePreferences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub browser: Option<BrowserPreferences>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LocalState {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub browser: Option<BrowserPreferences>,
 }
 
+/// An entry of `experimental_features`, either a raw `name@index` string or a
+/// catalog flag with a named option.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ExperimentalFeature {
+    Raw(String),
+    Flag { flag: String, option: String },
+}
+
+/// Preferences for one browser profile, matched by directory ("Profile 1") or display name ("Work").
+/// Settings that are left out fall back to the top-level ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfilePreferences {
+    pub name: String,
+    pub search_engine: Option<SearchProvider>,
+    pub dashboard: Option<NewTabPage>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PreferencesInputConfig {
     pub search_engines: Vec<SearchProvider>,
     pub dashboard: NewTabPage,
-    pub experimental_features: Vec<String>,
+    pub experimental_features: Vec<ExperimentalFeature>,
+    /// Command-line switches for features that have neither a policy nor a pref.
+    #[serde(default)]
+    pub launch_flags: Vec<String>,
+    /// Profiles to configure. When empty, every profile gets the top-level preferences.
+    #[serde(default)]
+    pub profiles: Vec<ProfilePreferences>,
+    /// Number of timestamped backup snapshots to keep.
+    #[serde(default)]
+    pub backup_retention: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PreferencesConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub preferences: Option<UserPreferences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub local_state: Option<LocalState>,
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use brave_debloater::platforms::ansible::AnsibleGenerator;
+use brave_debloater::preferences::calculate_mac;
+use brave_debloater::{build_plan, BraveVersion, Config, ConfigValue};
+use common::{require_python_module, temp_dir};
+use serde_json::{json, Value};
+
+#[test]
+fn ansible_role_keeps_policy_values_literal() {
+    let dir = temp_dir("ansible-role");
+    let mut config = Config::new();
+    config.insert("HomepageLocation".to_string(), ConfigValue::String("https://start.test/{{ lookup('pipe', 'id') }}".to_string()));
+    config.insert("DefaultPopupsSetting".to_string(), ConfigValue::Number(-1));
+    config.insert("URLBlocklist".to_string(), ConfigValue::StringArray(vec!["yes".to_string(), "a\"b".to_string()]));
+    let plan = build_plan(&config, &[], None).unwrap();
+    AnsibleGenerator.generate_role(&plan, &BraveVersion::Nightly, dir.to_str().unwrap()).unwrap();
+    let role = dir.join("brave_nightly_debloat");
+
+    for file in [
+        "defaults/main.yml",
+        "vars/main.yml",
+        "tasks/main.yml",
+        "tasks/user.yml",
+        "tasks/profile.yml",
+        "handlers/main.yml",
+        "templates/brave_policies.plist.j2",
+        "files/update_preference_macs.py",
+        "meta/main.yml",
+    ] {
+        assert!(role.join(file).is_file(), "{}", file);
+    }
+    let defaults = fs::read_to_string(role.join("defaults/main.yml")).unwrap();
+    assert!(defaults.contains("brave_debloat_channel: nightly\n"));
+    assert!(defaults.contains("  HomepageLocation: !unsafe \"https://start.test/{{ lookup('pipe', 'id') }}\"\n"));
+    assert!(defaults.contains("  URLBlocklist:\n    - \"yes\"\n    - \"a\\\"b\"\n"));
+
+    require_python_module("yaml");
+    // Load the defaults the way Ansible does, keeping !unsafe strings as plain strings
+    let script = "import json, sys, yaml\n\
+        yaml.SafeLoader.add_constructor('!unsafe', lambda loader, node: loader.construct_scalar(node))\n\
+        print(json.dumps(yaml.safe_load(open(sys.argv[1]))['brave_debloat_policies']))\n";
+    let output = Command::new("python3").arg("-c").arg(script).arg(role.join("defaults/main.yml")).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(serde_json::from_slice::<Value>(&output.stdout).unwrap(), plan.policies_json().unwrap());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn ansible_mac_script_matches_calculate_mac() {
+    require_python_module("hmac");
+    let dir = temp_dir("ansible-macs");
+    let plan = build_plan(&Config::new(), &[], None).unwrap();
+    AnsibleGenerator.generate_role(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+
+    let template_url_data = json!({ "keyword": "<brave>", "short_name": "Brave", "empty": {}, "urls": [] });
+    let prefs_file = dir.join("Preferences");
+    let prefs = json!({
+        "default_search_provider_data": { "template_url_data": template_url_data },
+        "protection": { "macs": { "homepage": "KEEP" }, "super_mac": "" }
+    });
+    fs::write(&prefs_file, prefs.to_string()).unwrap();
+
+    let output = Command::new("python3")
+        .arg(dir.join("brave_debloat/files/update_preference_macs.py"))
+        .arg(&prefs_file)
+        .arg("default_search_provider_data.template_url_data")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "changed");
+
+    let updated: Value = serde_json::from_str(&fs::read_to_string(&prefs_file).unwrap()).unwrap();
+    let macs = &updated["protection"]["macs"];
+    let path = "default_search_provider_data.template_url_data";
+    assert_eq!(macs["default_search_provider_data"]["template_url_data"], calculate_mac("", path, Some(&template_url_data)));
+    assert_eq!(macs["homepage"], "KEEP");
+    assert_eq!(updated["protection"]["super_mac"], calculate_mac("", "", Some(macs)));
+    let _ = fs::remove_dir_all(dir);
+}
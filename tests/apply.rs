@@ -0,0 +1,240 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use brave_debloater::platforms::{BACKUP_DIR_NAME, get_linux_flags_file};
+use brave_debloater::preferences::{PreferencesInputConfig, calculate_mac, get_profile_preference_sets};
+use brave_debloater::{
+    BackupSnapshot, BraveVersion, LinuxInstallation, LinuxPackaging, apply_user_data, get_profiles, merge_value, select_preference_set,
+    write_atomic,
+};
+use common::temp_dir;
+use serde_json::{Value, json};
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+fn write_json(path: &Path, value: &Value) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, value.to_string()).unwrap();
+}
+
+#[test]
+fn apply_configures_the_selected_profiles() {
+    let dir = temp_dir("apply-profiles");
+    let installation = LinuxInstallation {
+        packaging: LinuxPackaging::Native,
+        policy_path: dir.join("policies/brave.json"),
+        data_dir: dir.join("data"),
+        config_dir: dir.join("config"),
+    };
+    let local_state = json!({ "profile": { "info_cache": { "Default": { "name": "Personal" }, "Profile 1": { "name": "Work" } } } });
+    write_json(&installation.data_dir.join("Local State"), &local_state);
+    let personal = json!({ "homepage": "https://personal.test/" });
+    write_json(&installation.data_dir.join("Default/Preferences"), &personal);
+    let old_engine = json!({ "keyword": "google", "short_name": "Google", "url": "https://google.test/?q={searchTerms}", "prepopulate_id": 1 });
+    write_json(
+        &installation.data_dir.join("Profile 1/Preferences"),
+        &json!({
+            "default_search_provider_data": { "template_url_data": old_engine },
+            "brave": { "stats": { "enabled": true } },
+            "protection": { "macs": {} },
+        }),
+    );
+    fs::create_dir_all(&installation.config_dir).unwrap();
+    let flags_path = installation.config_dir.join(get_linux_flags_file(&BraveVersion::Normal));
+    fs::write(&flags_path, "--disable-features=Old\n").unwrap();
+
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "search_engines": [{ "keyword": "ddg", "name": "DuckDuckGo", "search_url": "https://duckduckgo.com/?q={searchTerms}" }],
+        "dashboard": { "show_clock": false },
+        "experimental_features": [],
+        "launch_flags": ["--disable-features=A"],
+        "profiles": [{ "name": "Work" }],
+    }))
+    .unwrap();
+    let changes = apply_user_data(&installation, &BraveVersion::Normal, Some(&preferences)).unwrap();
+
+    let mut summary: Vec<(String, String, Option<Value>)> = changes
+        .iter()
+        .map(|change| {
+            let file = change.file.strip_prefix(&dir).unwrap().to_string_lossy().to_string();
+            (file, change.key.clone(), change.old_value.clone())
+        })
+        .collect();
+    summary.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    assert_eq!(
+        summary,
+        vec![
+            ("config/brave-flags.conf".to_string(), "--disable-features=Old,A".to_string(), Some(json!("--disable-features=Old"))),
+            ("data/Local State".to_string(), "browser.enabled_labs_experiments".to_string(), None),
+            ("data/Profile 1/Preferences".to_string(), "brave.new_tab_page.show_clock".to_string(), None),
+            ("data/Profile 1/Preferences".to_string(), "brave.stats.enabled".to_string(), Some(json!(true))),
+            ("data/Profile 1/Preferences".to_string(), "brave.today.should_show_brave_today_widget".to_string(), None),
+            ("data/Profile 1/Preferences".to_string(), "default_search_provider_data.template_url_data".to_string(), Some(old_engine)),
+        ]
+    );
+
+    // The Personal profile has no preference set and is left alone
+    assert_eq!(read_json(&installation.data_dir.join("Default/Preferences")), personal);
+    let work = read_json(&installation.data_dir.join("Profile 1/Preferences"));
+    let template_url_data = json!({ "keyword": "ddg", "short_name": "DuckDuckGo", "url": "https://duckduckgo.com/?q={searchTerms}" });
+    assert_eq!(work["default_search_provider_data"]["template_url_data"], template_url_data);
+    assert_eq!(
+        work["protection"]["macs"]["default_search_provider_data"]["template_url_data"],
+        json!(calculate_mac("", "default_search_provider_data.template_url_data", Some(&template_url_data)))
+    );
+    assert_eq!(fs::read_to_string(&flags_path).unwrap(), "--disable-features=Old,A\n");
+
+    let snapshots: Vec<_> = fs::read_dir(installation.data_dir.join(BACKUP_DIR_NAME)).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(fs::read_to_string(snapshots[0].join("manifest.txt")).unwrap(), "Profile 1/Preferences\nLocal State\n");
+    assert_eq!(read_json(&snapshots[0].join("Profile 1/Preferences"))["brave"]["stats"]["enabled"], json!(true));
+
+    // A second run has nothing left to change
+    assert!(apply_user_data(&installation, &BraveVersion::Normal, Some(&preferences)).unwrap().is_empty());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn merge_value_reports_changed_leaves_and_replaces_the_search_engine() {
+    let mut target = json!({
+        "brave": { "stats": { "enabled": true }, "other": 1 },
+        "default_search_provider_data": { "template_url_data": { "keyword": "google", "prepopulate_id": 1 } },
+        "homepage": "https://home.test/",
+    });
+    let changes = json!({
+        "brave": { "stats": { "enabled": false }, "other": 1 },
+        "default_search_provider_data": { "template_url_data": { "keyword": "ddg" } },
+        "homepage": { "nested": true },
+    });
+    let mut changed = Vec::new();
+    merge_value(&mut target, changes, "", &mut |key, old_value, new_value| changed.push((key, old_value, new_value)));
+
+    assert_eq!(
+        changed,
+        vec![
+            ("brave.stats.enabled".to_string(), Some(json!(true)), json!(false)),
+            (
+                "default_search_provider_data.template_url_data".to_string(),
+                Some(json!({ "keyword": "google", "prepopulate_id": 1 })),
+                json!({ "keyword": "ddg" })
+            ),
+            ("homepage.nested".to_string(), None, json!(true)),
+        ]
+    );
+    assert_eq!(
+        target,
+        json!({
+            "brave": { "stats": { "enabled": false }, "other": 1 },
+            "default_search_provider_data": { "template_url_data": { "keyword": "ddg" } },
+            "homepage": { "nested": true },
+        })
+    );
+}
+
+#[test]
+fn profiles_are_listed_from_the_info_cache_or_the_data_dir() {
+    let dir = temp_dir("apply-list-profiles");
+    let local_state = json!({ "profile": { "info_cache": { "Profile 3": { "name": "Work" }, "Default": { "name": "Personal" } } } });
+    assert_eq!(get_profiles(&dir, &local_state), vec!["Default", "Profile 3"]);
+
+    // No profile yet, Brave creates Default on first start
+    assert_eq!(get_profiles(&dir, &json!({})), vec!["Default"]);
+
+    for name in ["Profile 2", "Default", "System Profile", "Guest Profile"] {
+        fs::create_dir_all(dir.join(name)).unwrap();
+    }
+    fs::write(dir.join("Profile 9"), "").unwrap();
+    assert_eq!(get_profiles(&dir, &json!({})), vec!["Default", "Profile 2"]);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn preference_sets_match_the_directory_or_display_name() {
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "search_engines": [{ "keyword": "ddg", "name": "DuckDuckGo", "search_url": "https://duckduckgo.com/?q={searchTerms}" }],
+        "dashboard": {},
+        "experimental_features": [],
+        "profiles": [
+            { "name": "Work", "search_engine": { "keyword": "kagi", "name": "Kagi", "search_url": "https://kagi.test/?q={searchTerms}" } },
+            { "name": "Profile 2" },
+        ],
+    }))
+    .unwrap();
+    let sets = get_profile_preference_sets(Some(&preferences));
+    let local_state = json!({ "profile": { "info_cache": { "Profile 1": { "name": "Work" }, "Profile 2": { "name": "Kids" } } } });
+
+    let keyword = |profile: &str| select_preference_set(&sets, profile, &local_state).map(|set| set.search_provider.keyword.clone());
+    assert_eq!(keyword("Profile 1"), Some("kagi".to_string()));
+    assert_eq!(keyword("Profile 2"), Some("ddg".to_string()));
+    assert_eq!(keyword("Default"), None);
+
+    // Without profiles every profile gets the top-level preferences
+    let sets = get_profile_preference_sets(None);
+    assert!(select_preference_set(&sets, "Profile 7", &json!({})).is_some_and(|set| set.profile.is_none()));
+}
+
+#[cfg(unix)]
+#[test]
+fn write_atomic_keeps_the_mode_and_owner() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = temp_dir("apply-write-atomic");
+    let path = dir.join("Preferences");
+    fs::write(&path, "{}").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    // Only root can hand the file to another user, the owner is then checked as well
+    let owner = std::os::unix::fs::chown(&path, Some(65534), Some(65534)).ok().map(|_| 65534);
+
+    write_atomic(&path, "{\"a\":1}").unwrap();
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{\"a\":1}");
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    if let Some(owner) = owner {
+        assert_eq!((metadata.uid(), metadata.gid()), (owner, owner));
+    }
+    assert!(!dir.join("Preferences.tmp").exists());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn write_atomic_removes_the_temporary_file_on_failure() {
+    let dir = temp_dir("apply-write-failure");
+    // A file cannot be renamed over a non-empty directory
+    let path = dir.join("Preferences");
+    fs::create_dir_all(path.join("child")).unwrap();
+
+    assert!(write_atomic(&path, "{}").is_err());
+    assert!(path.join("child").is_dir());
+    assert!(!dir.join("Preferences.tmp").exists());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn backup_snapshots_are_rotated_oldest_first() {
+    let dir = temp_dir("apply-rotate");
+    let backup_root = dir.join(BACKUP_DIR_NAME);
+    for timestamp in ["20240101-000000", "20240301-000000", "20240201-000000"] {
+        fs::create_dir_all(backup_root.join(timestamp)).unwrap();
+    }
+    let prefs_path = dir.join("Default/Preferences");
+    write_json(&prefs_path, &json!({ "homepage": "https://home.test/" }));
+
+    let backup = BackupSnapshot { data_dir: dir.clone(), dir: backup_root.join("20240401-000000") };
+    backup.add(&prefs_path).unwrap();
+    backup.add(&prefs_path).unwrap();
+    backup.add(&dir.join("Local State")).unwrap();
+    assert_eq!(fs::read_to_string(backup.dir.join("manifest.txt")).unwrap(), "Default/Preferences\n");
+
+    backup.rotate(2).unwrap();
+    let mut snapshots: Vec<String> =
+        fs::read_dir(&backup_root).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+    snapshots.sort();
+    // The oldest snapshot holds the original files and is kept besides the two newest
+    assert_eq!(snapshots, vec!["20240101-000000", "20240301-000000", "20240401-000000"]);
+    assert_eq!(read_json(&backup.dir.join("Default/Preferences")), json!({ "homepage": "https://home.test/" }));
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::fs;
+
+use brave_debloater::platforms::get_linux_flags_file;
+use brave_debloater::preferences::PreferencesInputConfig;
+use brave_debloater::{
+    BraveVersion, Config, ConfigValue, LinuxInstallation, LinuxPackaging, apply_user_data, audit_installations, get_policy_config,
+};
+use common::temp_dir;
+use serde_json::{Value, json};
+
+fn preferences(launch_flags: &[&str]) -> PreferencesInputConfig {
+    serde_json::from_value(json!({
+        "search_engines": [{ "keyword": "ddg", "name": "DuckDuckGo", "search_url": "https://duckduckgo.com/?q={searchTerms}" }],
+        "dashboard": { "show_clock": false },
+        "experimental_features": [],
+        "launch_flags": launch_flags,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn audit_reports_the_keys_that_differ() {
+    let dir = temp_dir("audit-<a&b>");
+    let installation = LinuxInstallation {
+        packaging: LinuxPackaging::Native,
+        policy_path: dir.join("policies/brave.json"),
+        data_dir: dir.join("data"),
+        config_dir: dir.join("config"),
+    };
+    let mut config = Config::new();
+    config.insert("HomepageLocation".to_string(), ConfigValue::String("https://home.test/?a=1&b=<2>".to_string()));
+    fs::create_dir_all(installation.policy_path.parent().unwrap()).unwrap();
+    fs::write(&installation.policy_path, serde_json::to_string_pretty(&get_policy_config(&config, &[])).unwrap()).unwrap();
+    apply_user_data(&installation, &BraveVersion::Normal, Some(&preferences(&["--incognito"]))).unwrap();
+
+    // Applied without the second flag, which is the only failing check
+    let hostile_flag = "--lang=\"<en>&'\"";
+    let report =
+        audit_installations(&[installation], &config, &[], &BraveVersion::Normal, Some(&preferences(&["--incognito", hostile_flag])))
+            .unwrap();
+    let failed: Vec<&str> = report.checks.iter().filter(|check| !check.passed).map(|check| check.key.as_str()).collect();
+    assert_eq!(failed, vec![hostile_flag]);
+    assert_eq!((report.passed, report.failed), (report.checks.len() - 1, 1));
+    assert!(report.checks.iter().any(|check| check.key == "HomepageLocation" && check.passed));
+    assert!(report.checks.iter().any(|check| check.key == "default_search_provider_data.template_url_data" && check.passed));
+
+    let flags_path = dir.join("config").join(get_linux_flags_file(&BraveVersion::Normal));
+    let text = report.to_text();
+    assert!(text.contains(&format!("PASS  {}: HomepageLocation\n", dir.join("policies/brave.json").display())));
+    assert!(text.contains(&format!("FAIL  {}: {} = false (expected true)\n", flags_path.display(), hostile_flag)));
+    assert!(text.ends_with(&format!("\n{} checks, {} passed, 1 failed\n", report.checks.len(), report.passed)));
+
+    let json: Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["failed"], json!(1));
+    assert_eq!(json["passed"], json!(report.passed));
+    let failing = json["checks"].as_array().unwrap().iter().find(|check| check["passed"] == json!(false)).unwrap();
+    assert_eq!(failing["key"], json!(hostile_flag));
+    assert_eq!(failing["file"], json!(flags_path.to_str().unwrap()));
+    assert_eq!(failing["actual"], json!(false));
+
+    let junit = report.to_junit();
+    let escaped_dir = dir.display().to_string().replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    assert!(junit.contains(&format!("<testsuites name=\"brave-debloater audit\" tests=\"{}\" failures=\"1\">\n", report.checks.len())));
+    assert!(junit.contains(&format!(
+        "  <testsuite name=\"{}/config/brave-flags.conf\" tests=\"2\" failures=\"1\">\n",
+        escaped_dir
+    )));
+    assert!(junit.contains(&format!(
+        "    <testcase classname=\"{}/config/brave-flags.conf\" name=\"--lang=&quot;&lt;en&gt;&amp;&apos;&quot;\">\n      \
+         <failure message=\"expected true, found false\"/>\n    </testcase>\n",
+        escaped_dir
+    )));
+    assert!(!junit.contains("<a&b>"));
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,26 @@
+// Each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Creates an empty directory for one test, unique to this test process.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("brave-debloater-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Fails the test when a tool it drives cannot be run, so a missing tool is never reported as a pass.
+pub fn require_tools(tools: &[&str]) {
+    for tool in tools {
+        assert!(Command::new(tool).arg("--version").output().is_ok(), "this test requires {} on the PATH", tool);
+    }
+}
+
+pub fn require_python_module(module: &str) {
+    let found = Command::new("python3").arg("-c").arg(format!("import {}", module)).output().is_ok_and(|output| output.status.success());
+    assert!(found, "this test requires python3 with the {} module", module);
+}
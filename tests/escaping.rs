@@ -0,0 +1,316 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use brave_debloater::platforms::escape::{batch_quote, batch_set, escape_argument, nix_quote, powershell_quote, shell_quote, xml_escape};
+use brave_debloater::platforms::linux::LinuxGenerator;
+use brave_debloater::platforms::macos::MacOSGenerator;
+use brave_debloater::platforms::windows::WindowsGenerator;
+use brave_debloater::platforms::PlatformGenerator;
+use brave_debloater::preferences::PreferencesInputConfig;
+use brave_debloater::{BraveVersion, Config, ConfigValue, Extension, MacOSPolicyMode, build_plan};
+use common::{require_tools, temp_dir};
+
+const HOSTILE_VALUES: &[&str] = &[
+    "O'Reilly",
+    "say \"hi\" & exit",
+    "$(touch PWNED) `touch PWNED` $HOME",
+    "50% off %PATH% !CD!",
+    "<string>&amp;</string>",
+    "a\\\"b\\",
+    "caret ^ pipe | redirect > (group)",
+    "line\nbreak",
+    "\u{2019}typographic\u{2018}",
+];
+
+const HOSTILE_NAME: &str = "O'Reilly \"Search\" $(touch PWNED) & <b>100%</b>!";
+const HOSTILE_PROFILE: &str = "Bob's \"Work\" $(touch PWNED)";
+const HOSTILE_URL: &str = "https://search.test/?q={searchTerms}&lang='en'&x=%25";
+
+fn hostile_config() -> Config {
+    let mut config = Config::new();
+    config.insert("HomepageLocation".to_string(), ConfigValue::String(HOSTILE_URL.to_string()));
+    config.insert("BraveRewardsDisabled".to_string(), ConfigValue::Bool(true));
+    config
+}
+
+fn hostile_extensions() -> Vec<Extension> {
+    vec![Extension {
+        id: "abc&def;https://x.test/<crx>".to_string(),
+        name: "Hostile".to_string(),
+        description: String::new(),
+    }]
+}
+
+fn hostile_preferences() -> PreferencesInputConfig {
+    serde_json::from_value(serde_json::json!({
+        "search_engines": [{ "keyword": "o'r", "name": HOSTILE_NAME, "search_url": HOSTILE_URL }],
+        "dashboard": { "show_clock": true },
+        "experimental_features": ["it's@1"],
+        "launch_flags": ["--user-agent=it's \"quoted\" & $(touch PWNED)"],
+        "profiles": [{ "name": HOSTILE_PROFILE }]
+    }))
+    .unwrap()
+}
+
+fn generate(generator: &dyn PlatformGenerator, name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    let preferences = hostile_preferences();
+    let plan = build_plan(&hostile_config(), &hostile_extensions(), Some(&preferences)).unwrap();
+    generator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    dir
+}
+
+/// The hostile search engine name as it appears in the shell-quoted `--argjson` changes.
+fn quoted_json_name() -> String {
+    serde_json::to_string(HOSTILE_NAME).unwrap().replace('\'', "'\\''")
+}
+
+/// The Set-JsonValue line that writes the hostile search engine in the PowerShell scripts.
+fn search_engine_powershell_line() -> String {
+    let template_url_data = serde_json::json!({ "keyword": "o'r", "short_name": HOSTILE_NAME, "url": HOSTILE_URL });
+    format!(
+        "$prefsJson = Set-JsonValue $prefsJson 'default_search_provider_data.template_url_data' {}\n",
+        powershell_quote(&template_url_data.to_string())
+    )
+}
+
+#[test]
+fn shell_quote_round_trips_through_bash() {
+    require_tools(&["bash"]);
+    for value in HOSTILE_VALUES {
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(format!("printf '%s' {}", shell_quote(value)))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), *value);
+    }
+}
+
+#[test]
+fn xml_escape_leaves_no_markup() {
+    assert_eq!(xml_escape("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;");
+    for value in HOSTILE_VALUES {
+        let escaped = xml_escape(value);
+        assert!(!escaped.contains('<') && !escaped.contains('>') && !escaped.contains('"'), "{}", escaped);
+    }
+}
+
+#[test]
+fn powershell_quote_doubles_quotes() {
+    assert_eq!(powershell_quote("O'Reilly"), "'O''Reilly'");
+    assert_eq!(powershell_quote("$env:PATH `n"), "'$env:PATH `n'");
+    assert_eq!(powershell_quote("\u{2019}x"), "'\u{2019}\u{2019}x'");
+}
+
+#[test]
+fn nix_quote_blocks_interpolation() {
+    assert_eq!(nix_quote("${builtins.readFile /etc/shadow}"), "\"\\${builtins.readFile /etc/shadow}\"");
+    assert_eq!(nix_quote("say \"hi\" \\ $HOME"), "\"say \\\"hi\\\" \\\\ $HOME\"");
+    assert_eq!(nix_quote("\\${x}"), "\"\\\\\\${x}\"");
+    assert_eq!(nix_quote("line\nbreak"), "\"line\\nbreak\"");
+}
+
+#[test]
+fn batch_quote_escapes_for_cmd_and_arguments() {
+    assert_eq!(batch_quote("a & b"), "\"a & b\"");
+    assert_eq!(batch_quote("100%"), "\"100%%\"");
+    assert_eq!(batch_quote("C:\\dir\\"), "\"C:\\dir\\\\\"");
+    assert_eq!(batch_quote("x\"&calc"), "\"x\\\"^&calc\"");
+    assert_eq!(batch_quote("wow!"), "\"wow^!\"");
+    assert_eq!(batch_quote("a^b"), "\"a^b\"");
+    assert_eq!(batch_quote("a^b!"), "\"a^^b^!\"");
+    assert_eq!(batch_quote("line\nbreak"), "\"line break\"");
+}
+
+#[test]
+fn escape_argument_escapes_quotes_and_their_backslashes() {
+    assert_eq!(escape_argument("x\"&calc"), "x\\\"&calc");
+    assert_eq!(escape_argument("C:\\dir\\"), "C:\\dir\\\\");
+    assert_eq!(escape_argument("a\\\"b"), "a\\\\\\\"b");
+    assert_eq!(escape_argument("line\nbreak"), "line break");
+}
+
+/// Whether cmd sees the redirection of every `reg` line outside quotes, so that it is not passed to reg.exe.
+fn redirections_outside_quotes(content: &str) -> bool {
+    content.lines().filter(|line| line.starts_with("reg ")).all(|line| {
+        let redirection = line.rfind(" >nul 2>&1").unwrap();
+        line[..redirection].matches('"').count() % 2 == 0
+    })
+}
+
+#[test]
+fn windows_registry_lines_survive_odd_quotes() {
+    let dir = temp_dir("windows-odd-quotes");
+    let mut config = Config::new();
+    config.insert("HomepageLocation".to_string(), ConfigValue::String("x\"&calc".to_string()));
+    config.insert("URLBlocklist".to_string(), ConfigValue::StringArray(vec!["a\"b".to_string(), "100%!".to_string()]));
+    let plan = build_plan(&config, &[], None).unwrap();
+    WindowsGenerator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    WindowsGenerator.generate_revert_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+
+    let content = fs::read_to_string(dir.join("brave_debloat.bat")).unwrap();
+    assert!(content.contains(
+        "set \"REG_NAME=HomepageLocation\"\n\
+         set \"REG_DATA=x\\\"^&calc\"\n\
+         reg add \"HKEY_LOCAL_MACHINE\\SOFTWARE\\Policies\\BraveSoftware\\Brave\" /v \"!REG_NAME!\" /t REG_SZ /d \"!REG_DATA!\" /f >nul 2>&1\n"
+    ));
+    assert!(content.contains(
+        "set \"REG_KEY=HKEY_LOCAL_MACHINE\\SOFTWARE\\Policies\\BraveSoftware\\Brave\\URLBlocklist\"\n\
+         reg delete \"!REG_KEY!\" /f >nul 2>&1\n\
+         set \"REG_DATA=a\\\"b\"\n\
+         reg add \"!REG_KEY!\" /v \"1\" /t REG_SZ /d \"!REG_DATA!\" /f >nul 2>&1\n\
+         set \"REG_DATA=100%%^!\"\n\
+         reg add \"!REG_KEY!\" /v \"2\" /t REG_SZ /d \"!REG_DATA!\" /f >nul 2>&1\n"
+    ));
+    assert!(redirections_outside_quotes(&content));
+
+    let revert = fs::read_to_string(dir.join("brave_revert.bat")).unwrap();
+    assert!(revert.contains(
+        "set \"REG_KEY=HKEY_LOCAL_MACHINE\\SOFTWARE\\Policies\\BraveSoftware\\Brave\"\n\
+         set \"REG_NAME=HomepageLocation\"\n\
+         reg delete \"!REG_KEY!\" /v \"!REG_NAME!\" /f >nul 2>&1\n"
+    ));
+    assert!(redirections_outside_quotes(&revert));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn batch_set_keeps_value_literal() {
+    assert_eq!(batch_set("REG_DATA", "a&b%c"), "set \"REG_DATA=a&b%%c\"");
+    assert_eq!(batch_set("REG_DATA", "hi!"), "set \"REG_DATA=hi^!\"");
+}
+
+#[test]
+fn linux_script_survives_hostile_values() {
+    require_tools(&["bash"]);
+    let dir = generate(&LinuxGenerator, "linux");
+    let script = dir.join("brave_debloat_linux.sh");
+    let content = fs::read_to_string(&script).unwrap();
+    assert!(content.contains(&quoted_json_name()));
+
+    let status = Command::new("bash").arg("-n").arg(&script).status().unwrap();
+    assert!(status.success());
+
+    // A dry run evaluates every quoted value without touching the system
+    let has_jq = Command::new("jq").arg("--version").output().is_ok();
+    if has_jq {
+        let home = dir.join("home");
+        let data_dir = home.join(".config/BraveSoftware/Brave-Browser");
+        fs::create_dir_all(&data_dir).unwrap();
+        let local_state = serde_json::json!({ "profile": { "info_cache": { "Default": { "name": HOSTILE_PROFILE } } } });
+        fs::write(data_dir.join("Local State"), local_state.to_string()).unwrap();
+        let output = Command::new("bash")
+            .arg(&script)
+            .arg("--dry-run")
+            .env("HOME", &home)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("Applying preferences to profile: Default"), "{}", stdout);
+        assert!(stdout.contains(&serde_json::to_string(HOSTILE_NAME).unwrap()), "{}", stdout);
+        assert!(!dir.join("PWNED").exists());
+        assert!(!home.join("PWNED").exists());
+    }
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn macos_script_escapes_plist_and_shell() {
+    let dir = generate(&MacOSGenerator::default(), "macos");
+    let script = dir.join("brave_debloat_macos.sh");
+    let content = fs::read_to_string(&script).unwrap();
+    assert!(content.contains(&format!("<string>{}</string>", xml_escape(HOSTILE_URL))));
+    assert!(content.contains("<string>abc&amp;def;https://x.test/&lt;crx&gt;</string>"));
+    assert!(content.contains(&quoted_json_name()));
+    require_tools(&["bash"]);
+    let status = Command::new("bash").arg("-n").arg(&script).status().unwrap();
+    assert!(status.success());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn macos_defaults_mode_quotes_every_value() {
+    let generator = MacOSGenerator { policy_mode: MacOSPolicyMode::Defaults, binary_plist: true };
+    let dir = generate(&generator, "macos-defaults");
+    let script = dir.join("brave_debloat_macos.sh");
+    let content = fs::read_to_string(&script).unwrap();
+    assert!(content.contains(&format!("defaults write \"$POLICY_DOMAIN\" 'HomepageLocation' -string {}\n", shell_quote(HOSTILE_URL))));
+    assert!(content.contains("defaults write \"$POLICY_DOMAIN\" 'ExtensionInstallForcelist' -array 'abc&def;https://x.test/<crx>'\n"));
+    assert!(content.contains("defaults write \"$POLICY_DOMAIN\" 'BraveRewardsDisabled' -bool true\n"));
+    assert!(content.contains(&format!("verify_policy \"$POLICY_EXPORT\" 'HomepageLocation' {}\n", shell_quote(&serde_json::to_string(HOSTILE_URL).unwrap()))));
+    assert!(content.contains("plutil -convert binary1 \"$POLICY_DOMAIN.plist\""));
+    assert!(!content.contains("> /Library/Managed\\ Preferences/"));
+    require_tools(&["bash"]);
+    let status = Command::new("bash").arg("-n").arg(&script).status().unwrap();
+    assert!(status.success());
+    let _ = fs::remove_dir_all(dir);
+}
+
+/// Decodes the `-EncodedCommand` scripts of a batch file, following the loader of scripts split into `PS_SCRIPT_n` variables.
+fn embedded_powershell(content: &str) -> Vec<String> {
+    let decode = |encoded: &str| {
+        let bytes = BASE64.decode(encoded).unwrap();
+        let units: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16(&units).unwrap()
+    };
+    let mut scripts = Vec::new();
+    let mut chunks = String::new();
+    for line in content.lines() {
+        if let Some(chunk) = line.strip_prefix("set \"PS_SCRIPT_") {
+            chunks.push_str(chunk.split_once('=').unwrap().1.trim_end_matches('"'));
+        } else if let Some(encoded) = line.strip_prefix("powershell -NoProfile -ExecutionPolicy Bypass -EncodedCommand ") {
+            if chunks.is_empty() {
+                scripts.push(decode(encoded));
+            } else {
+                assert!(decode(encoded).contains("PS_SCRIPT_"));
+                scripts.push(decode(&chunks));
+                chunks.clear();
+            }
+        }
+    }
+    scripts
+}
+
+#[test]
+fn windows_script_escapes_batch_and_powershell() {
+    let dir = generate(&WindowsGenerator, "windows");
+    let content = fs::read_to_string(dir.join("brave_debloat.bat")).unwrap();
+    assert!(content.contains(&format!("{}\n", batch_set("REG_DATA", &escape_argument(HOSTILE_URL)))));
+    assert!(content.contains("&x=%%25"));
+    assert!(redirections_outside_quotes(&content));
+    assert!(content.contains(&batch_set("REG_DATA", HOSTILE_URL)));
+    assert!(!content.contains(HOSTILE_NAME));
+    assert!(!content.contains("-Command"));
+
+    // The PowerShell code is passed encoded and reads the batch variables from the environment
+    let scripts = embedded_powershell(&content);
+    assert_eq!(scripts.len(), 2);
+    assert!(scripts[0].contains("$braveData = $env:BRAVE_DATA\n"));
+    assert!(scripts[0].contains(&search_engine_powershell_line()));
+    assert!(scripts[1].contains(&powershell_quote("--user-agent=it's \"quoted\" & $(touch PWNED)")));
+    assert!(scripts.iter().all(|script| !script.contains("%BRAVE_DATA%") && !script.contains("%DRY_RUN%")));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn windows_powershell_script_quotes_natively() {
+    let dir = temp_dir("ps1");
+    let preferences = hostile_preferences();
+    let plan = build_plan(&hostile_config(), &hostile_extensions(), Some(&preferences)).unwrap();
+    WindowsGenerator.generate_powershell_scripts(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    let content = fs::read_to_string(dir.join("brave_debloat.ps1")).unwrap();
+    assert!(content.contains("[CmdletBinding(SupportsShouldProcess = $true)]"));
+    assert!(content.contains(&format!("Set-Policy $policyKey 'HomepageLocation' {} String", powershell_quote(HOSTILE_URL))));
+    assert!(content.contains(&search_engine_powershell_line()));
+    assert!(content.contains(&format!("$profileName -eq {}", powershell_quote(HOSTILE_PROFILE))));
+    assert!(!content.contains("%DRY_RUN%"));
+    let _ = fs::remove_dir_all(dir);
+}
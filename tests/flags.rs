@@ -0,0 +1,72 @@
+use brave_debloater::preferences::{
+    ExperimentalFeature, FLAG_CATALOG, FlagKind, FlagWarning, PreferencesInputConfig, compile_experimental_feature, find_flag,
+    get_default_experimental_features, validate_raw_entry,
+};
+use serde_json::json;
+
+fn flag(flag: &str, option: &str) -> ExperimentalFeature {
+    ExperimentalFeature::Flag { flag: flag.to_string(), option: option.to_string() }
+}
+
+#[test]
+fn flag_catalog_is_consistent() {
+    for (i, definition) in FLAG_CATALOG.iter().enumerate() {
+        assert!(FLAG_CATALOG[..i].iter().all(|other| other.name != definition.name), "{} is listed twice", definition.name);
+        match definition.kind {
+            FlagKind::Switch => assert_eq!(definition.options, ["Enabled"], "{}", definition.name),
+            FlagKind::Options => assert_eq!(definition.options.first(), Some(&"Default"), "{}", definition.name),
+        }
+    }
+    assert!(find_flag("ignore-gpu-blocklist").is_some_and(|definition| definition.is_switch()));
+    assert!(find_flag("brave-vpn").is_some_and(|definition| !definition.is_switch()));
+    assert!(find_flag("brave-unknown").is_none());
+}
+
+#[test]
+fn catalog_flags_compile_to_their_entries() {
+    assert!(matches!(compile_experimental_feature(&flag("brave-vpn", "Disabled")), (Some(entry), None) if entry == "brave-vpn@2"));
+    assert!(matches!(compile_experimental_feature(&flag("ozone-platform-hint", "wayland")), (Some(entry), None) if entry == "ozone-platform-hint@3"));
+    assert!(
+        matches!(compile_experimental_feature(&flag("ignore-gpu-blocklist", "Enabled")), (Some(entry), None) if entry == "ignore-gpu-blocklist")
+    );
+
+    let (entry, warning) = compile_experimental_feature(&flag("brave-vpn", "On"));
+    assert!(entry.is_none());
+    assert_eq!(warning.unwrap().to_string(), "flag 'brave-vpn' has no option 'On' (available: Default, Enabled, Disabled)");
+    let (entry, warning) = compile_experimental_feature(&flag("brave-unknown", "Enabled"));
+    assert!(entry.is_none());
+    assert!(matches!(warning, Some(FlagWarning::UnknownFlag(name)) if name == "brave-unknown"));
+
+    // Raw entries are kept even when the catalog does not know them
+    let (entry, warning) = compile_experimental_feature(&ExperimentalFeature::Raw("brave-unknown@1".to_string()));
+    assert_eq!(entry.as_deref(), Some("brave-unknown@1"));
+    assert!(matches!(warning, Some(FlagWarning::UnknownFlag(_))));
+}
+
+#[test]
+fn raw_entries_are_validated_against_the_catalog() {
+    assert!(validate_raw_entry("brave-vpn@1").is_none());
+    assert!(validate_raw_entry("ignore-gpu-blocklist").is_none());
+    assert!(matches!(validate_raw_entry("brave-vpn"), Some(FlagWarning::MissingIndex(_))));
+    assert!(matches!(validate_raw_entry("ignore-gpu-blocklist@1"), Some(FlagWarning::UnexpectedIndex(_))));
+    assert!(matches!(validate_raw_entry("brave-vpn@3"), Some(FlagWarning::InvalidIndex { max: 2, .. })));
+    assert!(matches!(validate_raw_entry("brave-vpn@x"), Some(FlagWarning::InvalidIndex { .. })));
+    assert!(matches!(validate_raw_entry("brave-unknown@1"), Some(FlagWarning::UnknownFlag(name)) if name == "brave-unknown"));
+}
+
+#[test]
+fn experimental_features_are_collapsed() {
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "search_engines": [],
+        "dashboard": {},
+        "experimental_features": [
+            "ignore-gpu-blocklist",
+            { "flag": "brave-vpn", "option": "Enabled" },
+            { "flag": "ignore-gpu-blocklist", "option": "Enabled" },
+            "brave-vpn@1",
+            { "flag": "brave-vpn", "option": "Sometimes" },
+        ],
+    }))
+    .unwrap();
+    assert_eq!(get_default_experimental_features(Some(&preferences)), vec!["ignore-gpu-blocklist", "brave-vpn@1"]);
+}
@@ -0,0 +1,271 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use brave_debloater::platforms::linux::LinuxGenerator;
+use brave_debloater::platforms::nix::WEB_STORE_UPDATE_URL;
+use brave_debloater::platforms::package::PackageOptions;
+use brave_debloater::platforms::PlatformGenerator;
+use brave_debloater::preferences::PreferencesInputConfig;
+use brave_debloater::{build_plan, load_config, BraveVersion, Config, ConfigValue, Extension};
+use common::{require_tools, temp_dir};
+
+#[test]
+fn linux_script_configures_every_installation() {
+    require_tools(&["bash", "jq"]);
+    let dir = temp_dir("linux-installs");
+    let plan = build_plan(&load_config("configs/minimal.json").unwrap(), &[], None).unwrap();
+    LinuxGenerator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+
+    // A native launcher on the PATH and a user Flatpak install
+    let home = dir.join("home");
+    let bin = dir.join("bin");
+    fs::create_dir_all(home.join(".local/share/flatpak/app/com.brave.Browser")).unwrap();
+    fs::create_dir_all(&bin).unwrap();
+    fs::write(bin.join("brave-browser"), "").unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+
+    let output = Command::new("bash")
+        .arg(dir.join("brave_debloat_linux.sh"))
+        .arg("--dry-run")
+        .env("HOME", &home)
+        .env("PATH", path)
+        .env_remove("XDG_CONFIG_HOME")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Detected native Brave installation"), "{}", stdout);
+    assert!(stdout.contains("Detected user Flatpak Brave installation"), "{}", stdout);
+    assert!(stdout.contains("Policy file: /etc/brave/policies/managed/brave.json"), "{}", stdout);
+    assert!(stdout.contains(&format!(
+        "Policy file: {}/.local/share/flatpak/extension/com.brave.Browser.Policy.system-policies/",
+        home.display()
+    )));
+    for data_dir in [".config/BraveSoftware/Brave-Browser", ".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser"] {
+        let line = format!("Modifying user preferences in {}...", home.join(data_dir).display());
+        assert_eq!(stdout.matches(&line).count(), 1, "{}", stdout);
+    }
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn linux_scripts_merge_list_switches_in_the_flags_file() {
+    require_tools(&["bash", "jq"]);
+    let dir = temp_dir("linux-flags");
+    let preferences: PreferencesInputConfig = serde_json::from_value(serde_json::json!({
+        "search_engines": [],
+        "dashboard": {},
+        "experimental_features": [],
+        "launch_flags": ["--disable-features=A", "--disable-features=B", "--enable-features=X"]
+    }))
+    .unwrap();
+    let plan = build_plan(&Config::new(), &[], Some(&preferences)).unwrap();
+    assert_eq!(plan.launch_flags(), ["--disable-features=A,B", "--enable-features=X"]);
+    LinuxGenerator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    LinuxGenerator.generate_revert_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+
+    // A user Flatpak install, whose policies live in the home directory
+    let home = dir.join("home");
+    fs::create_dir_all(home.join(".local/share/flatpak/app/com.brave.Browser")).unwrap();
+    let flags_file = home.join(".var/app/com.brave.Browser/config/brave-flags.conf");
+    fs::create_dir_all(flags_file.parent().unwrap()).unwrap();
+    fs::write(&flags_file, "--disable-features=Old\n--foo\n--disable-features=B\n").unwrap();
+
+    let run = |script: &str| {
+        let output = Command::new("bash").arg(dir.join(script)).env("HOME", &home).env_remove("XDG_CONFIG_HOME").output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    };
+    for _ in 0..2 {
+        run("brave_debloat_linux.sh");
+        assert_eq!(fs::read_to_string(&flags_file).unwrap(), "--foo\n--disable-features=Old,B,A\n--enable-features=X\n");
+    }
+    run("brave_revert_linux.sh");
+    assert_eq!(fs::read_to_string(&flags_file).unwrap(), "--foo\n--disable-features=Old\n");
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn linux_revert_checks_the_snapshot_before_removing_policies() {
+    require_tools(&["bash", "jq"]);
+    let dir = temp_dir("linux-restore-typo");
+    let plan = build_plan(&load_config("configs/minimal.json").unwrap(), &[], None).unwrap();
+    LinuxGenerator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    LinuxGenerator.generate_revert_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    let home = dir.join("home");
+    fs::create_dir_all(home.join(".local/share/flatpak/app/com.brave.Browser")).unwrap();
+
+    let run = |script: &str, args: &[&str]| {
+        Command::new("bash").arg(dir.join(script)).args(args).env("HOME", &home).env_remove("XDG_CONFIG_HOME").output().unwrap()
+    };
+    assert!(run("brave_debloat_linux.sh", &[]).status.success());
+    let policy_dir = home.join(".local/share/flatpak/extension/com.brave.Browser.Policy.system-policies");
+    let count_policies = || {
+        let output = Command::new("find").arg(&policy_dir).arg("-name").arg("*.json").output().unwrap();
+        String::from_utf8_lossy(&output.stdout).lines().count()
+    };
+    assert_eq!(count_policies(), 1);
+
+    let output = run("brave_revert_linux.sh", &["--restore", "19700101-000000"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("no installation has the backup snapshot '19700101-000000'"), "{}", stdout);
+    assert_eq!(count_policies(), 1, "{}", stdout);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn linux_scripts_rotate_and_restore_backup_snapshots() {
+    require_tools(&["bash", "jq"]);
+    let dir = temp_dir("linux-snapshots");
+    let preferences: PreferencesInputConfig = serde_json::from_value(serde_json::json!({
+        "search_engines": [{ "keyword": "ddg", "name": "DuckDuckGo", "search_url": "https://duckduckgo.com/?q={searchTerms}" }],
+        "dashboard": {},
+        "experimental_features": [],
+        "backup_retention": 2
+    }))
+    .unwrap();
+    let plan = build_plan(&load_config("configs/minimal.json").unwrap(), &[], Some(&preferences)).unwrap();
+    LinuxGenerator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    LinuxGenerator.generate_revert_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    let home = dir.join("home");
+    fs::create_dir_all(home.join(".local/share/flatpak/app/com.brave.Browser")).unwrap();
+    let data_dir = home.join(".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser");
+    let prefs_path = data_dir.join("Default/Preferences");
+    fs::create_dir_all(prefs_path.parent().unwrap()).unwrap();
+    fs::write(data_dir.join("Local State"), "{\"profile\":{\"info_cache\":{\"Default\":{}}}}").unwrap();
+
+    let run = |script: &str, args: &[&str]| {
+        let output =
+            Command::new("bash").arg(dir.join(script)).args(args).env("HOME", &home).env_remove("XDG_CONFIG_HOME").output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let backup_root = data_dir.join("Debloater Backups");
+    let list_snapshots = || {
+        let mut snapshots: Vec<String> =
+            fs::read_dir(&backup_root).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        snapshots.sort();
+        snapshots
+    };
+
+    // The first run starts from the original files, later runs from the files the previous run debloated.
+    // Snapshots are named by the second.
+    let original_prefs = "{\"homepage\":\"https://first.test/\"}";
+    let original_local_state = fs::read_to_string(data_dir.join("Local State")).unwrap();
+    fs::write(&prefs_path, original_prefs).unwrap();
+    let mut created = Vec::new();
+    for _ in 0..4 {
+        if !created.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(1100));
+        }
+        let stdout = run("brave_debloat_linux.sh", &[]);
+        let snapshot = list_snapshots().pop().unwrap();
+        assert!(stdout.contains(&format!("Backup saved to: {}", backup_root.join(&snapshot).display())), "{}", stdout);
+        assert!(!created.contains(&snapshot));
+        created.push(snapshot);
+    }
+
+    // The original snapshot is always kept, along with the two newest others
+    assert_eq!(list_snapshots(), [created[0].clone(), created[2].clone(), created[3].clone()]);
+    let original = backup_root.join(&created[0]);
+    assert_eq!(fs::read_to_string(original.join("manifest.txt")).unwrap(), "Local State\nDefault/Preferences\n");
+    assert_eq!(fs::read_to_string(original.join("Default/Preferences")).unwrap(), original_prefs);
+    let applied = fs::read_to_string(&prefs_path).unwrap();
+    assert!(applied.contains("duckduckgo.com"));
+
+    // latest picks the newest snapshot, which already holds debloated files
+    let stdout = run("brave_revert_linux.sh", &["--restore", "latest"]);
+    assert!(stdout.contains(&format!("Restoring backup snapshot: {}", created[3])), "{}", stdout);
+    assert!(fs::read_to_string(&prefs_path).unwrap().contains("duckduckgo.com"));
+
+    // Without --restore the original files come back, however many times the debloat script ran
+    let stdout = run("brave_revert_linux.sh", &[]);
+    assert!(stdout.contains(&format!("Restoring backup snapshot: {}", created[0])), "{}", stdout);
+    assert_eq!(fs::read_to_string(&prefs_path).unwrap(), original_prefs);
+    assert_eq!(fs::read_to_string(data_dir.join("Local State")).unwrap(), original_local_state);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn nix_module_renders_policies_extensions_and_flags() {
+    let dir = temp_dir("nix");
+    let mut config = Config::new();
+    config.insert("DefaultPopupsSetting".to_string(), ConfigValue::Number(-1));
+    config.insert("HomepageLocation".to_string(), ConfigValue::String("https://start.test/${x}".to_string()));
+    let extensions = [
+        Extension { id: "cjpalhdlnbpafiamejdnhcphjbkeiagm".to_string(), name: String::new(), description: String::new() },
+        Extension { id: "abcdefghijklmnopabcdefghijklmnop;https://updates.test/crx".to_string(), name: String::new(), description: String::new() },
+    ];
+    let preferences: PreferencesInputConfig = serde_json::from_value(serde_json::json!({
+        "search_engines": [],
+        "dashboard": {},
+        "experimental_features": [],
+        "launch_flags": ["--disable-features=Foo"]
+    }))
+    .unwrap();
+    let plan = build_plan(&config, &extensions, Some(&preferences)).unwrap();
+    LinuxGenerator.generate_nix_module(&plan, &BraveVersion::Nightly, dir.to_str().unwrap()).unwrap();
+    let content = fs::read_to_string(dir.join("brave_nightly_debloat.nix")).unwrap();
+
+    assert!(content.contains("    DefaultPopupsSetting = (-1);\n"));
+    assert!(content.contains("    HomepageLocation = \"https://start.test/\\${x}\";\n"));
+    assert!(content.contains(&format!("    {{ id = \"cjpalhdlnbpafiamejdnhcphjbkeiagm\"; updateUrl = \"{}\"; }}\n", WEB_STORE_UPDATE_URL)));
+    assert!(content.contains("    { id = \"abcdefghijklmnopabcdefghijklmnop\"; updateUrl = \"https://updates.test/crx\"; }\n"));
+    assert!(content.contains("  commandLineArgs = [\n    \"--disable-features=Foo\"\n  ];\n"));
+    assert!(content.contains("environment.etc.\"brave-nightly/policies/managed/brave.json\".text = builtins.toJSON policies;"));
+    assert_eq!(content.matches('{').count(), content.matches('}').count());
+
+    if Command::new("nix-instantiate").arg("--version").output().is_ok() {
+        let output = Command::new("nix-instantiate")
+            .arg("--eval")
+            .arg("--strict")
+            .arg("--json")
+            .arg("--expr")
+            .arg(format!("(import {}).policies", dir.join("brave_nightly_debloat.nix").display()))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap(), plan.policies_json().unwrap());
+    }
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn linux_packages_install_the_policy_file() {
+    let dir = temp_dir("packages");
+    let plan = build_plan(&load_config("configs/minimal.json").unwrap(), &[], None).unwrap();
+    let options = PackageOptions { deb: true, rpm: true, version: "2.1+corp".to_string(), maintainer: "IT <it@example.com>".to_string() };
+    LinuxGenerator.generate_packages(&plan, &BraveVersion::Nightly, dir.to_str().unwrap(), &options, true).unwrap();
+    let policies = format!("{}\n", serde_json::to_string_pretty(&plan.policies_json().unwrap()).unwrap());
+
+    let deb = dir.join("brave-browser-nightly-debloat_2.1+corp_all.deb");
+    assert!(fs::read(&deb).unwrap().starts_with(b"!<arch>\ndebian-binary   "));
+    require_tools(&["dpkg-deb"]);
+    let output = Command::new("dpkg-deb").arg("-f").arg(&deb).arg("Package").arg("Version").arg("Conflicts").output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Package: brave-browser-nightly-debloat\nVersion: 2.1+corp\nConflicts: brave-browser-debloat\n"
+    );
+    let root = dir.join("root");
+    assert!(Command::new("dpkg-deb").arg("-x").arg(&deb).arg(&root).status().unwrap().success());
+    assert_eq!(fs::read_to_string(root.join("etc/brave-nightly/policies/managed/brave.json")).unwrap(), policies);
+    assert!(root.join("opt/brave.com/brave-nightly/initial_preferences").is_file());
+    let output = Command::new("dpkg-deb").arg("-I").arg(&deb).arg("conffiles").output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "/etc/brave-nightly/policies/managed/brave.json\n");
+
+    // The payload is stored uncompressed, so the files and header strings can be found as is
+    let rpm = fs::read(dir.join("brave-browser-nightly-debloat-2.1+corp-1.noarch.rpm")).unwrap();
+    let contains = |needle: &[u8]| rpm.windows(needle.len()).any(|window| window == needle);
+    assert!(rpm.starts_with(&[0xed, 0xab, 0xee, 0xdb, 3, 0]));
+    assert!(contains(b"./etc/brave-nightly/policies/managed/brave.json\0"));
+    assert!(contains(policies.as_bytes()));
+    assert!(contains(b"\0brave-browser-debloat\0"));
+    assert!(contains(b"\0IT <it@example.com>\0"));
+    assert!(contains(b"\0cpio\0gzip\x000\0"));
+
+    let invalid = PackageOptions { version: "2.1-1".to_string(), ..options };
+    assert!(LinuxGenerator.generate_packages(&plan, &BraveVersion::Normal, dir.to_str().unwrap(), &invalid, false).is_err());
+    let _ = fs::remove_dir_all(dir);
+}
@@ -0,0 +1,103 @@
+use std::fs;
+
+use brave_debloater::platforms::linux::LinuxGenerator;
+use brave_debloater::platforms::macos::{get_mobileconfig_identifier, MacOSGenerator};
+use brave_debloater::platforms::windows::WindowsGenerator;
+use brave_debloater::platforms::{get_initial_preferences_path, PlatformGenerator};
+use brave_debloater::preferences::PreferencesInputConfig;
+use brave_debloater::{build_plan, load_config, load_extensions, BraveVersion, Config, Platform};
+use serde_json::json;
+
+#[test]
+fn every_backend_renders_every_policy() {
+    let config = load_config("configs/privacy-focused.json").unwrap();
+    let extensions = load_extensions("extensions.json").unwrap();
+    let plan = build_plan(&config, &extensions, None).unwrap();
+
+    let generators: [(&dyn PlatformGenerator, &str, &str); 3] = [
+        (&LinuxGenerator, "linux", "brave_debloat_linux.sh"),
+        (&MacOSGenerator::default(), "macos", "brave_debloat_macos.sh"),
+        (&WindowsGenerator, "windows", "brave_debloat.bat"),
+    ];
+    for (generator, name, file) in generators {
+        let dir = std::env::temp_dir().join(format!("brave-debloater-plan-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        generator.generate_unified_script(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+        let content = fs::read_to_string(dir.join(file)).unwrap();
+        for (key, _) in plan.policies() {
+            assert!(content.contains(key), "{} script is missing policy {}", name, key);
+        }
+        for extension in &extensions {
+            assert!(content.contains(&extension.id), "{} script is missing extension {}", name, extension.id);
+        }
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+#[test]
+fn plan_exports_as_tagged_json() {
+    let config = load_config("configs/privacy-focused.json").unwrap();
+    let plan = build_plan(&config, &[], None).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+    let actions = json["actions"].as_array().unwrap();
+
+    assert_eq!(actions[0]["action"], "check_process");
+    assert!(actions.iter().any(|action| action["action"] == "write_policy" && action["key"] == "ReportAppInventory"));
+    assert!(actions
+        .iter()
+        .any(|action| action["action"] == "set_preference" && action["file"] == "local_state" && action["path"] == "browser.enabled_labs_experiments"));
+}
+
+#[test]
+fn mobileconfig_wraps_the_policies_with_stable_identifiers() {
+    let config = load_config("configs/minimal.json").unwrap();
+    let plan = build_plan(&config, &[], None).unwrap();
+    let dir = std::env::temp_dir().join(format!("brave-debloater-mobileconfig-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("brave_nightly_debloat.mobileconfig");
+
+    MacOSGenerator::default().generate_mobileconfig(&plan, &BraveVersion::Nightly, dir.to_str().unwrap(), "Team & Co").unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.contains("<key>com.brave.Browser.nightly</key>"));
+    assert!(content.contains("<string>com.apple.ManagedClient.preferences</string>"));
+    assert!(content.contains("<string>Brave Browser Nightly Debloat (Team &amp; Co)</string>"));
+    assert!(content.contains(&format!("<string>{}</string>", get_mobileconfig_identifier(&BraveVersion::Nightly, "Team & Co"))));
+    assert_eq!(get_mobileconfig_identifier(&BraveVersion::Nightly, "Team & Co"), "com.brave.Browser.nightly.debloat.Team---Co");
+    for (key, _) in plan.policies() {
+        assert!(content.contains(&format!("<key>{}</key>", key)), "missing policy {}", key);
+    }
+
+    // Regenerating gives the same UUIDs, so the new profile replaces the installed one
+    MacOSGenerator::default().generate_mobileconfig(&plan, &BraveVersion::Nightly, dir.to_str().unwrap(), "Team & Co").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    let uuids: Vec<&str> = content.split("<key>PayloadUUID</key>").skip(1).map(|rest| rest.split("<string>").nth(1).unwrap().split('<').next().unwrap()).collect();
+    assert_eq!(uuids.len(), 2);
+    assert_ne!(uuids[0], uuids[1]);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn initial_preferences_use_the_preference_set_of_new_profiles() {
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "search_engines": [],
+        "dashboard": {},
+        "experimental_features": ["brave-adblock-experimental-list-default@1"],
+        "profiles": [
+            { "name": "Work", "search_engine": { "name": "Work Search", "keyword": "work", "search_url": "https://work.test/?q={searchTerms}" } },
+            { "name": "Default" }
+        ]
+    }))
+    .unwrap();
+    let plan = build_plan(&Config::new(), &[], Some(&preferences)).unwrap();
+    let initial_preferences = plan.initial_preferences_json();
+
+    assert_eq!(initial_preferences["default_search_provider_data"]["template_url_data"]["keyword"], "brave");
+    assert_eq!(initial_preferences["brave"]["stats"]["enabled"], false);
+    // Local State values are not profile preferences
+    assert!(initial_preferences.get("browser").is_none());
+    assert_eq!(
+        get_initial_preferences_path(&Platform::Windows, &BraveVersion::Nightly),
+        "C:\\Program Files\\BraveSoftware\\Brave-Browser-Nightly\\Application\\initial_preferences"
+    );
+    assert_eq!(get_initial_preferences_path(&Platform::Linux, &BraveVersion::Normal), "/opt/brave.com/brave/initial_preferences");
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use brave_debloater::platforms::escape::powershell_quote;
+use brave_debloater::platforms::windows::{add_json_editing_powershell, WindowsGenerator};
+use brave_debloater::preferences::{
+    add_launch_flag, calculate_mac, get_default_launch_flags, get_protected_preferences, pref_hash_json, PreferencesInputConfig, MODIFIED_PREFERENCES,
+};
+use brave_debloater::{build_plan, Action, BraveVersion, Config, UserFile};
+use common::temp_dir;
+use serde_json::{json, Map, Value};
+
+const FIXTURE: &str = "tests/fixtures/Preferences";
+
+fn set_path(map: &mut Map<String, Value>, path: &str, value: Value) {
+    let (head, rest) = match path.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (path, None),
+    };
+    match rest {
+        None => {
+            map.insert(head.to_string(), value);
+        }
+        Some(rest) => {
+            let entry = map.entry(head.to_string()).or_insert_with(|| json!({}));
+            if !entry.is_object() {
+                *entry = json!({});
+            }
+            set_path(entry.as_object_mut().unwrap(), rest, value);
+        }
+    }
+}
+
+#[test]
+fn windows_scripts_patch_preferences_as_text() {
+    let dir = temp_dir("prefs-ps1");
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "dashboard": { "show_clock": true },
+        "experimental_features": ["brave-adblock-cosmetic-filtering@1"],
+        "search_engines": [{ "keyword": "o'r", "name": "O'Reilly", "search_url": "https://o.test/?q={searchTerms}" }]
+    }))
+    .unwrap();
+    let plan = build_plan(&Config::new(), &[], Some(&preferences)).unwrap();
+    WindowsGenerator.generate_powershell_scripts(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    let content = fs::read_to_string(dir.join("brave_debloat.ps1")).unwrap();
+
+    for changes in plan.profile_changes() {
+        for (path, value) in &changes.preferences {
+            let line = format!("$prefsJson = Set-JsonValue $prefsJson {} {}\n", powershell_quote(path), powershell_quote(&value.to_string()));
+            assert!(content.contains(&line), "missing {}", line);
+        }
+    }
+    assert!(!content.contains("ConvertTo-Json -Depth") && !content.contains("Set-Content"));
+
+    // Protected values go to Secure Preferences too, hashed with the machine SID rather than the user's domain SID
+    let profile_changes = plan.profile_changes();
+    let (_, template_url_data) = profile_changes[0].preferences.iter().find(|(path, _)| *path == "default_search_provider_data.template_url_data").unwrap();
+    assert!(content.contains(&format!("$protectedValues['default_search_provider_data.template_url_data'] = {}\n", powershell_quote(&template_url_data.to_string()))));
+    assert!(content.contains("Update-PreferenceMacs $prefsPath $protectedValues\n"));
+    assert!(content.contains("NTAccount($env:COMPUTERNAME)"));
+    assert!(!content.contains("GetCurrent().User.Value"));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+#[ignore = "requires pwsh"]
+fn set_json_value_keeps_a_deep_preferences_file_intact() {
+    let dir = temp_dir("prefs-fixture");
+    let original = fs::read_to_string(FIXTURE).unwrap();
+    let prefs_path = dir.join("Preferences");
+    fs::write(&prefs_path, &original).unwrap();
+
+    let changes = [
+        ("brave.new_tab_page.show_clock", json!(true)),
+        ("default_search_provider_data.template_url_data.short_name", json!("O'Reilly \"Search\" <b>")),
+        ("brave.today.sources.new", json!({ "enabled": false, "ids": [1, 2] })),
+        ("intl.selected_languages.nested", json!(1)),
+        ("web_apps.web_app_ids.mpnpojknpmmopombnjdcgaaiekajbnjb.default_app_install_source.type.metrics.seen", json!(false)),
+        ("protection.macs.homepage", json!("0123456789ABCDEF")),
+        ("brave.shields.new_setting", json!("Größe ✓")),
+    ];
+    let mut script = String::new();
+    add_json_editing_powershell(&mut script);
+    script.push_str(&format!("$path = {}\n", powershell_quote(prefs_path.to_str().unwrap())));
+    script.push_str("$json = Read-JsonText $path\n");
+    for (path, value) in &changes {
+        script.push_str(&format!("$json = Set-JsonValue $json {} {}\n", powershell_quote(path), powershell_quote(&value.to_string())));
+    }
+    script.push_str("Write-JsonFile $path $json\n");
+    let script_path = dir.join("patch.ps1");
+    fs::write(&script_path, script).unwrap();
+    let status = Command::new("pwsh").arg("-NoProfile").arg("-File").arg(&script_path).status().unwrap();
+    assert!(status.success());
+
+    let bytes = fs::read(&prefs_path).unwrap();
+    assert!(!bytes.starts_with(&[0xEF, 0xBB, 0xBF]));
+    let patched = String::from_utf8(bytes).unwrap();
+    let mut expected: Value = serde_json::from_str(&original).unwrap();
+    for (path, value) in changes {
+        set_path(expected.as_object_mut().unwrap(), path, value);
+    }
+    assert_eq!(serde_json::from_str::<Value>(&patched).unwrap(), expected);
+
+    // Values outside the edited keys keep their exact text, including the deepest and the escaped ones
+    for untouched in [
+        r#""label":"Install page as app…""#,
+        r#""lastEngagementTime":1.3371046812345678e+16,"lastShortcutLaunchTime":0.0"#,
+        r#""console-history":"[\"document.cookie\",\"JSON.parse('{\\\"a\\\":[1,{\\\"b\\\":2}]}')\"]""#,
+        r#""name":"Persönlich 👤 <work>""#,
+    ] {
+        assert!(patched.contains(untouched), "{} was rewritten", untouched);
+    }
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn pref_hash_json_drops_empty_containers_and_escapes_markup() {
+    let value = json!({ "e": 1, "d": "<x>", "b": { "c": {}, "l": [] }, "a": [] });
+    assert_eq!(pref_hash_json(&value), r#"{"d":"\u003Cx>","e":1}"#);
+    assert_eq!(pref_hash_json(&json!([{ "k": "<" }, 2])), r#"[{"k":"\u003C"},2]"#);
+    assert_eq!(pref_hash_json(&json!(false)), "false");
+}
+
+#[test]
+fn calculate_mac_matches_hmac_sha256_vectors() {
+    // HMAC-SHA256 with an empty key over device ID + path + value, as computed by Python's hmac module
+    assert_eq!(calculate_mac("", "homepage", None), "B1E9FE8108A84F532486D13AAC43C0AFDA16D3DFC9EB2F743AEE11F89F2F163E");
+    let template_url_data = json!({ "keyword": "<b>", "short_name": "B", "url": "https://b.test/?q={searchTerms}", "favicon": {} });
+    assert_eq!(
+        calculate_mac("S-1-5-21-1111111111-2222222222-3333333333", "default_search_provider_data.template_url_data", Some(&template_url_data)),
+        "89E9435D8920A2342D437F56D3D7547F8BB68EB9F95459AAE974D241EB11B251"
+    );
+    // The super MAC hashes the macs dictionary with an empty path
+    assert_eq!(calculate_mac("", "", Some(&json!({ "homepage": "ABC" }))), "491372BB9B0FE197FE5DBD184752195566F3ABD3989D071FAB33358A2C005478");
+}
+
+#[test]
+fn search_engine_is_written_whole_to_template_url_data() {
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "dashboard": {},
+        "experimental_features": [],
+        "search_engines": [{ "keyword": "ddg", "name": "DuckDuckGo", "search_url": "https://duckduckgo.com/?q={searchTerms}" }]
+    }))
+    .unwrap();
+    let plan = build_plan(&Config::new(), &[], Some(&preferences)).unwrap();
+    let search_engine = plan.actions.iter().find_map(|action| match action {
+        Action::SetPreference { file: UserFile::Preferences, path, value, .. } if path.starts_with("default_search_provider_data") => Some((path, value)),
+        _ => None,
+    });
+    let (path, value) = search_engine.unwrap();
+    assert_eq!(path, "default_search_provider_data.template_url_data");
+    assert_eq!(value, &json!({ "keyword": "ddg", "short_name": "DuckDuckGo", "url": "https://duckduckgo.com/?q={searchTerms}" }));
+    // The MAC recomputed by the scripts covers the value that is written
+    assert!(get_protected_preferences(MODIFIED_PREFERENCES).contains(&path.as_str()));
+}
+
+#[test]
+fn list_switches_are_merged_into_one_launch_flag() {
+    let preferences: PreferencesInputConfig = serde_json::from_value(json!({
+        "dashboard": {},
+        "experimental_features": [],
+        "search_engines": [],
+        "launch_flags": ["--disable-features=A", " --foo ", "--disable-features=B,A", "--enable-features=X", "--foo", "--disable-features"]
+    }))
+    .unwrap();
+    assert_eq!(get_default_launch_flags(Some(&preferences)), ["--disable-features=A,B", "--foo", "--enable-features=X", "--disable-features"]);
+
+    // Values already on the command line are kept, and later occurrences are folded into the first one
+    let mut flags: Vec<String> = ["--foo", "--disable-features=Old", "--user-data-dir=\"C:\\a b\"", "--disable-features=B"].map(String::from).to_vec();
+    add_launch_flag(&mut flags, "--disable-features=A,B");
+    assert_eq!(flags, ["--foo", "--disable-features=Old,B,A", "--user-data-dir=\"C:\\a b\""]);
+    add_launch_flag(&mut flags, "--foo");
+    assert_eq!(flags.len(), 3);
+
+    // Shortcut arguments are split outside quotes and merged the same way
+    let dir = temp_dir("prefs-shortcuts");
+    let plan = build_plan(&Config::new(), &[], Some(&preferences)).unwrap();
+    WindowsGenerator.generate_powershell_scripts(&plan, &BraveVersion::Normal, dir.to_str().unwrap()).unwrap();
+    for script in ["brave_debloat.ps1", "brave_revert.ps1"] {
+        let content = fs::read_to_string(dir.join(script)).unwrap();
+        assert!(content.contains("$arguments = @(Split-Arguments $shortcut.Arguments)\n"), "{}", script);
+        assert!(content.contains("$listSwitches = @('--enable-features', '--disable-features', '--enable-blink-features', '--disable-blink-features')\n"));
+        assert!(!content.contains("-split ' '"));
+    }
+    let _ = fs::remove_dir_all(dir);
+}
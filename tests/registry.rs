@@ -0,0 +1,163 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use brave_debloater::platforms::registry_pol::{get_registry_pol_entries, serialize_registry_pol, PolEntry};
+use brave_debloater::platforms::windows::WindowsGenerator;
+use brave_debloater::{build_plan, BraveVersion, Config, ConfigValue, Extension};
+use common::temp_dir;
+
+fn policies() -> (Config, Vec<Extension>) {
+    let mut config = Config::new();
+    config.insert("BraveRewardsDisabled".to_string(), ConfigValue::Bool(true));
+    config.insert("DefaultPopupsSetting".to_string(), ConfigValue::Number(-1));
+    config.insert("HomepageLocation".to_string(), ConfigValue::String("C:\\start \"page\"".to_string()));
+    config.insert("ProxyBypassList".to_string(), ConfigValue::String("a\nb".to_string()));
+    config.insert("ReportAppInventory".to_string(), ConfigValue::StringArray(vec![String::new()]));
+    let extensions = vec![Extension {
+        id: "cjpalhdlnbpafiamejdnhcphjbkeiagm".to_string(),
+        name: "uBlock Origin".to_string(),
+        description: String::new(),
+    }];
+    (config, extensions)
+}
+
+/// Decodes a `.reg` file, checking the UTF-16LE byte order mark and the CRLF line endings.
+fn read_reg_file(path: PathBuf) -> String {
+    let bytes = fs::read(path).unwrap();
+    assert_eq!(&bytes[..2], &[0xFF, 0xFE]);
+    let units: Vec<u16> = bytes[2..].chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    let content = String::from_utf16(&units).unwrap();
+    assert!(!content.replace("\r\n", "").contains('\n'));
+    content.replace("\r\n", "\n")
+}
+
+#[test]
+fn reg_files_match_the_policies() {
+    let dir = temp_dir("reg");
+    let (config, extensions) = policies();
+    let plan = build_plan(&config, &extensions, None).unwrap();
+    WindowsGenerator.generate_reg_files(&plan, &BraveVersion::Nightly, dir.to_str().unwrap()).unwrap();
+
+    let key = "HKEY_LOCAL_MACHINE\\SOFTWARE\\Policies\\BraveSoftware\\Brave-Nightly";
+    let content = read_reg_file(dir.join("brave_nightly_debloat.reg"));
+    assert!(content.starts_with(&format!("Windows Registry Editor Version 5.00\n\n[{}]\n", key)));
+    assert!(content.contains("\"BraveRewardsDisabled\"=dword:00000001\n"));
+    assert!(content.contains("\"DefaultPopupsSetting\"=dword:ffffffff\n"));
+    assert!(content.contains("\"HomepageLocation\"=\"C:\\\\start \\\"page\\\"\"\n"));
+    assert!(content.contains("\"ProxyBypassList\"=hex(1):61,00,0a,00,62,00,00,00\n"));
+    assert!(content.contains(&format!("[-{0}\\ExtensionInstallForcelist]\n\n[{0}\\ExtensionInstallForcelist]\n\"1\"=\"cjpalhdlnbpafiamejdnhcphjbkeiagm\"\n", key)));
+    assert!(content.contains(&format!("[{}\\ReportAppInventory]\n\"1\"=\"\"\n", key)));
+
+    let revert = read_reg_file(dir.join("brave_nightly_revert.reg"));
+    assert!(revert.contains("\"BraveRewardsDisabled\"=-\n"));
+    assert!(revert.contains(&format!("[{}\\ExtensionInstallForcelist]\n\"1\"=-\n", key)));
+    assert!(!revert.contains("dword:"));
+    let _ = fs::remove_dir_all(dir);
+}
+
+/// Parses a PReg file into (key, value name, type, data) records.
+fn parse_registry_pol(bytes: &[u8]) -> Vec<(String, String, u32, Vec<u8>)> {
+    assert_eq!(&bytes[..8], b"PReg\x01\x00\x00\x00");
+    let mut records = Vec::new();
+    let mut rest = &bytes[8..];
+    let take_u16 = |rest: &mut &[u8]| {
+        let unit = u16::from_le_bytes([rest[0], rest[1]]);
+        *rest = &rest[2..];
+        unit
+    };
+    let take_u32 = |rest: &mut &[u8]| {
+        let value = u32::from_le_bytes(rest[..4].try_into().unwrap());
+        *rest = &rest[4..];
+        value
+    };
+    let take_string = |rest: &mut &[u8]| {
+        let mut units = Vec::new();
+        loop {
+            match take_u16(rest) {
+                0 => break,
+                unit => units.push(unit),
+            }
+        }
+        String::from_utf16(&units).unwrap()
+    };
+    while !rest.is_empty() {
+        assert_eq!(take_u16(&mut rest), '[' as u16);
+        let key = take_string(&mut rest);
+        assert_eq!(take_u16(&mut rest), ';' as u16);
+        let name = take_string(&mut rest);
+        assert_eq!(take_u16(&mut rest), ';' as u16);
+        let value_type = take_u32(&mut rest);
+        assert_eq!(take_u16(&mut rest), ';' as u16);
+        let size = take_u32(&mut rest) as usize;
+        assert_eq!(take_u16(&mut rest), ';' as u16);
+        let data = rest[..size].to_vec();
+        rest = &rest[size..];
+        assert_eq!(take_u16(&mut rest), ']' as u16);
+        records.push((key, name, value_type, data));
+    }
+    records
+}
+
+fn utf16_data(value: &str) -> Vec<u8> {
+    value.encode_utf16().chain([0]).flat_map(|unit| unit.to_le_bytes()).collect()
+}
+
+#[test]
+fn registry_pol_serializes_every_value_type() {
+    let (config, extensions) = policies();
+    let plan = build_plan(&config, &extensions, None).unwrap();
+    let entries = get_registry_pol_entries(&plan, &BraveVersion::Normal);
+    let records = parse_registry_pol(&serialize_registry_pol(&entries));
+    assert_eq!(records.len(), entries.len());
+
+    let key = "SOFTWARE\\Policies\\BraveSoftware\\Brave";
+    let list_key = format!("{}\\ExtensionInstallForcelist", key);
+    assert!(records.contains(&(key.to_string(), "BraveRewardsDisabled".to_string(), 4, 1u32.to_le_bytes().to_vec())));
+    assert!(records.contains(&(key.to_string(), "DefaultPopupsSetting".to_string(), 4, u32::MAX.to_le_bytes().to_vec())));
+    assert!(records.contains(&(key.to_string(), "ProxyBypassList".to_string(), 1, utf16_data("a\nb"))));
+
+    // Lists are cleared before their numbered values are written
+    let list_start = records.iter().position(|record| record.0 == list_key).unwrap();
+    assert_eq!(records[list_start], (list_key.clone(), "**delvals.".to_string(), 1, utf16_data(" ")));
+    assert_eq!(records[list_start + 1], (list_key, "1".to_string(), 1, utf16_data("cjpalhdlnbpafiamejdnhcphjbkeiagm")));
+}
+
+#[test]
+fn registry_pol_writes_deletion_markers_and_gpo_backup() {
+    let dir = temp_dir("pol");
+    let (config, extensions) = policies();
+    let plan = build_plan(&config, &extensions, None).unwrap();
+    WindowsGenerator.generate_registry_pol(&plan, &BraveVersion::Normal, dir.to_str().unwrap(), true).unwrap();
+
+    let revert = parse_registry_pol(&fs::read(dir.join("brave_revert.pol")).unwrap());
+    assert!(revert.iter().all(|(_, name, value_type, data)| name.starts_with("**del.") && *value_type == 1 && *data == utf16_data(" ")));
+    assert!(revert.iter().any(|(key, name, _, _)| key.ends_with("\\ExtensionInstallForcelist") && name == "**del.1"));
+    assert_eq!(
+        serialize_registry_pol(&[PolEntry::DeleteValue { key: "K".to_string(), name: "V".to_string() }]),
+        [
+            b"PReg\x01\x00\x00\x00".to_vec(),
+            utf16_data("[K"),
+            utf16_data(";**del.V"),
+            b";\x00\x01\x00\x00\x00;\x00\x04\x00\x00\x00;\x00".to_vec(),
+            utf16_data(" "),
+            b"]\x00".to_vec(),
+        ]
+        .concat()
+    );
+
+    // GPMC finds the backup through manifest.xml and reads the settings from the backup folder
+    let backup_root = dir.join("brave_debloat_gpo");
+    let manifest = fs::read_to_string(backup_root.join("manifest.xml")).unwrap();
+    let backup_id = manifest.split("<ID><![CDATA[").nth(1).unwrap().split("]]>").next().unwrap();
+    let backup_dir = backup_root.join(backup_id);
+    assert!(backup_dir.join("Backup.xml").is_file());
+    assert!(backup_dir.join("bkupInfo.xml").is_file());
+    assert_eq!(fs::read(backup_dir.join("DomainSysvol/GPO/Machine/registry.pol")).unwrap(), fs::read(dir.join("brave_debloat.pol")).unwrap());
+
+    // Regenerating keeps the same backup and GPO identifiers
+    WindowsGenerator.generate_registry_pol(&plan, &BraveVersion::Normal, dir.to_str().unwrap(), true).unwrap();
+    assert_eq!(fs::read_dir(&backup_root).unwrap().count(), 2);
+    let _ = fs::remove_dir_all(dir);
+}